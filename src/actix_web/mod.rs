@@ -2,7 +2,11 @@ use std::{
     fmt::Display,
     future::{ready, Ready},
     rc::Rc,
-    sync::Arc,
+};
+#[cfg(feature = "actix-ws")]
+use {
+    crate::core::connection::{EXPIRED_CLOSE_REASON, MIN_CHECK_INTERVAL},
+    std::time::Duration,
 };
 
 use actix_web::{
@@ -12,14 +16,26 @@ use actix_web::{
     ResponseError,
 };
 use futures_util::future::LocalBoxFuture;
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
 
 use crate::core::{
+    authz::{
+        login_info_from_auth_result, Permission, RequirePermission, RequireRole, Requirement, Role,
+    },
     config::{Config, ConfigBuilder},
+    connection::{SessionToken, WxConnection},
+    handoff::{HandoffExchangeOk, HandoffExchangeRequest},
     login::{
-        self, Error as LoginError, WxLoginErr, WxLoginInfo, WxLoginOk, AUTH_FAIL_MSG,
+        self, err_resp_with, Error as LoginError, WxLoginErr, WxLoginInfo, WxLoginOk,
         LOGIN_FAIL_MSG,
     },
+    oauth::{OAuthLogin, OAuthOp, OAuthRequest},
+    open_data::{open_data_max_age, OpenData, OpenDataRequest, WxOpenData, OPEN_DATA_FAIL_MSG},
+    push::{PushEndpoint, PushQuery, PUSH_FAIL_MSG},
+    reload::ConfigHandle,
+    route::{AuthenticatedRoute, LoginRequest, PhoneNumberRequest, PublicRoute, Route},
+    scan_login::{ScanLogin, ScanLoginOp, ScanLoginRequest, ScanLoginResponse},
+    sec_check::{SecCheckContent, WxSecChecked, SEC_CHECK_FAIL_MSG},
 };
 
 type WxLoginAuthResult = Result<WxLoginInfo, LoginError>;
@@ -32,7 +48,7 @@ pub fn middleware_with_env_var() -> WxLoginMiddleware {
 /// A actix-web middleware wrapping the functionality of this crate.
 #[derive(Clone)]
 pub struct WxLoginMiddleware {
-    cfg: Arc<Config>,
+    cfg: ConfigHandle,
 }
 
 impl WxLoginMiddleware {
@@ -41,7 +57,12 @@ impl WxLoginMiddleware {
     }
 
     pub fn new(cfg: Config) -> Self {
-        Self { cfg: Arc::new(cfg) }
+        Self::with_config_handle(ConfigHandle::new(cfg))
+    }
+
+    /// Create a [WxLoginMiddleware] with a reloadable config handle.
+    pub fn with_config_handle(cfg: ConfigHandle) -> Self {
+        Self { cfg }
     }
 }
 
@@ -63,7 +84,7 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(WxLoginMiddlewareService {
             service: Rc::new(service),
-            wx_login: login::WxLogin::new(self.cfg.clone()),
+            wx_login: login::WxLogin::with_config_handle(self.cfg.clone()),
         }))
     }
 }
@@ -96,82 +117,269 @@ where
     forward_ready!(service);
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
-        #[derive(Deserialize)]
-        struct LoginRequest {
-            appid: String,
-            code: String,
-        }
-
         let myself = (*self).clone();
 
         Box::pin(async move {
-            if req.uri().path() == "/login" {
-                let LoginRequest { appid, code } = match match req.method() {
-                    &http::Method::GET => web::Query::<LoginRequest>::extract(req.request())
+            let cfg = myself.wx_login.config();
+            let route = match cfg.route(req.uri().path()) {
+                Some(Route::Public(route)) => {
+                    let resp = handle_public_route(&myself.wx_login, route, &mut req)
                         .await
-                        .map(|v| v.0)
-                        .map_err(err_resp(400, "parse-get-params-fail", req.request())),
-                    &http::Method::POST => {
-                        let (request, payload) = req.parts_mut();
-                        web::Json::<LoginRequest>::from_request(request, payload)
-                            .await
-                            .map(|v| v.0)
-                            .map_err(err_resp(400, "parse-post-json-fail", request))
-                    }
-                    meth => Err(LoginError::from(meth.to_string())).map_err(err_resp(
-                        500,
-                        "unexpected-http-method",
-                        req.request(),
-                    )),
-                } {
-                    Ok(res) => res,
-                    Err(err) => {
-                        let resp = err.respond_to(req.request()).map_into_right_body();
-                        return Ok(ServiceResponse::new(req.into_parts().0, resp));
-                    }
-                };
-                myself
-                    .wx_login
-                    .handle_login(appid, code)
-                    .await
-                    .map(|v| v.respond_to(req.request()))
-                    .or_else(|v| Ok(v.respond_to(req.request())))
-                    .map(|v| ServiceResponse::new(req.into_parts().0, v.map_into_right_body()))
-            } else {
-                let header_stoken = req
-                    .headers()
-                    .get("WX-LOGIN-STOKEN")
-                    .ok_or(LoginError::from("no WX-LOGIN-STOKEN header"));
-                let stoken = header_stoken.and_then(|header_stoken| {
-                    header_stoken
-                        .to_str()
-                        .map_err(|e| LoginError::from(e.to_string()))
-                });
-                let auth_info: WxLoginAuthResult = stoken.and_then(|stoken| {
-                    let header_sig = req
-                        .headers()
-                        .get("WX-LOGIN-SIG")
-                        .ok_or(LoginError::from("no WX-LOGIN-SIG header"));
-                    let sig = header_sig.and_then(|header_sig| {
-                        header_sig
-                            .to_str()
-                            .map_err(|e| LoginError::from(e.to_string()))
-                    });
-                    myself
-                        .wx_login
-                        .authenticate(stoken, &req.uri().to_string(), sig)
-                });
-                req.extensions_mut().insert(auth_info);
-                myself
-                    .service
-                    .call(req)
+                        .unwrap_or_else(|resp| resp);
+                    return Ok(ServiceResponse::new(
+                        req.into_parts().0,
+                        resp.map_into_right_body(),
+                    ));
+                }
+                Some(Route::Authenticated(route)) => Some(route),
+                None => None,
+            };
+            let (stoken, auth_info) = authenticate_request(&myself.wx_login, &req).await;
+            if let Some(route) = route {
+                let resp = handle_authenticated_route(&myself.wx_login, route, &mut req, auth_info)
                     .await
-                    .map(|v| v.map_into_left_body())
+                    .unwrap_or_else(|resp| resp);
+                return Ok(ServiceResponse::new(
+                    req.into_parts().0,
+                    resp.map_into_right_body(),
+                ));
+            }
+            if let Some(stoken) = stoken.filter(|_| auth_info.is_ok()) {
+                req.extensions_mut().insert(SessionToken(stoken));
             }
+            req.extensions_mut().insert(auth_info);
+            req.extensions_mut().insert(myself.wx_login.clone());
+            myself
+                .service
+                .call(req)
+                .await
+                .map(|v| v.map_into_left_body())
         })
     }
 }
 
+type RouteResult = Result<HttpResponse<BoxBody>, HttpResponse<BoxBody>>;
+
+/// Authenticate the request by its credentials or session cookie.
+async fn authenticate_request(
+    wx_login: &login::WxLogin,
+    req: &ServiceRequest,
+) -> (Option<String>, WxLoginAuthResult) {
+    let cookies = req
+        .headers()
+        .get_all(http::header::COOKIE)
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    let safe_method = matches!(
+        *req.method(),
+        http::Method::GET | http::Method::HEAD | http::Method::OPTIONS
+    );
+    wx_login
+        .authenticate_request(
+            &req.uri().to_string(),
+            |name| req.headers().get(name)?.to_str().ok(),
+            &cookies,
+            safe_method,
+        )
+        .await
+}
+
+/// Handle the endpoints called without a login session.
+async fn handle_public_route(
+    wx_login: &login::WxLogin,
+    route: PublicRoute<'_>,
+    req: &mut ServiceRequest,
+) -> RouteResult {
+    match route {
+        PublicRoute::Push(endpoint) => handle_push(wx_login, endpoint, req).await,
+        PublicRoute::Login => {
+            let LoginRequest { appid, code } = parse_params(req).await?;
+            wx_login
+                .handle_login(appid, code)
+                .await
+                .map(|v| {
+                    let set_cookies = wx_login.login_cookies(&v.stoken);
+                    with_set_cookies(v.respond_to(req.request()), set_cookies)
+                })
+                .map_err(|v| v.respond_to(req.request()))
+        }
+        PublicRoute::HandoffExchange => {
+            let params = parse_params::<HandoffExchangeRequest>(req).await?;
+            let exchange = wx_login
+                .exchange_handoff_ticket(&params)
+                .await
+                .map_err(|v| v.respond_to(req.request()))?;
+            let resp = match exchange.redirect {
+                Some(redirect) => HttpResponse::Found()
+                    .insert_header((http::header::LOCATION, redirect))
+                    .finish(),
+                None => HttpResponse::Ok().json(HandoffExchangeOk {
+                    openid: exchange.openid,
+                }),
+            };
+            Ok(with_set_cookies(resp, exchange.set_cookies))
+        }
+        PublicRoute::OAuth(oauth, op) => handle_oauth(wx_login, oauth, op, req).await,
+        PublicRoute::ScanLogin(scan, op) => {
+            handle_scan_login(wx_login, scan, op, req, login_info_from_auth_result(None)).await
+        }
+    }
+}
+
+/// Handle the endpoints called by the logged-in user.
+async fn handle_authenticated_route(
+    wx_login: &login::WxLogin,
+    route: AuthenticatedRoute<'_>,
+    req: &mut ServiceRequest,
+    auth_info: WxLoginAuthResult,
+) -> RouteResult {
+    let login_info = || login_info_from_auth_result(Some(&auth_info));
+    match route {
+        AuthenticatedRoute::HandoffMint(handoff) => {
+            let login_info = login_info().map_err(|v| v.respond_to(req.request()))?;
+            wx_login
+                .mint_handoff_ticket(handoff, &login_info)
+                .await
+                .map(|v| web::Json(v).respond_to(req.request()).map_into_boxed_body())
+                .map_err(|v| v.respond_to(req.request()))
+        }
+        AuthenticatedRoute::PhoneNumber => {
+            let login_info = login_info().map_err(|v| v.respond_to(req.request()))?;
+            let PhoneNumberRequest { code } = parse_params(req).await?;
+            wx_login
+                .get_phone_number(&login_info, &code)
+                .await
+                .map(|v| web::Json(v).respond_to(req.request()).map_into_boxed_body())
+                .map_err(|v| v.respond_to(req.request()))
+        }
+        AuthenticatedRoute::CheckSession => {
+            let login_info = login_info().map_err(|v| v.respond_to(req.request()))?;
+            wx_login
+                .handle_check_session(&login_info)
+                .await
+                .map(|v| web::Json(v).respond_to(req.request()).map_into_boxed_body())
+                .map_err(|v| v.respond_to(req.request()))
+        }
+        AuthenticatedRoute::ScanConfirm(scan) => {
+            handle_scan_login(wx_login, scan, ScanLoginOp::Confirm, req, login_info()).await
+        }
+    }
+}
+
+async fn handle_push(
+    wx_login: &login::WxLogin,
+    endpoint: &PushEndpoint,
+    req: &mut ServiceRequest,
+) -> RouteResult {
+    let push_err = |e: &dyn Display, req: &HttpRequest| {
+        err_resp_with(400, "push-parse-fail", PUSH_FAIL_MSG)(e).respond_to(req)
+    };
+    let query = web::Query::<PushQuery>::extract(req.request())
+        .await
+        .map_err(|e| push_err(&e, req.request()))?;
+    let body = match req.method() {
+        &http::Method::GET => None,
+        _ => {
+            let (request, payload) = req.parts_mut();
+            Some(
+                web::Bytes::from_request(request, payload)
+                    .await
+                    .map_err(|e| push_err(&e, request))?,
+            )
+        }
+    };
+    wx_login
+        .handle_push(endpoint, &query, body.as_deref())
+        .await
+        .map(|v| v.respond_to(req.request()).map_into_boxed_body())
+        .map_err(|v| v.respond_to(req.request()))
+}
+
+async fn handle_oauth(
+    wx_login: &login::WxLogin,
+    oauth: &OAuthLogin,
+    op: OAuthOp,
+    req: &mut ServiceRequest,
+) -> RouteResult {
+    let params = parse_params::<OAuthRequest>(req).await?;
+    let cookies = req
+        .headers()
+        .get_all(http::header::COOKIE)
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    let oauth_resp = wx_login
+        .handle_oauth(oauth, op, &params, &cookies)
+        .await
+        .map_err(|v| v.respond_to(req.request()))?;
+    let resp = match oauth_resp.redirect {
+        Some(redirect) => HttpResponse::Found()
+            .insert_header((http::header::LOCATION, redirect))
+            .finish(),
+        None => HttpResponse::Ok().json(oauth_resp.login),
+    };
+    Ok(with_set_cookies(resp, oauth_resp.set_cookies))
+}
+
+async fn handle_scan_login(
+    wx_login: &login::WxLogin,
+    scan: &ScanLogin,
+    op: ScanLoginOp,
+    req: &mut ServiceRequest,
+    login_info: Result<WxLoginInfo, WxLoginErr>,
+) -> RouteResult {
+    let params = match req.method() {
+        &http::Method::POST if req.headers().get(http::header::CONTENT_TYPE).is_none() => {
+            Default::default()
+        }
+        _ => parse_params::<ScanLoginRequest>(req).await?,
+    };
+    match wx_login
+        .handle_scan_login(scan, op, params, login_info)
+        .await
+        .map_err(|err| err.respond_to(req.request()))?
+    {
+        ScanLoginResponse::Ticket(v) => {
+            Ok(web::Json(v).respond_to(req.request()).map_into_boxed_body())
+        }
+        ScanLoginResponse::QrCode(v) => {
+            Ok(HttpResponse::Ok().content_type(v.content_type).body(v.data))
+        }
+        ScanLoginResponse::State(v) => {
+            Ok(web::Json(v).respond_to(req.request()).map_into_boxed_body())
+        }
+    }
+}
+
+/// Parse the params of endpoints from GET query or POST JSON body.
+async fn parse_params<T: DeserializeOwned + 'static>(
+    req: &mut ServiceRequest,
+) -> Result<T, HttpResponse<BoxBody>> {
+    match req.method() {
+        &http::Method::GET => web::Query::<T>::extract(req.request())
+            .await
+            .map(|v| v.0)
+            .map_err(err_resp(400, "parse-get-params-fail", req.request())),
+        &http::Method::POST => {
+            let (request, payload) = req.parts_mut();
+            web::Json::<T>::from_request(request, payload)
+                .await
+                .map(|v| v.0)
+                .map_err(err_resp(400, "parse-post-json-fail", request))
+        }
+        meth => Err(err_resp(500, "unexpected-http-method", req.request())(meth)),
+    }
+}
+
+/// Append `Set-Cookie` headers to the response.
+fn with_set_cookies<B>(mut resp: HttpResponse<B>, set_cookies: Vec<String>) -> HttpResponse<B> {
+    for cookie in set_cookies {
+        if let Ok(value) = http::header::HeaderValue::from_str(&cookie) {
+            resp.headers_mut().append(http::header::SET_COOKIE, value);
+        }
+    }
+    resp
+}
+
 fn err_resp<'a, E: Display>(
     status: u16,
     code: &'a str,
@@ -190,35 +398,222 @@ fn err_resp<'a, E: Display>(
 
 impl FromRequest for WxLoginInfo {
     type Error = Error;
-    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Future = Ready<Result<Self, Self::Error>>;
     fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())
+                .map_err(|err| wrap_err(err, req)),
+        )
+    }
+}
+
+impl<R: Role> FromRequest for RequireRole<R> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())
+                .and_then(RequireRole::check)
+                .map_err(|err| wrap_err(err, req)),
+        )
+    }
+}
+
+impl<P: Permission> FromRequest for RequirePermission<P> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())
+                .and_then(RequirePermission::check)
+                .map_err(|err| wrap_err(err, req)),
+        )
+    }
+}
+
+impl<T: OpenData + 'static> FromRequest for WxOpenData<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let login_info = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>());
+        let max_age = open_data_max_age(req.extensions().get::<login::WxLogin>());
+        let body = web::Json::<OpenDataRequest>::from_request(req, payload);
         let req = req.clone();
         Box::pin(async move {
-            match req.extensions().get::<WxLoginAuthResult>() {
-                Some(Ok(login_info)) => Ok(login_info.clone()),
-                Some(Err(err)) => Err(WrappedWxLoginErr {
-                    err: WxLoginErr {
-                        status: 401,
-                        code: "auth-login-session-fail".into(),
-                        message: AUTH_FAIL_MSG.into(),
-                        detail: err.to_string(),
-                    },
-                    req: req.clone(),
+            let login_info = login_info.map_err(|err| wrap_err(err, &req))?;
+            let web::Json(body) = body.await.map_err(|e| {
+                wrap_err(
+                    err_resp_with(400, "open-data-parse-fail", OPEN_DATA_FAIL_MSG)(e),
+                    &req,
+                )
+            })?;
+            WxOpenData::from_request(&login_info, &body, max_age).map_err(|err| wrap_err(err, &req))
+        })
+    }
+}
+
+impl<T: SecCheckContent + 'static> FromRequest for WxSecChecked<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let login_info = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>());
+        let wx_login = req.extensions().get::<login::WxLogin>().cloned();
+        let body = web::Json::<T>::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let login_info = login_info.map_err(|err| wrap_err(err, &req))?;
+            let web::Json(body) = body.await.map_err(|e| {
+                wrap_err(
+                    err_resp_with(400, "sec-check-parse-fail", SEC_CHECK_FAIL_MSG)(e),
+                    &req,
+                )
+            })?;
+            WxSecChecked::from_request(wx_login.as_ref(), &login_info, body)
+                .await
+                .map_err(|err| wrap_err(err, &req))
+        })
+    }
+}
+
+impl FromRequest for WxConnection {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let extensions = req.extensions();
+        ready(
+            WxConnection::from_request(
+                extensions.get::<login::WxLogin>(),
+                extensions.get::<WxLoginAuthResult>(),
+                extensions.get::<SessionToken>(),
+            )
+            .map_err(|err| wrap_err(err, req)),
+        )
+    }
+}
+
+/// Upgrade the request of an authenticated connection to a WebSocket by actix-ws,
+/// returning the response of the handshake, the session and the message stream like [actix_ws::handle].
+///
+/// If `close_on_expiry` is set, the session is checked every interval, and the socket is closed
+/// with the policy violation code when the session expires. Pings are sent at the interval as well
+/// to detect closed sockets.
+#[cfg(feature = "actix-ws")]
+pub fn ws_upgrade(
+    req: &HttpRequest,
+    body: web::Payload,
+    connection: &WxConnection,
+    close_on_expiry: Option<Duration>,
+) -> Result<(HttpResponse, actix_ws::Session, actix_ws::MessageStream), Error> {
+    let (resp, session, stream) = actix_ws::handle(req, body)?;
+    if let Some(check_interval) = close_on_expiry {
+        let check_interval = check_interval.max(MIN_CHECK_INTERVAL);
+        let connection = connection.clone();
+        let mut ping_session = session.clone();
+        let close_session = session.clone();
+        actix_web::rt::spawn(async move {
+            let closed = async {
+                loop {
+                    actix_web::rt::time::sleep(check_interval).await;
+                    if ping_session.ping(b"").await.is_err() {
+                        break;
+                    }
                 }
-                .into()),
-                None => Err(WrappedWxLoginErr {
-                    err: WxLoginErr {
-                        status: 500,
-                        code: "login-session-lost".into(),
-                        message: AUTH_FAIL_MSG.into(),
-                        detail: "".into(),
-                    },
-                    req: req.clone(),
+            };
+            tokio::select! {
+                _ = closed => {}
+                _ = connection.expired(check_interval) => {
+                    let reason = actix_ws::CloseReason {
+                        code: actix_ws::CloseCode::Policy,
+                        description: Some(EXPIRED_CLOSE_REASON.into()),
+                    };
+                    let _ = close_session.close(Some(reason)).await;
                 }
-                .into()),
             }
-        })
+        });
+    }
+    Ok((resp, session, stream))
+}
+
+/// Create a [RequireMiddleware] which only allows login sessions having the role.
+///
+/// Wrap it on a resource or scope under a [WxLoginMiddleware].
+pub fn require_role(role: &str) -> RequireMiddleware {
+    RequireMiddleware {
+        requirement: Requirement::Role(role.into()),
+    }
+}
+
+/// Create a [RequireMiddleware] which only allows login sessions having the permission.
+///
+/// Wrap it on a resource or scope under a [WxLoginMiddleware].
+pub fn require_permission(permission: &str) -> RequireMiddleware {
+    RequireMiddleware {
+        requirement: Requirement::Permission(permission.into()),
+    }
+}
+
+/// A actix-web middleware checking the claims of login sessions for resources.
+#[derive(Clone)]
+pub struct RequireMiddleware {
+    requirement: Requirement,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireMiddlewareService {
+            service: Rc::new(service),
+            requirement: self.requirement.clone(),
+        }))
+    }
+}
+
+/// A actix-web middleware service created by [RequireMiddleware].
+pub struct RequireMiddlewareService<S> {
+    service: Rc<S>,
+    requirement: Requirement,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let checked = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())
+            .and_then(|login_info| self.requirement.check(&login_info));
+        if let Err(err) = checked {
+            let resp = err.respond_to(req.request()).map_into_right_body();
+            return Box::pin(ready(Ok(ServiceResponse::new(req.into_parts().0, resp))));
+        }
+        let service = self.service.clone();
+        Box::pin(async move { service.call(req).await.map(|v| v.map_into_left_body()) })
+    }
+}
+
+fn wrap_err(err: WxLoginErr, req: &HttpRequest) -> Error {
+    WrappedWxLoginErr {
+        err,
+        req: req.clone(),
     }
+    .into()
 }
 
 impl Responder for WxLoginOk {
@@ -1,27 +1,50 @@
 use axum::{
     async_trait,
     extract::{FromRequest, FromRequestParts, Query, Request},
-    http::{request::Parts, Method, StatusCode},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode, Uri},
     response::{IntoResponse, Response},
     Json,
 };
 use futures_util::{future::BoxFuture, TryFutureExt};
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use std::{
     convert::Infallible,
     fmt::Display,
-    sync::Arc,
     task::{Context, Poll},
 };
 use tower::{Layer, Service};
+#[cfg(feature = "axum-ws")]
+use {
+    crate::core::connection::EXPIRED_CLOSE_REASON,
+    axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    futures_util::{
+        stream::{SplitSink, SplitStream},
+        SinkExt, Stream, StreamExt,
+    },
+    std::{future::Future, pin::Pin, sync::Arc, time::Duration},
+};
 
 use crate::core::{
+    authz::{
+        login_info_from_auth_result, Permission, RequirePermission, RequireRole, Requirement, Role,
+    },
     config::{Config, ConfigBuilder},
-    login::{Error, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk, AUTH_FAIL_MSG, LOGIN_FAIL_MSG},
+    connection::{SessionToken, WxConnection},
+    handoff::{HandoffExchangeOk, HandoffExchangeRequest},
+    login::{err_resp_with, Error, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk, LOGIN_FAIL_MSG},
+    oauth::{OAuthLogin, OAuthOp, OAuthRequest},
+    open_data::{open_data_max_age, OpenData, OpenDataRequest, WxOpenData, OPEN_DATA_FAIL_MSG},
+    push::{PushEndpoint, PushQuery, PUSH_FAIL_MSG},
+    reload::ConfigHandle,
+    route::{AuthenticatedRoute, LoginRequest, PhoneNumberRequest, PublicRoute, Route},
+    scan_login::{ScanLogin, ScanLoginOp, ScanLoginResponse},
+    sec_check::{SecCheckContent, WxSecChecked, SEC_CHECK_FAIL_MSG},
 };
 
 type WxLoginAuthResult = Result<WxLoginInfo, Error>;
 
+const PUSH_BODY_LIMIT: usize = 1 << 20;
+
 /// Create a [WxLoginLayer] with config derived from environment variables and default values.
 pub fn layer_with_env_var() -> WxLoginLayer {
     WxLoginLayer::new_with_env_var()
@@ -30,7 +53,7 @@ pub fn layer_with_env_var() -> WxLoginLayer {
 /// An axum layer (middleware) wrapping the functionality of this crate.
 #[derive(Clone)]
 pub struct WxLoginLayer {
-    cfg: Arc<Config>,
+    cfg: ConfigHandle,
 }
 
 impl WxLoginLayer {
@@ -41,7 +64,12 @@ impl WxLoginLayer {
 
     /// Create a [WxLoginLayer] with specified config.
     pub fn new(cfg: Config) -> Self {
-        Self { cfg: Arc::new(cfg) }
+        Self::with_config_handle(ConfigHandle::new(cfg))
+    }
+
+    /// Create a [WxLoginLayer] with a reloadable config handle.
+    pub fn with_config_handle(cfg: ConfigHandle) -> Self {
+        Self { cfg }
     }
 }
 
@@ -51,7 +79,7 @@ impl<S> Layer<S> for WxLoginLayer {
     fn layer(&self, inner: S) -> Self::Service {
         WxLoginService {
             inner,
-            wx_login: WxLogin::new(self.cfg.clone()),
+            wx_login: WxLogin::with_config_handle(self.cfg.clone()),
         }
     }
 }
@@ -78,73 +106,239 @@ where
     }
 
     fn call(&mut self, mut req: Request) -> Self::Future {
-        #[derive(Deserialize)]
-        struct LoginRequest {
-            appid: String,
-            code: String,
-        }
-
         let mut myself = self.clone();
 
         Box::pin(
             async move {
-                if req.uri().path() == "/login" {
-                    let LoginRequest { appid, code } = match req.method() {
-                        &Method::GET => {
-                            Query::<LoginRequest>::try_from_uri(req.uri())
-                                .map_err(err_resp(400, "parse-get-params-fail"))?
-                                .0
-                        }
-                        &Method::POST => {
-                            Json::<LoginRequest>::from_request(req, &())
-                                .await
-                                .map_err(err_resp(400, "parse-post-json-fail"))?
-                                .0
-                        }
-                        meth => Err(Error::from(meth.to_string()))
-                            .map_err(err_resp(500, "unexpected-http-method"))?,
-                    };
-                    myself
-                        .wx_login
-                        .handle_login(appid, code)
-                        .await
-                        .map(|v| v.into_response())
-                        .map_err(|v| v.into_response())
-                } else {
-                    let header_stoken = req
-                        .headers()
-                        .get("WX-LOGIN-STOKEN")
-                        .ok_or(Error::from("no WX-LOGIN-STOKEN header"));
-                    let stoken = header_stoken.and_then(|header_stoken| {
-                        header_stoken
-                            .to_str()
-                            .map_err(|e| Error::from(e.to_string()))
-                    });
-                    let auth_info: WxLoginAuthResult = stoken.and_then(|stoken| {
-                        let header_sig = req
-                            .headers()
-                            .get("WX-LOGIN-SIG")
-                            .ok_or(Error::from("no WX-LOGIN-SIG header"));
-                        let sig = header_sig.and_then(|header_sig| {
-                            header_sig
-                                .to_str()
-                                .map_err(|e| Error::from(e.to_string()))
-                        });
-                        myself.wx_login.authenticate(stoken, &req.uri().to_string(), sig)
-                    });
-                    req.extensions_mut().insert(auth_info);
-                    myself
-                        .inner
-                        .call(req)
-                        .await
-                        .map_err(err_resp(500, "inner-service-fail"))
+                let cfg = myself.wx_login.config();
+                let route = match cfg.route(req.uri().path()) {
+                    Some(Route::Public(route)) => {
+                        return handle_public_route(&myself.wx_login, route, req).await
+                    }
+                    Some(Route::Authenticated(route)) => Some(route),
+                    None => None,
+                };
+                let (stoken, auth_info) =
+                    authenticate_request(&myself.wx_login, req.method(), req.uri(), req.headers())
+                        .await;
+                if let Some(route) = route {
+                    return handle_authenticated_route(&myself.wx_login, route, req, auth_info)
+                        .await;
                 }
+                if let Some(stoken) = stoken.filter(|_| auth_info.is_ok()) {
+                    req.extensions_mut().insert(SessionToken(stoken));
+                }
+                req.extensions_mut().insert(auth_info);
+                req.extensions_mut().insert(myself.wx_login.clone());
+                myself
+                    .inner
+                    .call(req)
+                    .await
+                    .map_err(err_resp(500, "inner-service-fail"))
             }
             .or_else(|error_resp| async move { Ok(error_resp) }),
         )
     }
 }
 
+/// Authenticate the request by its credentials or session cookie.
+async fn authenticate_request(
+    wx_login: &WxLogin,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> (Option<String>, WxLoginAuthResult) {
+    let cookies = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    let safe_method = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    wx_login
+        .authenticate_request(
+            &uri.to_string(),
+            |name| headers.get(name)?.to_str().ok(),
+            &cookies,
+            safe_method,
+        )
+        .await
+}
+
+/// Handle the endpoints called without a login session.
+async fn handle_public_route(
+    wx_login: &WxLogin,
+    route: PublicRoute<'_>,
+    req: Request,
+) -> Result<Response, Response> {
+    match route {
+        PublicRoute::Push(endpoint) => handle_push(wx_login, endpoint, req).await,
+        PublicRoute::Login => {
+            let LoginRequest { appid, code } = parse_params(req).await?;
+            wx_login
+                .handle_login(appid, code)
+                .await
+                .map(|v| {
+                    let set_cookies = wx_login.login_cookies(&v.stoken);
+                    with_set_cookies(v.into_response(), set_cookies)
+                })
+                .map_err(|v| v.into_response())
+        }
+        PublicRoute::HandoffExchange => {
+            let params: HandoffExchangeRequest = parse_params(req).await?;
+            let exchange = wx_login
+                .exchange_handoff_ticket(&params)
+                .await
+                .map_err(|v| v.into_response())?;
+            let resp = match exchange.redirect {
+                Some(redirect) => {
+                    (StatusCode::FOUND, [(header::LOCATION, redirect)]).into_response()
+                }
+                None => Json(HandoffExchangeOk {
+                    openid: exchange.openid,
+                })
+                .into_response(),
+            };
+            Ok(with_set_cookies(resp, exchange.set_cookies))
+        }
+        PublicRoute::OAuth(oauth, op) => handle_oauth(wx_login, oauth, op, req).await,
+        PublicRoute::ScanLogin(scan, op) => {
+            handle_scan_login(wx_login, scan, op, req, login_info_from_auth_result(None)).await
+        }
+    }
+}
+
+/// Handle the endpoints called by the logged-in user.
+async fn handle_authenticated_route(
+    wx_login: &WxLogin,
+    route: AuthenticatedRoute<'_>,
+    req: Request,
+    auth_info: WxLoginAuthResult,
+) -> Result<Response, Response> {
+    let login_info = || login_info_from_auth_result(Some(&auth_info));
+    match route {
+        AuthenticatedRoute::HandoffMint(handoff) => wx_login
+            .mint_handoff_ticket(handoff, &login_info().map_err(|v| v.into_response())?)
+            .await
+            .map(|v| Json(v).into_response())
+            .map_err(|v| v.into_response()),
+        AuthenticatedRoute::PhoneNumber => {
+            let login_info = login_info().map_err(|v| v.into_response())?;
+            let PhoneNumberRequest { code } = parse_params(req).await?;
+            wx_login
+                .get_phone_number(&login_info, &code)
+                .await
+                .map(|v| Json(v).into_response())
+                .map_err(|v| v.into_response())
+        }
+        AuthenticatedRoute::CheckSession => wx_login
+            .handle_check_session(&login_info().map_err(|v| v.into_response())?)
+            .await
+            .map(|v| Json(v).into_response())
+            .map_err(|v| v.into_response()),
+        AuthenticatedRoute::ScanConfirm(scan) => {
+            handle_scan_login(wx_login, scan, ScanLoginOp::Confirm, req, login_info()).await
+        }
+    }
+}
+
+async fn handle_push(
+    wx_login: &WxLogin,
+    endpoint: &PushEndpoint,
+    req: Request,
+) -> Result<Response, Response> {
+    let push_err =
+        |e: &dyn Display| err_resp_with(400, "push-parse-fail", PUSH_FAIL_MSG)(e).into_response();
+    let query = Query::<PushQuery>::try_from_uri(req.uri())
+        .map_err(|e| push_err(&e))?
+        .0;
+    let body = match req.method() {
+        &Method::GET => None,
+        _ => Some(
+            axum::body::to_bytes(req.into_body(), PUSH_BODY_LIMIT)
+                .await
+                .map_err(|e| push_err(&e))?,
+        ),
+    };
+    wx_login
+        .handle_push(endpoint, &query, body.as_deref())
+        .await
+        .map(|v| v.into_response())
+        .map_err(|v| v.into_response())
+}
+
+async fn handle_oauth(
+    wx_login: &WxLogin,
+    oauth: &OAuthLogin,
+    op: OAuthOp,
+    req: Request,
+) -> Result<Response, Response> {
+    let cookies = req
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| Some(v.to_str().ok()?.to_string()))
+        .collect::<Vec<_>>();
+    let params: OAuthRequest = parse_params(req).await?;
+    let cookies = cookies.iter().map(String::as_str).collect::<Vec<_>>();
+    let oauth_resp = wx_login
+        .handle_oauth(oauth, op, &params, &cookies)
+        .await
+        .map_err(|v| v.into_response())?;
+    let resp = match oauth_resp.redirect {
+        Some(redirect) => (StatusCode::FOUND, [(header::LOCATION, redirect)]).into_response(),
+        None => Json(oauth_resp.login).into_response(),
+    };
+    Ok(with_set_cookies(resp, oauth_resp.set_cookies))
+}
+
+async fn handle_scan_login(
+    wx_login: &WxLogin,
+    scan: &ScanLogin,
+    op: ScanLoginOp,
+    req: Request,
+    login_info: Result<WxLoginInfo, WxLoginErr>,
+) -> Result<Response, Response> {
+    let params = match req.method() {
+        &Method::POST if req.headers().get(header::CONTENT_TYPE).is_none() => Default::default(),
+        _ => parse_params(req).await?,
+    };
+    wx_login
+        .handle_scan_login(scan, op, params, login_info)
+        .await
+        .map(|v| match v {
+            ScanLoginResponse::Ticket(v) => Json(v).into_response(),
+            ScanLoginResponse::QrCode(v) => {
+                ([(header::CONTENT_TYPE, v.content_type)], v.data).into_response()
+            }
+            ScanLoginResponse::State(v) => Json(v).into_response(),
+        })
+        .map_err(|v| v.into_response())
+}
+
+/// Append `Set-Cookie` headers to the response.
+fn with_set_cookies(mut resp: Response, set_cookies: Vec<String>) -> Response {
+    for cookie in set_cookies {
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            resp.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    resp
+}
+
+/// Parse the params of endpoints from GET query or POST JSON body.
+async fn parse_params<T: DeserializeOwned>(req: Request) -> Result<T, Response> {
+    match req.method() {
+        &Method::GET => Ok(Query::<T>::try_from_uri(req.uri())
+            .map_err(err_resp(400, "parse-get-params-fail"))?
+            .0),
+        &Method::POST => Ok(Json::<T>::from_request(req, &())
+            .await
+            .map_err(err_resp(400, "parse-post-json-fail"))?
+            .0),
+        meth => Err(err_resp(500, "unexpected-http-method")(meth)),
+    }
+}
+
 fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> Response {
     move |e| {
         WxLoginErr {
@@ -168,24 +362,285 @@ where
     type Rejection = WxLoginInfoRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        match parts.extensions.get::<WxLoginAuthResult>() {
-            Some(Ok(login_info)) => Ok(login_info.clone()),
-            Some(Err(err)) => Err(WxLoginErr {
-                status: 401,
-                code: "auth-login-session-fail".into(),
-                message: AUTH_FAIL_MSG.into(),
-                detail: err.to_string(),
-            }),
-            None => Err(WxLoginErr {
-                status: 500,
-                code: "login-session-lost".into(),
-                message: AUTH_FAIL_MSG.into(),
-                detail: "".into(),
-            }),
+        login_info_from_auth_result(parts.extensions.get::<WxLoginAuthResult>())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for WxConnection
+where
+    S: Send + Sync,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        WxConnection::from_request(
+            parts.extensions.get::<WxLogin>(),
+            parts.extensions.get::<WxLoginAuthResult>(),
+            parts.extensions.get::<SessionToken>(),
+        )
+    }
+}
+
+/// An extractor of the WebSocket upgrade of an authenticated connection,
+/// used instead of [WebSocketUpgrade] in handlers.
+#[cfg(feature = "axum-ws")]
+pub struct WxWebSocketUpgrade {
+    upgrade: WebSocketUpgrade,
+    connection: WxConnection,
+    close_on_expiry: Option<Duration>,
+}
+
+#[cfg(feature = "axum-ws")]
+impl WxWebSocketUpgrade {
+    /// Get the login session of the connection.
+    pub fn connection(&self) -> &WxConnection {
+        &self.connection
+    }
+    /// Configure the inner [WebSocketUpgrade], e.g. protocols and message sizes.
+    pub fn map_upgrade(mut self, f: impl FnOnce(WebSocketUpgrade) -> WebSocketUpgrade) -> Self {
+        self.upgrade = f(self.upgrade);
+        self
+    }
+    /// Close the socket with code 1008 (policy violation) when the session expires,
+    /// checked every interval, and drop the callback.
+    pub fn close_on_expiry(mut self, check_interval: Duration) -> Self {
+        self.close_on_expiry = Some(check_interval);
+        self
+    }
+    /// Finalize upgrading the connection and call the callback with the socket and the login info,
+    /// like [WebSocketUpgrade::on_upgrade].
+    pub fn on_upgrade<C, Fut>(self, callback: C) -> Response
+    where
+        C: FnOnce(WxWebSocket, WxLoginInfo) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let Self {
+            upgrade,
+            connection,
+            close_on_expiry,
+        } = self;
+        upgrade.on_upgrade(move |socket| async move {
+            let (sink, stream) = socket.split();
+            let sink = Arc::new(tokio::sync::Mutex::new(sink));
+            let socket = WxWebSocket {
+                stream,
+                sink: sink.clone(),
+            };
+            let handler = callback(socket, connection.login_info.clone());
+            match close_on_expiry {
+                Some(check_interval) => {
+                    if connection
+                        .run_until_expired(check_interval, handler)
+                        .await
+                        .is_err()
+                    {
+                        let frame = CloseFrame {
+                            code: close_code::POLICY,
+                            reason: EXPIRED_CLOSE_REASON.into(),
+                        };
+                        let _ = sink.lock().await.send(Message::Close(Some(frame))).await;
+                    }
+                }
+                None => handler.await,
+            }
+        })
+    }
+}
+
+/// The WebSocket of an authenticated connection, passed to the callback of
+/// [WxWebSocketUpgrade::on_upgrade].
+///
+/// The sending half is shared with the upgrade, so that the socket can still be closed with
+/// a close frame after the callback is dropped on session expiry.
+#[cfg(feature = "axum-ws")]
+pub struct WxWebSocket {
+    stream: SplitStream<WebSocket>,
+    sink: Arc<tokio::sync::Mutex<SplitSink<WebSocket, Message>>>,
+}
+
+#[cfg(feature = "axum-ws")]
+impl WxWebSocket {
+    /// Receive the next message, `None` if the socket is closed, like [WebSocket::recv].
+    pub async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        self.stream.next().await
+    }
+    /// Send a message, like [WebSocket::send].
+    pub async fn send(&self, msg: Message) -> Result<(), axum::Error> {
+        self.sink.lock().await.send(msg).await
+    }
+}
+
+#[cfg(feature = "axum-ws")]
+impl Stream for WxWebSocket {
+    type Item = Result<Message, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(feature = "axum-ws")]
+#[async_trait]
+impl<S> FromRequestParts<S> for WxWebSocketUpgrade
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let connection = WxConnection::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let upgrade = WebSocketUpgrade::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self {
+            upgrade,
+            connection,
+            close_on_expiry: None,
+        })
+    }
+}
+
+#[async_trait]
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    R: Role,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        RequireRole::check(WxLoginInfo::from_request_parts(parts, state).await?)
+    }
+}
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for RequirePermission<P>
+where
+    S: Send + Sync,
+    P: Permission,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        RequirePermission::check(WxLoginInfo::from_request_parts(parts, state).await?)
+    }
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for WxOpenData<T>
+where
+    S: Send + Sync,
+    T: OpenData,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let login_info = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())?;
+        let max_age = open_data_max_age(req.extensions().get::<WxLogin>());
+        let Json(body) = Json::<OpenDataRequest>::from_request(req, state)
+            .await
+            .map_err(|e| {
+                err_resp_with(400, "open-data-parse-fail", OPEN_DATA_FAIL_MSG)(e.body_text())
+            })?;
+        WxOpenData::from_request(&login_info, &body, max_age)
+    }
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for WxSecChecked<T>
+where
+    S: Send + Sync,
+    T: SecCheckContent + Send,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let login_info = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())?;
+        let wx_login = req.extensions().get::<WxLogin>().cloned();
+        let Json(body) = Json::<T>::from_request(req, state).await.map_err(|e| {
+            err_resp_with(400, "sec-check-parse-fail", SEC_CHECK_FAIL_MSG)(e.body_text())
+        })?;
+        WxSecChecked::from_request(wx_login.as_ref(), &login_info, body).await
+    }
+}
+
+/// Create a [RequireLayer] which only allows login sessions having the role.
+///
+/// Use it with `Router::route_layer` under a [WxLoginLayer].
+pub fn require_role(role: &str) -> RequireLayer {
+    RequireLayer {
+        requirement: Requirement::Role(role.into()),
+    }
+}
+
+/// Create a [RequireLayer] which only allows login sessions having the permission.
+///
+/// Use it with `Router::route_layer` under a [WxLoginLayer].
+pub fn require_permission(permission: &str) -> RequireLayer {
+    RequireLayer {
+        requirement: Requirement::Permission(permission.into()),
+    }
+}
+
+/// An axum layer checking the claims of login sessions for routes.
+#[derive(Clone)]
+pub struct RequireLayer {
+    requirement: Requirement,
+}
+
+impl<S> Layer<S> for RequireLayer {
+    type Service = RequireService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireService {
+            inner,
+            requirement: self.requirement.clone(),
         }
     }
 }
 
+/// An axum service created by [RequireLayer].
+#[derive(Clone)]
+pub struct RequireService<S> {
+    inner: S,
+    requirement: Requirement,
+}
+
+impl<S> Service<Request> for RequireService<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static + Clone,
+    S::Future: Send + 'static,
+    S::Error: Display,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map(|r| r.or(Ok(())))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut myself = self.clone();
+
+        Box::pin(async move {
+            let checked = login_info_from_auth_result(req.extensions().get::<WxLoginAuthResult>())
+                .and_then(|login_info| myself.requirement.check(&login_info));
+            match checked {
+                Ok(()) => Ok(myself
+                    .inner
+                    .call(req)
+                    .await
+                    .unwrap_or_else(err_resp(500, "inner-service-fail"))),
+                Err(err) => Ok(err.into_response()),
+            }
+        })
+    }
+}
+
 impl IntoResponse for WxLoginOk {
     fn into_response(self) -> Response {
         (StatusCode::OK, Json(self)).into_response()
@@ -0,0 +1,410 @@
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    api::{get_json, post_json, ApiError, WX_API_BASE_URL},
+    config::AppInfo,
+    login::new_http_client,
+    security::random_bytes,
+    token_cache::{AccessTokenCache, MemoryTokenCache},
+    wecom::WECOM_API_BASE_URL,
+};
+use tiny_crypto::encoding::{Encoder, HEX};
+
+/// The WeChat server API used to fetch access_token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessTokenApi {
+    /// `cgi-bin/token`, which invalidates the previous token when a new one is fetched.
+    #[default]
+    Token,
+    /// `cgi-bin/stable_token`, which returns the same token until it expires.
+    StableToken,
+    /// `cgi-bin/gettoken` of WeCom, fetching the corp access_token by the corpid and corpsecret,
+    /// always used by apps of WeCom.
+    WeCom,
+    /// `cgi-bin/component/api_component_token` of third-party platforms, fetching the
+    /// component_access_token by the component_appid, component_appsecret and the latest
+    /// component_verify_ticket, always used by components.
+    Component,
+}
+
+/// An access_token of a WeChat mini-program.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    pub token: String,
+    pub expires_at: SystemTime,
+}
+impl AccessToken {
+    fn expires_within(&self, dur: Duration) -> bool {
+        self.expires_at <= SystemTime::now() + dur
+    }
+}
+impl std::fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"***")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// The manager of access_token of one app.
+///
+/// It fetches the token on demand, caches it and refreshes it `refresh_ahead`
+/// (default 300 seconds) before expiry. Concurrent refreshes are deduplicated, and while
+/// one refresh is in flight other callers keep using the cached token if it is still valid.
+///
+/// Managers sharing the same [AccessTokenCache] (e.g. replicas using a file or Redis backed
+/// cache) share one token, and only one of them refreshes it at a time.
+pub struct AccessTokenManager {
+    app_info: AppInfo,
+    api: AccessTokenApi,
+    refresh_ahead: Duration,
+    lock_ttl: Duration,
+    http_client: reqwest::Client,
+    cache: Arc<dyn AccessTokenCache>,
+    holder: String,
+    current: RwLock<Option<AccessToken>>,
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+impl std::fmt::Debug for AccessTokenManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessTokenManager")
+            .field("appid", &self.app_info.appid)
+            .field("api", &self.api)
+            .field("refresh_ahead", &self.refresh_ahead)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl AccessTokenManager {
+    /// Create an AccessTokenManager of the app.
+    pub fn new(app_info: AppInfo) -> Self {
+        Self {
+            app_info,
+            api: Default::default(),
+            refresh_ahead: Duration::from_secs(300),
+            lock_ttl: Duration::from_secs(10),
+            http_client: new_http_client(10),
+            cache: Arc::new(MemoryTokenCache::new()),
+            holder: HEX.to_text(&random_bytes::<8>()),
+            current: Default::default(),
+            refresh_lock: Default::default(),
+        }
+    }
+    /// Set the API to fetch access_token.
+    pub fn with_api(mut self, api: AccessTokenApi) -> Self {
+        self.api = api;
+        self
+    }
+    /// Set how long before expiry the token is refreshed.
+    pub fn with_refresh_ahead(mut self, dur: Duration) -> Self {
+        self.refresh_ahead = dur;
+        self
+    }
+    /// Set the cache shared with other managers of the same app.
+    ///
+    /// By default each manager has its own in-memory cache.
+    pub fn with_cache(mut self, cache: Arc<dyn AccessTokenCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+    /// Set the max period of holding the refresh lock of the cache, the default is 10 seconds.
+    pub fn with_lock_ttl(mut self, ttl: Duration) -> Self {
+        self.lock_ttl = ttl;
+        self
+    }
+    /// Set the HTTP client to call WeChat server APIs, the default one has a timeout of 10 seconds.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = client;
+        self
+    }
+    /// Get the appid of the managed token.
+    pub fn appid(&self) -> &str {
+        &self.app_info.appid
+    }
+    pub(crate) fn app_info(&self) -> &AppInfo {
+        &self.app_info
+    }
+    pub(crate) fn api(&self) -> AccessTokenApi {
+        self.api
+    }
+    pub(crate) fn cache(&self) -> &Arc<dyn AccessTokenCache> {
+        &self.cache
+    }
+
+    /// Get a valid access_token, fetching a new one if needed.
+    pub async fn get(&self) -> Result<AccessToken, ApiError> {
+        let cached = self.current.read().unwrap().clone();
+        if let Some(token) = &cached {
+            if !token.expires_within(self.refresh_ahead) {
+                return Ok(token.clone());
+            }
+        }
+        let _guard = match self.refresh_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                if let Some(token) = cached.filter(|t| !t.expires_within(Duration::ZERO)) {
+                    return Ok(token);
+                }
+                self.refresh_lock.lock().await
+            }
+        };
+        if let Some(token) = self.current.read().unwrap().clone() {
+            if !token.expires_within(self.refresh_ahead) {
+                return Ok(token);
+            }
+        }
+        self.refresh(false).await
+    }
+
+    /// Get the token string of a valid access_token.
+    pub async fn token(&self) -> Result<String, ApiError> {
+        self.get().await.map(|t| t.token)
+    }
+
+    /// Fetch a new access_token regardless of the cached one.
+    pub async fn force_refresh(&self) -> Result<AccessToken, ApiError> {
+        let _guard = self.refresh_lock.lock().await;
+        self.refresh(true).await
+    }
+
+    /// Drop the cached token if it is the given one, e.g. after an API returns 40001/42001.
+    pub async fn invalidate(&self, token: &str) {
+        {
+            let mut current = self.current.write().unwrap();
+            if current.as_ref().is_some_and(|t| t.token == token) {
+                *current = None;
+            }
+        }
+        if let Err(e) = self.cache.remove(self.appid(), token).await {
+            tracing::error!(
+                appid = self.appid(),
+                "remove access_token from cache fail: {e}"
+            );
+        }
+        tracing::info!(appid = self.appid(), "access_token invalidated");
+    }
+
+    /// Call an API with a valid access_token, and retry once with a new token if the API
+    /// reports the token is invalid or expired.
+    pub async fn call<T, F, Fut>(&self, f: F) -> Result<T, ApiError>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let token = self.token().await?;
+        match f(token.clone()).await {
+            Err(err) if err.is_access_token_invalid() => {
+                self.invalidate(&token).await;
+                f(self.token().await?).await
+            }
+            res => res,
+        }
+    }
+
+    /// Get a fresh token from the shared cache, or fetch one while holding the cache lock.
+    async fn refresh(&self, force: bool) -> Result<AccessToken, ApiError> {
+        let appid = self.appid();
+        let mut check_cache = !force;
+        loop {
+            let cached = self.cache.get(appid).await.map_err(cache_err)?;
+            if let Some(token) = cached.clone().filter(|_| check_cache) {
+                if !token.expires_within(self.refresh_ahead) {
+                    *self.current.write().unwrap() = Some(token.clone());
+                    return Ok(token);
+                }
+            }
+            if self
+                .cache
+                .try_lock(appid, &self.holder, self.lock_ttl)
+                .await
+                .map_err(cache_err)?
+            {
+                let res = self.fetch_with_lock(check_cache, force).await;
+                if let Err(e) = self.cache.unlock(appid, &self.holder).await {
+                    tracing::error!(appid, "unlock access_token cache fail: {e}");
+                }
+                return res;
+            }
+            // another manager is refreshing, use the old token if it is still valid
+            if let Some(token) = cached.filter(|t| !t.expires_within(Duration::ZERO)) {
+                if !force {
+                    return Ok(token);
+                }
+            }
+            check_cache = true;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn fetch_with_lock(
+        &self,
+        check_cache: bool,
+        force: bool,
+    ) -> Result<AccessToken, ApiError> {
+        let appid = self.appid();
+        if check_cache {
+            // the token may be refreshed by others just before the lock is acquired
+            if let Some(token) = self.cache.get(appid).await.map_err(cache_err)? {
+                if !token.expires_within(self.refresh_ahead) {
+                    *self.current.write().unwrap() = Some(token.clone());
+                    return Ok(token);
+                }
+            }
+        }
+        let token = self.fetch(force).await?;
+        self.cache.put(appid, &token).await.map_err(cache_err)?;
+        Ok(token)
+    }
+
+    async fn fetch(&self, force_refresh: bool) -> Result<AccessToken, ApiError> {
+        let requested_at = SystemTime::now();
+        let res: proto::AccessTokenResponse = match self.api {
+            AccessTokenApi::Token => {
+                get_json(
+                    &self.http_client,
+                    &format!("{WX_API_BASE_URL}/cgi-bin/token"),
+                    &proto::TokenRequest {
+                        grant_type: "client_credential",
+                        appid: &self.app_info.appid,
+                        secret: &self.app_info.secret.0,
+                    },
+                )
+                .await?
+            }
+            AccessTokenApi::StableToken => {
+                post_json(
+                    &self.http_client,
+                    &format!("{WX_API_BASE_URL}/cgi-bin/stable_token"),
+                    &(),
+                    &proto::StableTokenRequest {
+                        grant_type: "client_credential",
+                        appid: &self.app_info.appid,
+                        secret: &self.app_info.secret.0,
+                        force_refresh,
+                    },
+                )
+                .await?
+            }
+            AccessTokenApi::WeCom => {
+                let corpid =
+                    self.app_info.corpid.as_deref().ok_or_else(|| {
+                        ApiError::Http(format!("no corpid of app {}", self.appid()))
+                    })?;
+                get_json(
+                    &self.http_client,
+                    &format!("{WECOM_API_BASE_URL}/cgi-bin/gettoken"),
+                    &[("corpid", corpid), ("corpsecret", &self.app_info.secret.0)],
+                )
+                .await?
+            }
+            AccessTokenApi::Component => {
+                let ticket = self
+                    .cache
+                    .get_ticket(self.appid())
+                    .await
+                    .map_err(cache_err)?
+                    .ok_or_else(|| {
+                        ApiError::Cache(format!(
+                            "no component_verify_ticket of component {}",
+                            self.appid()
+                        ))
+                    })?;
+                let res: proto::ComponentTokenResponse = post_json(
+                    &self.http_client,
+                    &format!("{WX_API_BASE_URL}/cgi-bin/component/api_component_token"),
+                    &(),
+                    &proto::ComponentTokenRequest {
+                        component_appid: &self.app_info.appid,
+                        component_appsecret: &self.app_info.secret.0,
+                        component_verify_ticket: &ticket,
+                    },
+                )
+                .await?;
+                proto::AccessTokenResponse {
+                    access_token: res.component_access_token,
+                    expires_in: res.expires_in,
+                }
+            }
+        };
+        let token = AccessToken {
+            token: res.access_token,
+            expires_at: requested_at + Duration::from_secs(res.expires_in),
+        };
+        tracing::info!(appid = self.appid(), ?token, "access_token fetched");
+        *self.current.write().unwrap() = Some(token.clone());
+        Ok(token)
+    }
+}
+
+fn cache_err(e: crate::core::login::Error) -> ApiError {
+    ApiError::Cache(e.to_string())
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub(crate) struct TokenRequest<'a> {
+        pub(crate) grant_type: &'a str,
+        pub(crate) appid: &'a str,
+        pub(crate) secret: &'a str,
+    }
+
+    #[derive(Serialize)]
+    pub(crate) struct StableTokenRequest<'a> {
+        pub(crate) grant_type: &'a str,
+        pub(crate) appid: &'a str,
+        pub(crate) secret: &'a str,
+        pub(crate) force_refresh: bool,
+    }
+
+    #[derive(Serialize)]
+    pub(crate) struct ComponentTokenRequest<'a> {
+        pub(crate) component_appid: &'a str,
+        pub(crate) component_appsecret: &'a str,
+        pub(crate) component_verify_ticket: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct AccessTokenResponse {
+        pub(crate) access_token: String,
+        pub(crate) expires_in: u64,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct ComponentTokenResponse {
+        pub(crate) component_access_token: String,
+        pub(crate) expires_in: u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_and_invalidate() {
+        tokio_test::block_on(async {
+            let manager = AccessTokenManager::new(AppInfo::from("appid".into(), "secret".into()));
+            let token = AccessToken {
+                token: "some_token".into(),
+                expires_at: SystemTime::now() + Duration::from_secs(7200),
+            };
+            *manager.current.write().unwrap() = Some(token.clone());
+            assert_eq!(manager.get().await.unwrap(), token);
+            manager.invalidate("other_token").await;
+            assert_eq!(manager.token().await.unwrap(), "some_token");
+            manager.invalidate("some_token").await;
+            assert!(manager.current.read().unwrap().is_none());
+        })
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::core::login::Error;
+
+pub(crate) const WX_API_BASE_URL: &str = "https://api.weixin.qq.com";
+
+/// The errcode of an invalid access_token.
+pub const ERRCODE_INVALID_ACCESS_TOKEN: i64 = 40001;
+/// The errcode of an expired access_token.
+pub const ERRCODE_ACCESS_TOKEN_EXPIRED: i64 = 42001;
+/// The errcode of WeCom APIs (also some WeChat APIs) meaning the access_token is invalid.
+pub const ERRCODE_WECOM_INVALID_ACCESS_TOKEN: i64 = 40014;
+
+/// The error of calling WeChat server APIs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// The API is not reachable or returns an unexpected response.
+    Http(String),
+    /// The API returns a non-zero errcode.
+    WeChat { errcode: i64, errmsg: String },
+    /// The access_token cache fails.
+    Cache(String),
+}
+impl ApiError {
+    /// Get the errcode returned by WeChat server, if any.
+    pub fn errcode(&self) -> Option<i64> {
+        match self {
+            ApiError::WeChat { errcode, .. } => Some(*errcode),
+            ApiError::Http(_) | ApiError::Cache(_) => None,
+        }
+    }
+    /// Check if the error is caused by an invalid or expired access_token.
+    pub fn is_access_token_invalid(&self) -> bool {
+        matches!(
+            self.errcode(),
+            Some(
+                ERRCODE_INVALID_ACCESS_TOKEN
+                    | ERRCODE_ACCESS_TOKEN_EXPIRED
+                    | ERRCODE_WECOM_INVALID_ACCESS_TOKEN
+            )
+        )
+    }
+}
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Http(err) => write!(f, "wx api http error: {err}"),
+            ApiError::WeChat { errcode, errmsg } => {
+                write!(f, "wx api error: errcode={errcode}, errmsg={errmsg}")
+            }
+            ApiError::Cache(err) => write!(f, "access_token cache error: {err}"),
+        }
+    }
+}
+impl std::error::Error for ApiError {}
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiError::Http(err.to_string())
+    }
+}
+impl From<ApiError> for Error {
+    fn from(err: ApiError) -> Self {
+        Error::from(err.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    errcode: i64,
+    #[serde(default)]
+    errmsg: String,
+}
+
+/// Parse the JSON body of WeChat server APIs, which carries a non-zero errcode on failure.
+pub(crate) fn parse_response<T: DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    let err: ErrorResponse =
+        serde_json::from_slice(body).map_err(|e| ApiError::Http(e.to_string()))?;
+    if err.errcode != 0 {
+        return Err(ApiError::WeChat {
+            errcode: err.errcode,
+            errmsg: err.errmsg,
+        });
+    }
+    serde_json::from_slice(body).map_err(|e| ApiError::Http(e.to_string()))
+}
+
+/// Call a GET API with query params.
+pub(crate) async fn get_json<Q: Serialize + ?Sized, T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    query: &Q,
+) -> Result<T, ApiError> {
+    let body = client
+        .get(url)
+        .query(query)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    parse_response(&body)
+}
+
+/// Call a POST API with query params and JSON body.
+pub(crate) async fn post_json<Q: Serialize + ?Sized, B: Serialize + ?Sized, T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    query: &Q,
+    body: &B,
+) -> Result<T, ApiError> {
+    let body = client
+        .post(url)
+        .query(query)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    parse_response(&body)
+}
+
+/// Call a POST API with query params and JSON body, and return the content type and the
+/// raw body of the response, for APIs returning binary content on success.
+pub(crate) async fn post_bytes<Q: Serialize + ?Sized, B: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    url: &str,
+    query: &Q,
+    body: &B,
+) -> Result<(String, Vec<u8>), ApiError> {
+    let res = client
+        .post(url)
+        .query(query)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Ok((content_type, res.bytes().await?.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct TokenResponse {
+        access_token: String,
+    }
+
+    #[test]
+    fn parse_api_response() {
+        let res: TokenResponse =
+            parse_response(br#"{"access_token":"ACCESS_TOKEN","expires_in":7200}"#).unwrap();
+        assert_eq!(res.access_token, "ACCESS_TOKEN");
+        let res: TokenResponse =
+            parse_response(br#"{"errcode":0,"errmsg":"ok","access_token":"T"}"#).unwrap();
+        assert_eq!(res.access_token, "T");
+        let err = parse_response::<TokenResponse>(br#"{"errcode":40001,"errmsg":"invalid"}"#)
+            .unwrap_err();
+        assert!(err.is_access_token_invalid());
+        assert!(matches!(
+            parse_response::<TokenResponse>(b"not json"),
+            Err(ApiError::Http(_))
+        ));
+    }
+}
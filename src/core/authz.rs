@@ -0,0 +1,188 @@
+use std::{collections::BTreeSet, fmt::Debug, marker::PhantomData};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::login::AUTH_FAIL_MSG;
+use crate::core::login::{Error, WxLoginErr, WxLoginInfo};
+
+pub(crate) const AUTHZ_FAIL_MSG: &str = "没有访问权限";
+
+/// The roles and permissions granted to a login session.
+///
+/// Claims are resolved by the [ClaimsResolver] on login and sealed into the session token,
+/// so that they can be checked on every request without any extra lookup.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Claims {
+    pub roles: BTreeSet<String>,
+    pub permissions: BTreeSet<String>,
+}
+impl Claims {
+    /// Create empty claims.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Add a role.
+    pub fn with_role(mut self, role: &str) -> Self {
+        self.roles.insert(role.into());
+        self
+    }
+    /// Add a permission.
+    pub fn with_permission(mut self, permission: &str) -> Self {
+        self.permissions.insert(permission.into());
+        self
+    }
+    /// Check if the role is granted.
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+    /// Check if the permission is granted.
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission)
+    }
+}
+
+/// Resolve the [Claims] of a user on login.
+///
+/// Set it with [ConfigBuilder::with_claims_resolver](crate::wx_login::ConfigBuilder::with_claims_resolver).
+/// An error returned by the resolver fails the login.
+/// Members of corps logging in mini-programs of WeCom are passed as `wecom:<corpid>:<userid>` in place of openid.
+#[async_trait]
+pub trait ClaimsResolver: Send + Sync + Debug {
+    async fn resolve(&self, appid: &str, openid: &str) -> Result<Claims, Error>;
+}
+
+/// A role which can be required by the [RequireRole] extractor.
+///
+/// ```
+/// use wx_login_middleware::preclude::*;
+///
+/// struct Admin;
+/// impl wx_login::Role for Admin {
+///     const NAME: &'static str = "admin";
+/// }
+/// ```
+pub trait Role {
+    const NAME: &'static str;
+}
+
+/// A permission which can be required by the [RequirePermission] extractor.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+/// An access requirement on the claims of a login session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Role(String),
+    Permission(String),
+}
+impl Requirement {
+    /// Check the requirement against the login session, a 403 error is returned if not satisfied.
+    pub fn check(&self, login_info: &WxLoginInfo) -> Result<(), WxLoginErr> {
+        let (ok, code, name) = match self {
+            Requirement::Role(role) => (login_info.claims.has_role(role), "auth-role-denied", role),
+            Requirement::Permission(perm) => (
+                login_info.claims.has_permission(perm),
+                "auth-permission-denied",
+                perm,
+            ),
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(WxLoginErr {
+                status: 403,
+                code: code.into(),
+                message: AUTHZ_FAIL_MSG.into(),
+                detail: format!("{name} is required"),
+            })
+        }
+    }
+}
+
+/// An extractor that requires the login session having role `R`.
+pub struct RequireRole<R: Role>(pub WxLoginInfo, PhantomData<R>);
+impl<R: Role> RequireRole<R> {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn check(login_info: WxLoginInfo) -> Result<Self, WxLoginErr> {
+        Requirement::Role(R::NAME.into()).check(&login_info)?;
+        Ok(Self(login_info, PhantomData))
+    }
+}
+impl<R: Role> std::ops::Deref for RequireRole<R> {
+    type Target = WxLoginInfo;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// An extractor that requires the login session having permission `P`.
+pub struct RequirePermission<P: Permission>(pub WxLoginInfo, PhantomData<P>);
+impl<P: Permission> RequirePermission<P> {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn check(login_info: WxLoginInfo) -> Result<Self, WxLoginErr> {
+        Requirement::Permission(P::NAME.into()).check(&login_info)?;
+        Ok(Self(login_info, PhantomData))
+    }
+}
+impl<P: Permission> std::ops::Deref for RequirePermission<P> {
+    type Target = WxLoginInfo;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Convert the authentication result left by the middleware to [WxLoginInfo] or an error response.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn login_info_from_auth_result(
+    auth_result: Option<&Result<WxLoginInfo, Error>>,
+) -> Result<WxLoginInfo, WxLoginErr> {
+    match auth_result {
+        Some(Ok(login_info)) => Ok(login_info.clone()),
+        Some(Err(err)) => Err(WxLoginErr {
+            status: 401,
+            code: "auth-login-session-fail".into(),
+            message: AUTH_FAIL_MSG.into(),
+            detail: err.to_string(),
+        }),
+        None => Err(WxLoginErr {
+            status: 500,
+            code: "login-session-lost".into(),
+            message: AUTH_FAIL_MSG.into(),
+            detail: "".into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::login::tests::{test_app_info, test_login, test_wx_login};
+
+    #[test]
+    fn check_requirement() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let wx_login = test_wx_login(&app_info, |b| b);
+            let login_info = |claims| async {
+                test_login(&wx_login, &app_info, "some_openid", claims)
+                    .await
+                    .1
+            };
+            let info = login_info(Claims::new().with_role("admin").with_permission("read")).await;
+            assert!(Requirement::Role("admin".into()).check(&info).is_ok());
+            assert!(Requirement::Permission("read".into()).check(&info).is_ok());
+            let err = Requirement::Permission("write".into())
+                .check(&info)
+                .unwrap_err();
+            assert_eq!(err.status, 403);
+            assert_eq!(err.code, "auth-permission-denied");
+            let err = Requirement::Role("admin".into())
+                .check(&login_info(Claims::new()).await)
+                .unwrap_err();
+            assert_eq!(err.code, "auth-role-denied");
+        })
+    }
+}
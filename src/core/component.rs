@@ -0,0 +1,346 @@
+use std::{
+    borrow::Cow,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::core::{
+    access_token::{AccessTokenApi, AccessTokenManager},
+    api::{get_json, WX_API_BASE_URL},
+    config::AppInfo,
+    login::{decode_session_key, Error, WxLogin, WxLoginErr, WxLoginOk, LOGIN_ERR},
+    push::PushEndpoint,
+    security::secret_utils::SecretString,
+};
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    login::{err_with, ErrWith},
+    push::{PushQuery, PUSH_FAIL_MSG},
+    security::constant_time_eq,
+};
+
+/// The component_verify_ticket is pushed every 10 minutes and is valid for 12 hours.
+const VERIFY_TICKET_TTL: Duration = Duration::from_secs(12 * 3600);
+
+/// A third-party platform (component) logging in the mini-programs authorized to it.
+///
+/// Logins of apps created by [AppInfo::component] go through `sns/component/jscode2session` with the
+/// component_access_token, which is fetched by the component_appid, component_appsecret and the
+/// latest component_verify_ticket, so that no secret of the apps is needed.
+///
+/// The component_verify_ticket is pushed by WeChat server to the authorization event URL of the
+/// platform, which can be served by [with_ticket_endpoint](Self::with_ticket_endpoint), or received
+/// elsewhere and saved by [WxLogin::set_component_verify_ticket].
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub(crate) appid: String,
+    pub(crate) secret: SecretString,
+    pub(crate) ticket_endpoint: Option<PushEndpoint>,
+}
+
+impl Component {
+    /// Create a Component from (component_appid, component_appsecret).
+    pub fn new(component_appid: &str, component_secret: &str) -> Self {
+        Self {
+            appid: component_appid.into(),
+            secret: SecretString(component_secret.into()),
+            ticket_endpoint: None,
+        }
+    }
+    /// Serve the authorization event URL of the platform at the path, with the token and EncodingAESKey
+    /// of the message settings of the platform, saving the pushed component_verify_ticket.
+    ///
+    /// Requests to the path are verified by the token and do not require login sessions.
+    pub fn with_ticket_endpoint(mut self, path: &str, token: &str, encoding_aes_key: &str) -> Self {
+        let mut endpoint =
+            PushEndpoint::new(path, &self.appid, token).with_encoding_aes_key(encoding_aes_key);
+        endpoint.component = true;
+        self.ticket_endpoint = Some(endpoint);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.appid.is_empty() || self.secret.0.is_empty() {
+            Err(format!(
+                "empty appid or secret of component {:?}",
+                self.appid
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the text of the element `<name>` of a flat XML message, with CDATA unwrapped.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn xml_field<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let value = xml
+        .split_once(&format!("<{name}>"))?
+        .1
+        .split_once(&format!("</{name}>"))?
+        .0;
+    Some(
+        value
+            .strip_prefix("<![CDATA[")
+            .and_then(|v| v.strip_suffix("]]>"))
+            .unwrap_or(value),
+    )
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const PUSH_ERR: ErrWith = err_with(PUSH_FAIL_MSG);
+
+impl WxLogin {
+    /// Save the component_verify_ticket of the component, e.g. received by another service.
+    ///
+    /// It is kept by the access_token cache, so that replicas sharing the cache share the ticket.
+    pub async fn set_component_verify_ticket(
+        &self,
+        component_appid: &str,
+        ticket: &str,
+    ) -> Result<(), Error> {
+        self.config()
+            .access_token_cache
+            .put_ticket(
+                component_appid,
+                ticket,
+                SystemTime::now() + VERIFY_TICKET_TTL,
+            )
+            .await?;
+        tracing::info!(component_appid, "component_verify_ticket saved");
+        Ok(())
+    }
+
+    /// Get the shared manager of the component_access_token of the component.
+    ///
+    /// The manager is created on first use and recreated if the component secret
+    /// or the access_token cache changes.
+    pub async fn component_access_token_manager(
+        &self,
+        component_appid: &str,
+    ) -> Result<Arc<AccessTokenManager>, Error> {
+        let cfg = self.config();
+        let component = cfg
+            .component(component_appid)
+            .ok_or_else(|| format!("component {component_appid} not found"))?;
+        let app_info = AppInfo::from(component.appid.clone(), component.secret.0.clone());
+        Ok(self.shared_token_manager(app_info, AccessTokenApi::Component))
+    }
+
+    /// Get the AppInfo whose secret derives keys of stokens, which is the component secret
+    /// for apps authorized to a component.
+    pub(crate) fn token_key_app_info<'a>(
+        &self,
+        app_info: &'a AppInfo,
+    ) -> Result<Cow<'a, AppInfo>, Error> {
+        let Some(component_appid) = &app_info.component_appid else {
+            return Ok(Cow::Borrowed(app_info));
+        };
+        let cfg = self.config();
+        let component = cfg
+            .component(component_appid)
+            .ok_or_else(|| format!("component {component_appid} not found"))?;
+        Ok(Cow::Owned(AppInfo {
+            secret: component.secret.clone(),
+            ..app_info.clone()
+        }))
+    }
+
+    /// Handle login of the mini-program authorized to a component, exchanging the code
+    /// by `sns/component/jscode2session` with the component_access_token.
+    pub(crate) async fn handle_component_login(
+        &self,
+        app_info: &AppInfo,
+        code: &str,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        let component_appid = app_info
+            .component_appid
+            .as_deref()
+            .ok_or_else(|| LOGIN_ERR.err(500, "component-appid-missing", &app_info.appid))?;
+        let manager = self
+            .component_access_token_manager(component_appid)
+            .await
+            .map_err(|e| LOGIN_ERR.err(500, "component-not-found", e))?;
+        let res: proto::Code2SessionResponse = manager
+            .call(|token| async move {
+                get_json(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/sns/component/jscode2session"),
+                    &[
+                        ("appid", app_info.appid.as_str()),
+                        ("js_code", code),
+                        ("grant_type", "authorization_code"),
+                        ("component_appid", component_appid),
+                        ("component_access_token", token.as_str()),
+                    ],
+                )
+                .await
+            })
+            .await
+            .map_err(|e| LOGIN_ERR.err(401, "component-jscode2session-fail", e))?;
+        tracing::info!(
+            appid = app_info.appid,
+            component_appid,
+            openid = res.openid,
+            "component jscode2session ok"
+        );
+        let session_key = decode_session_key(&res.session_key)
+            .map_err(|e| LOGIN_ERR.err(500, "session-key-invalid-base64", e))?;
+        let claims = self
+            .resolve_claims(&app_info.appid, &res.openid)
+            .await
+            .map_err(|e| LOGIN_ERR.err(500, "resolve-claims-fail", e))?;
+        self.issue_session(app_info, res.openid, res.unionid, session_key, claims)
+            .await
+    }
+
+    /// Handle an authorization event pushed to the ticket endpoint of a component in encrypted XML,
+    /// saving the component_verify_ticket.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) async fn handle_component_push(
+        &self,
+        endpoint: &PushEndpoint,
+        query: &PushQuery,
+        body: &[u8],
+    ) -> Result<String, WxLoginErr> {
+        let body =
+            std::str::from_utf8(body).map_err(|e| PUSH_ERR.err(400, "push-parse-fail", e))?;
+        let encrypt = xml_field(body, "Encrypt")
+            .ok_or_else(|| PUSH_ERR.err(400, "push-decrypt-fail", "message is not encrypted"))?;
+        let msg_signature =
+            endpoint.sign(&mut [&endpoint.token.0, &query.timestamp, &query.nonce, encrypt]);
+        if !query
+            .msg_signature
+            .as_ref()
+            .is_some_and(|sig| constant_time_eq(sig.as_bytes(), msg_signature.as_bytes()))
+        {
+            return Err(PUSH_ERR.err(401, "push-sig-fail", "bad msg_signature"));
+        }
+        let key = endpoint
+            .aes_key()
+            .ok()
+            .flatten()
+            .ok_or_else(|| PUSH_ERR.err(500, "push-config-fail", "no valid EncodingAESKey"))?;
+        let msg = endpoint
+            .decrypt(&key, encrypt)
+            .map_err(|e| PUSH_ERR.err(400, "push-decrypt-fail", e))?;
+        let info_type = xml_field(&msg, "InfoType")
+            .ok_or_else(|| PUSH_ERR.err(400, "push-parse-fail", "no InfoType"))?;
+        tracing::info!(
+            component_appid = endpoint.appid,
+            info_type,
+            authorizer_appid = xml_field(&msg, "AuthorizerAppid"),
+            "component event received"
+        );
+        if info_type == "component_verify_ticket" {
+            let ticket = xml_field(&msg, "ComponentVerifyTicket")
+                .filter(|t| !t.is_empty())
+                .ok_or_else(|| PUSH_ERR.err(400, "push-parse-fail", "no ComponentVerifyTicket"))?;
+            self.set_component_verify_ticket(&endpoint.appid, ticket)
+                .await
+                .map_err(|e| PUSH_ERR.err(500, "component-ticket-save-fail", e))?;
+        }
+        Ok("success".into())
+    }
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    pub(crate) struct Code2SessionResponse {
+        pub(crate) openid: String,
+        pub(crate) session_key: String,
+        pub(crate) unionid: Option<String>,
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use tiny_crypto::encoding::{Encoder, BASE64};
+
+    use crate::core::{
+        authz::Claims,
+        config::ConfigBuilder,
+        login::tests::{test_login_with, test_wx_login},
+        push::tests::{encrypt, now_timestamp},
+    };
+
+    #[test]
+    fn verify_ticket_and_sessions() {
+        let key = [5u8; 32];
+        let encoding_aes_key = BASE64.to_text(&key).trim_end_matches('=').to_string();
+        let component = Component::new("some_component_appid", "some_component_secret")
+            .with_ticket_endpoint("/component/event", "some_token", &encoding_aes_key);
+        let app_info = AppInfo::component("some_appid".into(), "some_component_appid".into());
+        let wx_login = test_wx_login(&app_info, |b| b.with_component(component));
+        let cfg = wx_login.config();
+        let endpoint = cfg.all_push_endpoints().next().unwrap();
+        assert!(endpoint.component);
+
+        tokio_test::block_on(async {
+            let msg = "<xml><AppId><![CDATA[some_component_appid]]></AppId><CreateTime>1700000000</CreateTime><InfoType><![CDATA[component_verify_ticket]]></InfoType><ComponentVerifyTicket><![CDATA[some_ticket]]></ComponentVerifyTicket></xml>";
+            let encrypted = encrypt(&key, msg, "some_component_appid");
+            let body = format!(
+                "<xml><AppId><![CDATA[some_component_appid]]></AppId><Encrypt><![CDATA[{encrypted}]]></Encrypt></xml>"
+            );
+            let ts = now_timestamp();
+            let query = |msg_signature: String| PushQuery {
+                signature: endpoint.sign(&mut ["some_token", &ts, "nonce"]),
+                timestamp: ts.clone(),
+                nonce: "nonce".into(),
+                echostr: None,
+                msg_signature: Some(msg_signature),
+            };
+            let err = wx_login
+                .handle_push(endpoint, &query("bad".into()), Some(body.as_bytes()))
+                .await;
+            assert_eq!(err.unwrap_err().code, "push-sig-fail");
+            let msg_signature = endpoint.sign(&mut ["some_token", &ts, "nonce", &encrypted]);
+            let res = wx_login
+                .handle_push(endpoint, &query(msg_signature), Some(body.as_bytes()))
+                .await;
+            assert_eq!(res.unwrap(), "success");
+            let ticket = cfg
+                .access_token_cache
+                .get_ticket("some_component_appid")
+                .await
+                .unwrap();
+            assert_eq!(ticket.as_deref(), Some("some_ticket"));
+
+            let manager = wx_login
+                .component_access_token_manager("some_component_appid")
+                .await
+                .unwrap();
+            assert_eq!(manager.api(), AccessTokenApi::Component);
+            let err = wx_login
+                .access_token_manager("some_appid")
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("unsupported"));
+
+            let (_, login_info) = test_login_with(
+                &wx_login,
+                &app_info,
+                "some_openid",
+                Some("some_unionid"),
+                [1; 16],
+                Claims::new(),
+            )
+            .await;
+            assert_eq!(login_info.openid, "some_openid");
+            assert_eq!(login_info.unionid.as_deref(), Some("some_unionid"));
+        });
+
+        assert!(ConfigBuilder::new()
+            .with_app_info(AppInfo::component(
+                "some_appid".into(),
+                "other_component_appid".into()
+            ))
+            .try_build()
+            .is_err());
+    }
+}
@@ -1,12 +1,35 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
 
-use crate::core::security::secret_utils::SecretString;
+use crate::core::{
+    access_token::AccessTokenApi,
+    authz::ClaimsResolver,
+    component::Component,
+    cookie::CookieSession,
+    credential::CredentialSource,
+    handoff::Handoff,
+    login::Error,
+    oauth::OAuthLogin,
+    push::PushEndpoint,
+    registry::{AppRegistry, StaticAppRegistry},
+    revocation::RevocationStore,
+    scan_login::ScanLogin,
+    security::secret_utils::SecretString,
+    session::SessionStore,
+    ticket::{MemoryTicketStore, TicketStore},
+    token_cache::{AccessTokenCache, MemoryTokenCache},
+};
 
 /// Basic data (app-id, app-secret) of a WeChat mini-program.
 #[derive(Default, Debug, Clone)]
 pub struct AppInfo {
     pub(crate) appid: String,
     pub(crate) secret: SecretString,
+    pub(crate) corpid: Option<String>,
+    pub(crate) component_appid: Option<String>,
 }
 impl AppInfo {
     /// Create a new AppInfo from (appid, secret).
@@ -14,6 +37,28 @@ impl AppInfo {
         Self {
             appid,
             secret: SecretString(secret),
+            corpid: None,
+            component_appid: None,
+        }
+    }
+    /// Create a new AppInfo of a mini-program of WeCom (Enterprise WeChat) from (appid, corpid, corpsecret),
+    /// where the corpsecret is the secret of the application in WeCom bound to the mini-program.
+    ///
+    /// Login of the app goes through WeCom, see [WeCom login](crate#wecom-login).
+    pub fn wecom(appid: String, corpid: String, corpsecret: String) -> Self {
+        Self {
+            corpid: Some(corpid),
+            ..Self::from(appid, corpsecret)
+        }
+    }
+    /// Create a new AppInfo of a mini-program authorized to the third-party platform of the component_appid,
+    /// which needs no secret of its own.
+    ///
+    /// Login of the app goes through the platform, see [third-party platform login](crate#third-party-platform-login).
+    pub fn component(appid: String, component_appid: String) -> Self {
+        Self {
+            component_appid: Some(component_appid),
+            ..Self::from(appid, String::new())
         }
     }
 }
@@ -21,18 +66,52 @@ impl AppInfo {
 /// Configuration of the crate.
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub(crate) app_map: HashMap<String, AppInfo>,
+    pub(crate) app_registry: Arc<dyn AppRegistry>,
     pub(crate) login_path: String,
+    pub(crate) phone_number_path: Option<String>,
+    pub(crate) check_session_path: Option<String>,
+    pub(crate) push_endpoints: Vec<PushEndpoint>,
+    pub(crate) scan_login: Option<ScanLogin>,
+    pub(crate) handoff: Option<Handoff>,
+    pub(crate) oauth_logins: Vec<OAuthLogin>,
+    pub(crate) components: Vec<Component>,
+    pub(crate) cookie_session: Option<CookieSession>,
+    pub(crate) credential_sources: Vec<CredentialSource>,
     pub(crate) auth_sig: bool,
     pub(crate) sig_valid_secs: u64,
+    pub(crate) http_timeout_secs: u64,
+    pub(crate) open_data_max_age_secs: u64,
+    pub(crate) claims_resolver: Option<Arc<dyn ClaimsResolver>>,
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+    pub(crate) revocation_store: Option<Arc<dyn RevocationStore>>,
+    pub(crate) ticket_store: Arc<dyn TicketStore>,
+    pub(crate) access_token_api: AccessTokenApi,
+    pub(crate) access_token_cache: Arc<dyn AccessTokenCache>,
 }
 impl Default for Config {
     fn default() -> Self {
         Self {
-            app_map: Default::default(),
+            app_registry: Arc::new(StaticAppRegistry::default()),
             login_path: "/login".into(),
+            phone_number_path: None,
+            check_session_path: None,
+            push_endpoints: vec![],
+            scan_login: None,
+            handoff: None,
+            oauth_logins: vec![],
+            components: vec![],
+            cookie_session: None,
+            credential_sources: vec![CredentialSource::default()],
             auth_sig: true,
             sig_valid_secs: 600,
+            http_timeout_secs: 10,
+            open_data_max_age_secs: 600,
+            claims_resolver: None,
+            session_store: None,
+            revocation_store: None,
+            ticket_store: Arc::new(MemoryTicketStore::new()),
+            access_token_api: Default::default(),
+            access_token_cache: Arc::new(MemoryTokenCache::new()),
         }
     }
 }
@@ -41,12 +120,184 @@ impl Config {
     pub fn builder() -> ConfigBuilder {
         ConfigBuilder::new()
     }
+    /// Load and validate a Config from a TOML or JSON file.
+    ///
+    /// See [ConfigBuilder::with_file] for the file format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config, Error> {
+        ConfigBuilder::new().with_file(path)?.try_build()
+    }
+    /// Get the configured component (third-party platform) of the component_appid.
+    pub(crate) fn component(&self, component_appid: &str) -> Option<&Component> {
+        self.components.iter().find(|c| c.appid == component_appid)
+    }
+    /// Get all push endpoints, including the ticket endpoints of components.
+    pub(crate) fn all_push_endpoints(&self) -> impl Iterator<Item = &PushEndpoint> {
+        self.push_endpoints.iter().chain(
+            self.components
+                .iter()
+                .filter_map(|c| c.ticket_endpoint.as_ref()),
+        )
+    }
+    /// Describe the settings changed from self to other.
+    ///
+    /// Apps, push endpoints and components are compared by their real fields, and only
+    /// the names of changed fields are reported, so secrets never show up in the result.
+    pub(crate) fn diff(&self, other: &Config) -> Vec<String> {
+        fn fields(cfg: &Config) -> Vec<(&'static str, String)> {
+            vec![
+                ("login_path", format!("{:?}", cfg.login_path)),
+                ("phone_number_path", format!("{:?}", cfg.phone_number_path)),
+                (
+                    "check_session_path",
+                    format!("{:?}", cfg.check_session_path),
+                ),
+                ("scan_login", format!("{:?}", cfg.scan_login)),
+                ("handoff", format!("{:?}", cfg.handoff)),
+                ("oauth_logins", format!("{:?}", cfg.oauth_logins)),
+                ("cookie_session", format!("{:?}", cfg.cookie_session)),
+                (
+                    "credential_sources",
+                    format!("{:?}", cfg.credential_sources),
+                ),
+                ("auth_sig", format!("{:?}", cfg.auth_sig)),
+                ("sig_valid_secs", format!("{:?}", cfg.sig_valid_secs)),
+                ("http_timeout_secs", format!("{:?}", cfg.http_timeout_secs)),
+                (
+                    "open_data_max_age_secs",
+                    format!("{:?}", cfg.open_data_max_age_secs),
+                ),
+                ("claims_resolver", format!("{:?}", cfg.claims_resolver)),
+                ("session_store", format!("{:?}", cfg.session_store)),
+                ("revocation_store", format!("{:?}", cfg.revocation_store)),
+                ("ticket_store", format!("{:?}", cfg.ticket_store)),
+                ("access_token_api", format!("{:?}", cfg.access_token_api)),
+                (
+                    "access_token_cache",
+                    format!("{:?}", cfg.access_token_cache),
+                ),
+            ]
+        }
+        let mut diff = Vec::new();
+        match (self.app_registry.apps(), other.app_registry.apps()) {
+            (Some(old), Some(new)) => diff_items(
+                "apps",
+                old.iter().map(|app| (app.appid.as_str(), app)),
+                new.iter().map(|app| (app.appid.as_str(), app)),
+                app_changes,
+                &mut diff,
+            ),
+            _ if !Arc::ptr_eq(&self.app_registry, &other.app_registry) => {
+                diff.push("app_registry: replaced".into())
+            }
+            _ => {}
+        }
+        diff_items(
+            "push_endpoints",
+            self.push_endpoints.iter().map(|e| (e.path.as_str(), e)),
+            other.push_endpoints.iter().map(|e| (e.path.as_str(), e)),
+            push_endpoint_changes,
+            &mut diff,
+        );
+        diff_items(
+            "components",
+            self.components.iter().map(|c| (c.appid.as_str(), c)),
+            other.components.iter().map(|c| (c.appid.as_str(), c)),
+            component_changes,
+            &mut diff,
+        );
+        diff.extend(
+            fields(self)
+                .into_iter()
+                .zip(fields(other))
+                .filter(|((_, old), (_, new))| old != new)
+                .map(|((name, old), (_, new))| format!("{name}: {old} -> {new}")),
+        );
+        diff
+    }
+}
+
+/// Describe the added, removed and changed items of two lists keyed by `(key, item)`.
+fn diff_items<'a, T: 'a>(
+    name: &str,
+    old: impl Iterator<Item = (&'a str, &'a T)>,
+    new: impl Iterator<Item = (&'a str, &'a T)>,
+    changes: fn(&T, &T) -> Vec<&'static str>,
+    diff: &mut Vec<String>,
+) {
+    let old = old.collect::<BTreeMap<_, _>>();
+    let new = new.collect::<BTreeMap<_, _>>();
+    for (key, old_item) in &old {
+        match new.get(key) {
+            None => diff.push(format!("{name}[{key}]: removed")),
+            Some(new_item) => diff.extend(
+                changes(old_item, new_item)
+                    .into_iter()
+                    .map(|field| format!("{name}[{key}].{field}: changed")),
+            ),
+        }
+    }
+    for key in new.keys().filter(|key| !old.contains_key(*key)) {
+        diff.push(format!("{name}[{key}]: added"));
+    }
+}
+
+fn app_changes(old: &AppInfo, new: &AppInfo) -> Vec<&'static str> {
+    [
+        ("secret", old.secret != new.secret),
+        ("corpid", old.corpid != new.corpid),
+        (
+            "component_appid",
+            old.component_appid != new.component_appid,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+fn push_endpoint_changes(old: &PushEndpoint, new: &PushEndpoint) -> Vec<&'static str> {
+    let handlers_changed = old.handlers.len() != new.handlers.len()
+        || old
+            .handlers
+            .iter()
+            .zip(&new.handlers)
+            .any(|(old, new)| !Arc::ptr_eq(old, new));
+    [
+        ("path", old.path != new.path),
+        ("appid", old.appid != new.appid),
+        ("token", old.token != new.token),
+        (
+            "encoding_aes_key",
+            old.encoding_aes_key != new.encoding_aes_key,
+        ),
+        ("handlers", handlers_changed),
+        ("component", old.component != new.component),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+fn component_changes(old: &Component, new: &Component) -> Vec<&'static str> {
+    let ticket_endpoint_changed = match (&old.ticket_endpoint, &new.ticket_endpoint) {
+        (Some(old), Some(new)) => !push_endpoint_changes(old, new).is_empty(),
+        (old, new) => old.is_some() != new.is_some(),
+    };
+    [
+        ("secret", old.secret != new.secret),
+        ("ticket_endpoint", ticket_endpoint_changed),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
 }
 
 /// A builder for make custumized Config.
 #[derive(Default)]
 pub struct ConfigBuilder {
     cfg: Config,
+    app_map: HashMap<String, AppInfo>,
+    app_registry: Option<Arc<dyn AppRegistry>>,
 }
 impl ConfigBuilder {
     /// Create a ConfigBuilder with default configuration.
@@ -54,7 +305,7 @@ impl ConfigBuilder {
         Default::default()
     }
     fn add_app_info(&mut self, app_info: AppInfo) {
-        self.cfg.app_map.insert(app_info.appid.clone(), app_info);
+        self.app_map.insert(app_info.appid.clone(), app_info);
     }
     /// Add one entry of app
     pub fn with_app_info(mut self, app_info: AppInfo) -> Self {
@@ -62,7 +313,7 @@ impl ConfigBuilder {
         self
     }
     /// Load app entries from environment varibles.
-    /// 
+    ///
     /// All environment variables with prefix of WX_APP_ will be parsed as WX_APP_\<app-id\> = \<app-secret\>
     /// and be loaded as app-info.
     pub fn with_env_var(mut self) -> Self {
@@ -75,29 +326,520 @@ impl ConfigBuilder {
         self
     }
     /// Set the login path
-    /// 
+    ///
     /// The default value is "/login", one can override the path value.
     pub fn with_login_path(mut self, path: &str) -> Self {
         self.cfg.login_path = path.into();
         self
     }
+    /// Enable the endpoint exchanging `getPhoneNumber` codes for phone numbers at the path.
+    ///
+    /// The endpoint is disabled by default. It requires a login session and takes `code`
+    /// by GET query or POST JSON, see [WxLogin::get_phone_number](crate::wx_login::WxLogin::get_phone_number).
+    pub fn with_phone_number_path(mut self, path: &str) -> Self {
+        self.cfg.phone_number_path = Some(path.into());
+        self
+    }
+    /// Enable the endpoint telling whether the session_key of the login session is still valid at the path.
+    ///
+    /// The endpoint is disabled by default. It requires a login session and returns `{"valid": <bool>}`,
+    /// so the client can login again before decrypting open data fails.
+    pub fn with_check_session_path(mut self, path: &str) -> Self {
+        self.cfg.check_session_path = Some(path.into());
+        self
+    }
+    /// Add an endpoint receiving messages pushed by WeChat server.
+    ///
+    /// Requests to the path of the endpoint are verified by the token and do not require login sessions.
+    pub fn with_push_endpoint(mut self, endpoint: PushEndpoint) -> Self {
+        self.cfg.push_endpoints.push(endpoint);
+        self
+    }
+    /// Enable the endpoints of scan-to-login for web, see [ScanLogin].
+    ///
+    /// Tickets are kept by the ticket store, see [with_ticket_store](Self::with_ticket_store).
+    pub fn with_scan_login(mut self, scan_login: ScanLogin) -> Self {
+        self.cfg.scan_login = Some(scan_login);
+        self
+    }
+    /// Enable the endpoints handing off login sessions to H5 pages in `<web-view>`, see [Handoff].
+    ///
+    /// Sessions handed off are kept in cookies of the cookie session mode, which is the default
+    /// [CookieSession] if not set by [with_cookie_session](Self::with_cookie_session).
+    pub fn with_handoff(mut self, handoff: Handoff) -> Self {
+        self.cfg.handoff = Some(handoff);
+        self
+    }
+    /// Add the endpoints of the web authorization (OAuth2) of an official account or a website app, see [OAuthLogin].
+    ///
+    /// Sessions are kept in cookies of the cookie session mode, which is the default
+    /// [CookieSession] if not set by [with_cookie_session](Self::with_cookie_session).
+    pub fn with_oauth_login(mut self, oauth_login: OAuthLogin) -> Self {
+        self.cfg.oauth_logins.push(oauth_login);
+        self
+    }
+    /// Add a third-party platform (component) logging in the mini-programs authorized to it, see [Component].
+    ///
+    /// The component_verify_ticket is kept by the access_token cache, see [with_access_token_cache](Self::with_access_token_cache).
+    pub fn with_component(mut self, component: Component) -> Self {
+        self.cfg.components.push(component);
+        self
+    }
+    /// Enable the cookie-based session mode for browser clients, see [CookieSession].
+    ///
+    /// Login responses set the session cookies besides returning the stoken.
+    pub fn with_cookie_session(mut self, cookie_session: CookieSession) -> Self {
+        self.cfg.cookie_session = Some(cookie_session);
+        self
+    }
+    /// Set where the credentials (stoken and signature) of requests come from, tried in order.
+    ///
+    /// The default is the headers `WX-LOGIN-STOKEN` and `WX-LOGIN-SIG`, see [CredentialSource].
+    pub fn with_credential_sources(mut self, sources: Vec<CredentialSource>) -> Self {
+        self.cfg.credential_sources = sources;
+        self
+    }
     /// Enable or disable signature authentication.
-    /// 
+    ///
     /// The default value is *true*.
     pub fn with_auth_sig(mut self, on: bool) -> Self {
         self.cfg.auth_sig = on;
         self
     }
     /// Set the signature valid period.
-    /// 
+    ///
     /// The default value is 600 seconds.
     pub fn with_sig_valid_secs(mut self, secs: u64) -> Self {
         self.cfg.sig_valid_secs = secs;
         self
     }
+    /// Set the timeout of calling WeChat server APIs.
+    ///
+    /// The default value is 10 seconds.
+    pub fn with_http_timeout_secs(mut self, secs: u64) -> Self {
+        self.cfg.http_timeout_secs = secs;
+        self
+    }
+    /// Set the max age of open data decrypted by the `WxOpenData` extractor,
+    /// checked against the timestamp of its watermark.
+    ///
+    /// The default value is 600 seconds.
+    pub fn with_open_data_max_age_secs(mut self, secs: u64) -> Self {
+        self.cfg.open_data_max_age_secs = secs;
+        self
+    }
+    /// Load settings from a TOML string, see [with_file](Self::with_file) for the format.
+    pub fn with_toml_str(self, toml_str: &str) -> Result<Self, Error> {
+        let file: file::ConfigFile =
+            toml::from_str(toml_str).map_err(|e| format!("bad toml config: {e}"))?;
+        self.with_config_file(file)
+    }
+    /// Load settings from a JSON string, see [with_file](Self::with_file) for the format.
+    pub fn with_json_str(self, json_str: &str) -> Result<Self, Error> {
+        let file: file::ConfigFile =
+            serde_json::from_str(json_str).map_err(|e| format!("bad json config: {e}"))?;
+        self.with_config_file(file)
+    }
+    /// Load settings from a TOML or JSON file, the format is decided by the file extension.
+    ///
+    /// Settings present in the file override current ones and apps are added. For example:
+    ///
+    /// ```toml
+    /// login_path = "/login"
+    /// phone_number_path = "/phone-number"
+    /// check_session_path = "/check-session"
+    /// auth_sig = true
+    /// sig_valid_secs = 600
+    /// http_timeout_secs = 10
+    /// open_data_max_age_secs = 600
+    ///
+    /// [[apps]]
+    /// appid = "wx0000000000000001"
+    /// secret = "the-app-secret"
+    ///
+    /// [[apps]]
+    /// appid = "wx0000000000000002"
+    /// # or read the secret from a file
+    /// secret_file = "/run/secrets/wx0000000000000002"
+    ///
+    /// [[apps]]
+    /// appid = "wx0000000000000003"
+    /// # or read the secret from an environment variable
+    /// secret_env = "WX_SECRET_3"
+    /// ```
+    pub fn with_file(self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("read config file {} fail: {e}", path.display()))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => self.with_toml_str(&content),
+            Some("json") => self.with_json_str(&content),
+            _ => Err(format!("unknown config file type: {}", path.display()).into()),
+        }
+    }
+    fn with_config_file(mut self, file: file::ConfigFile) -> Result<Self, Error> {
+        for app in file.apps {
+            let app_info = app.into_app_info()?;
+            self.add_app_info(app_info);
+        }
+        if let Some(login_path) = file.login_path {
+            self.cfg.login_path = login_path;
+        }
+        if let Some(path) = file.phone_number_path {
+            self.cfg.phone_number_path = Some(path);
+        }
+        if let Some(path) = file.check_session_path {
+            self.cfg.check_session_path = Some(path);
+        }
+        if let Some(auth_sig) = file.auth_sig {
+            self.cfg.auth_sig = auth_sig;
+        }
+        if let Some(secs) = file.sig_valid_secs {
+            self.cfg.sig_valid_secs = secs;
+        }
+        if let Some(secs) = file.http_timeout_secs {
+            self.cfg.http_timeout_secs = secs;
+        }
+        if let Some(secs) = file.open_data_max_age_secs {
+            self.cfg.open_data_max_age_secs = secs;
+        }
+        Ok(self)
+    }
+    /// Set the registry to look up apps dynamically.
+    ///
+    /// If set, app entries added by [with_app_info](Self::with_app_info) or
+    /// [with_env_var](Self::with_env_var) are ignored.
+    pub fn with_app_registry(mut self, registry: Arc<dyn AppRegistry>) -> Self {
+        self.app_registry = Some(registry);
+        self
+    }
+    /// Set the resolver of roles and permissions granted to users on login.
+    ///
+    /// By default no resolver is set and sessions have empty claims.
+    pub fn with_claims_resolver(mut self, resolver: Arc<dyn ClaimsResolver>) -> Self {
+        self.cfg.claims_resolver = Some(resolver);
+        self
+    }
+    /// Set the store to keep sessions on the server side.
+    ///
+    /// By default no store is set and sessions are carried by self-contained stokens,
+    /// if set, login hands out opaque session IDs resolved by the store instead.
+    pub fn with_session_store(mut self, store: Arc<dyn SessionStore>) -> Self {
+        self.cfg.session_store = Some(store);
+        self
+    }
+    /// Set the store of revoked users, checked on every authentication.
+    ///
+    /// By default no store is set and only sessions kept by the session store can be revoked,
+    /// see [WxLogin::revoke_user_sessions](crate::wx_login::WxLogin::revoke_user_sessions).
+    pub fn with_revocation_store(mut self, store: Arc<dyn RevocationStore>) -> Self {
+        self.cfg.revocation_store = Some(store);
+        self
+    }
+    /// Set the store of short-lived tickets, e.g. those of scan-to-login.
+    ///
+    /// The default is an in-memory store, use a custom (e.g. Redis-backed) [TicketStore]
+    /// so that replicas share tickets.
+    pub fn with_ticket_store(mut self, store: Arc<dyn TicketStore>) -> Self {
+        self.cfg.ticket_store = store;
+        self
+    }
+    /// Set the WeChat server API to fetch access_token.
+    ///
+    /// The default value is [AccessTokenApi::Token].
+    pub fn with_access_token_api(mut self, api: AccessTokenApi) -> Self {
+        self.cfg.access_token_api = api;
+        self
+    }
+    /// Set the cache of access_token shared by replicas.
+    ///
+    /// The default is an in-memory cache, use [FileTokenCache](crate::wx_login::FileTokenCache)
+    /// or a custom (e.g. Redis-backed) [AccessTokenCache] so that replicas share one token.
+    pub fn with_access_token_cache(mut self, cache: Arc<dyn AccessTokenCache>) -> Self {
+        self.cfg.access_token_cache = cache;
+        self
+    }
     /// Build a new Config object using current params.
+    ///
+    /// Invalid params are only logged, use [try_build](Self::try_build) to get the error.
     pub fn build(self) -> Config {
+        if let Err(e) = self.validate() {
+            tracing::warn!("invalid config: {e}");
+        }
+        self.build_unchecked()
+    }
+    /// Validate current params and build a new Config object.
+    pub fn try_build(self) -> Result<Config, Error> {
+        self.validate()?;
+        Ok(self.build_unchecked())
+    }
+    fn validate(&self) -> Result<(), Error> {
+        if self.app_registry.is_none() {
+            if self.app_map.is_empty() {
+                Err("no app is configured")?;
+            }
+            for app_info in self.app_map.values() {
+                if app_info.appid.is_empty() {
+                    Err("empty appid")?;
+                }
+                match &app_info.component_appid {
+                    Some(component_appid) => {
+                        if self.cfg.component(component_appid).is_none() {
+                            Err(format!(
+                                "component {component_appid} of app {} is not configured",
+                                app_info.appid
+                            ))?;
+                        }
+                        if app_info.corpid.is_some() {
+                            Err(format!(
+                                "app {} of WeCom can not be authorized to a component",
+                                app_info.appid
+                            ))?;
+                        }
+                    }
+                    None if app_info.secret.0.is_empty() => {
+                        Err(format!("empty secret of app {}", app_info.appid))?;
+                    }
+                    None => (),
+                }
+                if app_info.corpid.as_deref() == Some("") {
+                    Err(format!("empty corpid of app {}", app_info.appid))?;
+                }
+            }
+        }
+        if !self.cfg.login_path.starts_with('/') {
+            Err(format!(
+                "login_path {:?} is not started with '/'",
+                self.cfg.login_path
+            ))?;
+        }
+        let endpoints = [
+            ("phone_number_path", &self.cfg.phone_number_path),
+            ("check_session_path", &self.cfg.check_session_path),
+        ];
+        for (name, path) in endpoints {
+            if let Some(path) = path {
+                if !path.starts_with('/') || *path == self.cfg.login_path {
+                    Err(format!(
+                        "{name} {path:?} is not started with '/' or conflicts with login_path"
+                    ))?;
+                }
+            }
+        }
+        for component in &self.cfg.components {
+            component.validate()?;
+        }
+        for endpoint in self.cfg.all_push_endpoints() {
+            endpoint.validate()?;
+            if endpoint.path == self.cfg.login_path {
+                Err(format!(
+                    "push path {:?} conflicts with login_path",
+                    endpoint.path
+                ))?;
+            }
+        }
+        if let Some(scan_login) = &self.cfg.scan_login {
+            scan_login.validate()?;
+            if self.cfg.login_path.starts_with(&scan_login.path) {
+                Err(format!(
+                    "scan login path {:?} conflicts with login_path",
+                    scan_login.path
+                ))?;
+            }
+        }
+        if let Some(handoff) = &self.cfg.handoff {
+            handoff.validate()?;
+            if [&handoff.mint_path, &handoff.exchange_path].contains(&&self.cfg.login_path) {
+                Err("handoff path conflicts with login_path")?;
+            }
+        }
+        for oauth_login in &self.cfg.oauth_logins {
+            oauth_login.validate()?;
+            if [Some(oauth_login.path.as_str()), oauth_login.callback_path()]
+                .contains(&Some(self.cfg.login_path.as_str()))
+            {
+                Err("OAuth login path conflicts with login_path")?;
+            }
+        }
+        if let Some(cookie_session) = &self.cfg.cookie_session {
+            cookie_session.validate()?;
+        }
+        if self.cfg.credential_sources.is_empty() {
+            Err("no credential source")?;
+        }
+        for source in &self.cfg.credential_sources {
+            source.validate()?;
+        }
+        if self.cfg.auth_sig && self.cfg.sig_valid_secs == 0 {
+            Err("sig_valid_secs is 0 while auth_sig is on")?;
+        }
+        if self.cfg.http_timeout_secs == 0 {
+            Err("http_timeout_secs is 0")?;
+        }
+        Ok(())
+    }
+    fn build_unchecked(mut self) -> Config {
+        self.cfg.app_registry = match self.app_registry {
+            Some(registry) => registry,
+            None => Arc::new(StaticAppRegistry::new(self.app_map)),
+        };
         tracing::info!("use {:?}", self.cfg);
         self.cfg
     }
 }
+
+mod file {
+    use super::*;
+    use serde::Deserialize;
+    use std::path::PathBuf;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(crate) struct ConfigFile {
+        #[serde(default)]
+        pub(crate) apps: Vec<AppEntry>,
+        pub(crate) login_path: Option<String>,
+        pub(crate) phone_number_path: Option<String>,
+        pub(crate) check_session_path: Option<String>,
+        pub(crate) auth_sig: Option<bool>,
+        pub(crate) sig_valid_secs: Option<u64>,
+        pub(crate) http_timeout_secs: Option<u64>,
+        pub(crate) open_data_max_age_secs: Option<u64>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(crate) struct AppEntry {
+        appid: String,
+        secret: Option<String>,
+        secret_file: Option<PathBuf>,
+        secret_env: Option<String>,
+        corpid: Option<String>,
+        component_appid: Option<String>,
+    }
+
+    impl AppEntry {
+        pub(crate) fn into_app_info(self) -> Result<AppInfo, Error> {
+            if let Some(component_appid) = self.component_appid {
+                if self.secret.is_some()
+                    || self.secret_file.is_some()
+                    || self.secret_env.is_some()
+                    || self.corpid.is_some()
+                {
+                    Err(format!(
+                        "app {} authorized to a component requires no secret or corpid",
+                        self.appid
+                    ))?;
+                }
+                return Ok(AppInfo::component(self.appid, component_appid));
+            }
+            let secret = match (self.secret, self.secret_file, self.secret_env) {
+                (Some(secret), None, None) => secret,
+                (None, Some(path), None) => std::fs::read_to_string(&path)
+                    .map_err(|e| {
+                        format!(
+                            "read secret file {} of app {} fail: {e}",
+                            path.display(),
+                            self.appid
+                        )
+                    })?
+                    .trim()
+                    .into(),
+                (None, None, Some(name)) => std::env::var(&name).map_err(|e| {
+                    format!("read secret env {name} of app {} fail: {e}", self.appid)
+                })?,
+                _ => Err(format!(
+                    "app {} requires exactly one of secret, secret_file and secret_env",
+                    self.appid
+                ))?,
+            };
+            Ok(match self.corpid {
+                Some(corpid) => AppInfo::wecom(self.appid, corpid, secret),
+                None => AppInfo::from(self.appid, secret),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_and_validate() {
+        std::env::set_var("WX_TEST_CONFIG_SECRET", "secret_from_env");
+        let cfg = ConfigBuilder::new()
+            .with_toml_str(
+                r#"
+                login_path = "/wx/login"
+                sig_valid_secs = 300
+
+                [[apps]]
+                appid = "appid_1"
+                secret = "secret_1"
+
+                [[apps]]
+                appid = "appid_2"
+                secret_env = "WX_TEST_CONFIG_SECRET"
+                "#,
+            )
+            .unwrap()
+            .try_build()
+            .unwrap();
+        assert_eq!(cfg.login_path, "/wx/login");
+        assert_eq!(cfg.sig_valid_secs, 300);
+        assert!(cfg.auth_sig);
+
+        let cfg = ConfigBuilder::new()
+            .with_json_str(r#"{"apps": [{"appid": "appid_1", "secret": "secret_1"}]}"#)
+            .unwrap()
+            .try_build();
+        assert!(cfg.is_ok());
+
+        let err = ConfigBuilder::new()
+            .with_json_str(r#"{"apps": [{"appid": "appid_1"}]}"#)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("exactly one of"));
+        assert!(ConfigBuilder::new().with_json_str(r#"{"bad": 1}"#).is_err());
+        assert!(ConfigBuilder::new().try_build().is_err());
+        assert!(ConfigBuilder::new()
+            .with_app_info(AppInfo::from("appid_1".into(), "secret_1".into()))
+            .with_login_path("login")
+            .try_build()
+            .is_err());
+    }
+
+    #[test]
+    fn diff_without_secrets() {
+        let build = |secret: &str, token: &str, apps: &[&str]| {
+            let mut builder = ConfigBuilder::new().with_push_endpoint(PushEndpoint::new(
+                "/push",
+                "some_appid",
+                token,
+            ));
+            for appid in apps {
+                builder = builder.with_app_info(AppInfo::from(appid.to_string(), secret.into()));
+            }
+            builder.build()
+        };
+        let old = build("old_secret_value", "old_token_value", &["app1", "app2"]);
+        assert!(old.diff(&old).is_empty());
+        let new = build("new_secret_value", "old_token_value", &["app1", "app2"]);
+        assert_eq!(
+            old.diff(&new),
+            ["apps[app1].secret: changed", "apps[app2].secret: changed"]
+        );
+        let new = build("old_secret_value", "new_token_value", &["app2", "app3"]);
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            [
+                "apps[app1]: removed",
+                "apps[app3]: added",
+                "push_endpoints[/push].token: changed"
+            ]
+        );
+        assert!(!diff.join(", ").contains("token_value"));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{future::Future, time::Duration};
+
+use futures_util::{Stream, StreamExt};
+
+use crate::core::login::{Error, WxLogin, WxLoginInfo};
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    authz::login_info_from_auth_result,
+    login::{WxLoginErr, AUTH_FAIL_MSG},
+};
+
+/// The minimal interval of checking the session of connections.
+pub(crate) const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// The reason of closing WebSockets with code 1008 when the session expires, which is fixed
+/// since the reason of a close frame is limited to 123 bytes.
+#[cfg(any(feature = "axum-ws", feature = "actix-ws"))]
+pub(crate) const EXPIRED_CLOSE_REASON: &str = "login session expired";
+
+/// The stoken of the authenticated request, kept by the middleware for [WxConnection].
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug, Clone)]
+pub(crate) struct SessionToken(pub(crate) String);
+
+/// The login session of a long-lived connection, e.g. WebSocket and SSE.
+///
+/// The connection is authenticated once at the handshake (usually with query credentials,
+/// see [CredentialSource::query](crate::wx_login::CredentialSource::query)), and the session
+/// can be re-checked periodically afterwards, to end the connection when the session expires,
+/// e.g. revoked, or removed by the session store.
+#[derive(Debug, Clone)]
+pub struct WxConnection {
+    pub login_info: WxLoginInfo,
+    wx_login: WxLogin,
+    stoken: String,
+}
+
+impl WxConnection {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn from_request(
+        wx_login: Option<&WxLogin>,
+        auth_result: Option<&Result<WxLoginInfo, Error>>,
+        stoken: Option<&SessionToken>,
+    ) -> Result<Self, WxLoginErr> {
+        let login_info = login_info_from_auth_result(auth_result)?;
+        match (wx_login, stoken) {
+            (Some(wx_login), Some(stoken)) => Ok(Self {
+                login_info,
+                wx_login: wx_login.clone(),
+                stoken: stoken.0.clone(),
+            }),
+            _ => Err(WxLoginErr {
+                status: 500,
+                code: "login-session-lost".into(),
+                message: AUTH_FAIL_MSG.into(),
+                detail: "no stoken of the connection".into(),
+            }),
+        }
+    }
+
+    /// Check if the session is still valid, without signature.
+    pub async fn check_session(&self) -> Result<(), Error> {
+        self.wx_login
+            .authenticate_cookie(&self.stoken)
+            .await
+            .map(|_| ())
+    }
+
+    /// Wait until the session expires, checked every interval (at least 1 second),
+    /// returning the reason.
+    ///
+    /// Note that each check also refreshes the idle time of sessions kept by the session store.
+    pub async fn expired(&self, check_interval: Duration) -> Error {
+        loop {
+            tokio::time::sleep(check_interval.max(MIN_CHECK_INTERVAL)).await;
+            if let Err(e) = self.check_session().await {
+                tracing::info!(
+                    appid = self.login_info.appid,
+                    openid = self.login_info.openid,
+                    "session of connection expired: {e}"
+                );
+                return e;
+            }
+        }
+    }
+
+    /// Run the future (e.g. the handler of a WebSocket) until it completes,
+    /// or the session expires where the future is dropped.
+    pub async fn run_until_expired<F: Future>(
+        &self,
+        check_interval: Duration,
+        fut: F,
+    ) -> Result<F::Output, Error> {
+        tokio::select! {
+            output = fut => Ok(output),
+            e = self.expired(check_interval) => Err(e),
+        }
+    }
+
+    /// Wrap the stream (e.g. the events of SSE) to end when the session expires.
+    pub fn take_until_expired<S: Stream>(
+        &self,
+        check_interval: Duration,
+        stream: S,
+    ) -> impl Stream<Item = S::Item> {
+        let myself = self.clone();
+        stream.take_until(Box::pin(
+            async move { myself.expired(check_interval).await },
+        ))
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use crate::core::{
+        login::tests::{test_app_info, test_login, test_wx_login},
+        session::{MemorySessionStore, SessionStore},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn end_connection_when_session_expires() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let store = Arc::new(MemorySessionStore::new());
+            let wx_login = test_wx_login(&app_info, |b| b.with_session_store(store.clone()));
+            let (ok, login_info) =
+                test_login(&wx_login, &app_info, "some_openid", Default::default()).await;
+            let auth_result = Ok(login_info);
+            assert!(WxConnection::from_request(Some(&wx_login), Some(&auth_result), None).is_err());
+            let stoken = SessionToken(ok.stoken.clone());
+            let conn =
+                WxConnection::from_request(Some(&wx_login), Some(&auth_result), Some(&stoken))
+                    .unwrap();
+            assert_eq!(conn.login_info.openid, "some_openid");
+            assert!(conn.check_session().await.is_ok());
+
+            let interval = Duration::from_secs(1);
+            let done = conn.run_until_expired(interval, async { 1 }).await;
+            assert_eq!(done.unwrap(), 1);
+
+            let sid = ok.stoken.split_once(':').unwrap().1;
+            store.remove(sid).await.unwrap();
+            let pending = futures_util::future::pending::<()>();
+            assert!(conn.run_until_expired(interval, pending).await.is_err());
+            let events = conn.take_until_expired(interval, futures_util::stream::repeat(1).take(3));
+            assert_eq!(events.collect::<Vec<_>>().await, vec![1, 1, 1]);
+            let events = conn.take_until_expired(interval, futures_util::stream::pending::<i32>());
+            assert!(events.collect::<Vec<_>>().await.is_empty());
+        })
+    }
+}
@@ -0,0 +1,302 @@
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use tiny_crypto::encoding::{Encoder, HEX};
+
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    login::{WxLogin, WxLoginInfo},
+    security::random_bytes,
+};
+
+/// The default name of the cookie keeping the stoken of browser sessions.
+pub const SESSION_COOKIE_NAME: &str = "wx_login_stoken";
+/// The default name of the cookie keeping the CSRF token.
+pub const CSRF_COOKIE_NAME: &str = "wx_login_csrf";
+/// The default name of the header echoing the CSRF token.
+pub const CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+
+/// The SameSite attribute of cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// The cookie-based session mode for browser clients, e.g. H5 pages and server-rendered admin tools.
+///
+/// Login sets the stoken in an HttpOnly cookie, and the middleware authenticates requests
+/// without other credentials by the cookie, where the signature is not required.
+/// Instead, requests other than GET, HEAD and OPTIONS must echo the value of the CSRF cookie
+/// (readable by scripts) in the CSRF header, i.e. the double-submit token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieSession {
+    pub(crate) name: String,
+    pub(crate) domain: Option<String>,
+    pub(crate) path: String,
+    pub(crate) max_age_secs: Option<u64>,
+    pub(crate) same_site: SameSite,
+    pub(crate) secure: bool,
+    pub(crate) csrf_cookie_name: String,
+    pub(crate) csrf_header_name: String,
+}
+
+impl Default for CookieSession {
+    fn default() -> Self {
+        Self {
+            name: SESSION_COOKIE_NAME.into(),
+            domain: None,
+            path: "/".into(),
+            max_age_secs: None,
+            same_site: SameSite::Strict,
+            secure: true,
+            csrf_cookie_name: CSRF_COOKIE_NAME.into(),
+            csrf_header_name: CSRF_HEADER_NAME.into(),
+        }
+    }
+}
+
+impl CookieSession {
+    /// Create the mode with default settings: cookie `wx_login_stoken` of path `/`,
+    /// Secure, SameSite=Strict and expiring when the browser closes.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Set the name of the session cookie.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.into();
+        self
+    }
+    /// Set the Domain attribute, the default is the host of the request.
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+    /// Set the Path attribute, the default is `/`.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = path.into();
+        self
+    }
+    /// Set the Max-Age attribute, the default is none (expiring when the browser closes).
+    pub fn with_max_age_secs(mut self, secs: u64) -> Self {
+        self.max_age_secs = Some(secs);
+        self
+    }
+    /// Set the SameSite attribute, the default is Strict.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+    /// Set the Secure attribute, the default is true, turn it off only for local development over HTTP.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+    /// Set the names of the CSRF cookie and header, the default is `wx_login_csrf` and `X-CSRF-Token`.
+    pub fn with_csrf_names(mut self, cookie_name: &str, header_name: &str) -> Self {
+        self.csrf_cookie_name = cookie_name.into();
+        self.csrf_header_name = header_name.into();
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let is_token = |s: &str| {
+            !s.is_empty()
+                && s.bytes()
+                    .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b))
+        };
+        for name in [&self.name, &self.csrf_cookie_name, &self.csrf_header_name] {
+            if !is_token(name) {
+                Err(format!("bad cookie or header name {name:?}"))?;
+            }
+        }
+        if self.name == self.csrf_cookie_name {
+            Err("session cookie and CSRF cookie have the same name")?;
+        }
+        if !self.path.starts_with('/') {
+            Err(format!(
+                "cookie path {:?} is not started with '/'",
+                self.path
+            ))?;
+        }
+        if self.same_site == SameSite::None && !self.secure {
+            Err("cookies of SameSite=None must be Secure")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn cookie(&self, name: &str, value: &str, http_only: bool) -> String {
+        let mut cookie = format!("{name}={value}; Path={}", self.path);
+        if let Some(domain) = &self.domain {
+            cookie += &format!("; Domain={domain}");
+        }
+        if let Some(max_age) = self.max_age_secs {
+            cookie += &format!("; Max-Age={max_age}");
+        }
+        if http_only {
+            cookie += "; HttpOnly";
+        }
+        if self.secure {
+            cookie += "; Secure";
+        }
+        cookie += match self.same_site {
+            SameSite::Strict => "; SameSite=Strict",
+            SameSite::Lax => "; SameSite=Lax",
+            SameSite::None => "; SameSite=None",
+        };
+        cookie
+    }
+
+    /// Make the `Set-Cookie` headers of the session cookie and a new CSRF cookie.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn set_cookies(&self, stoken: &str) -> Vec<String> {
+        let csrf_token = HEX.to_text(&random_bytes::<16>());
+        vec![
+            self.cookie(&self.name, stoken, true),
+            self.cookie(&self.csrf_cookie_name, &csrf_token, false),
+        ]
+    }
+
+    /// Check the double-submit CSRF token of requests with unsafe methods.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn check_csrf(
+        &self,
+        cookies: &[&str],
+        safe_method: bool,
+        csrf_header: Option<&str>,
+    ) -> Result<(), Error> {
+        if safe_method {
+            return Ok(());
+        }
+        let csrf_cookie = cookie_value(cookies.iter().copied(), &self.csrf_cookie_name)
+            .ok_or("no CSRF cookie")?;
+        match csrf_header {
+            Some(token) if !token.is_empty() && token == csrf_cookie => Ok(()),
+            Some(_) => Err("CSRF token mismatch".into()),
+            None => Err(format!("no {} header", self.csrf_header_name).into()),
+        }
+    }
+}
+
+/// Get the value of the cookie from `Cookie` headers.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn cookie_value<'a>(
+    headers: impl IntoIterator<Item = &'a str>,
+    name: &str,
+) -> Option<&'a str> {
+    headers
+        .into_iter()
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.trim_matches('"'))
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Get the cookie session mode, which is the configured one, or the default one
+    /// if only handoff or OAuth login is enabled.
+    pub(crate) fn cookie_session(&self) -> Option<CookieSession> {
+        let cfg = self.config();
+        match &cfg.cookie_session {
+            Some(cookie_session) => Some(cookie_session.clone()),
+            None if cfg.handoff.is_some() || !cfg.oauth_logins.is_empty() => {
+                Some(CookieSession::default())
+            }
+            None => None,
+        }
+    }
+
+    /// Authenticate a request by the session cookie and the CSRF token, with the stoken in the cookie,
+    /// `None` means the cookie session mode is off or there is no session cookie.
+    pub(crate) async fn authenticate_cookies(
+        &self,
+        cookies: &[&str],
+        safe_method: bool,
+        csrf_header: Option<&str>,
+    ) -> Option<(String, Result<WxLoginInfo, Error>)> {
+        let cookie_session = self.cookie_session()?;
+        let stoken = cookie_value(cookies.iter().copied(), &cookie_session.name)?;
+        if let Err(e) = cookie_session.check_csrf(cookies, safe_method, csrf_header) {
+            return Some((stoken.into(), Err(e)));
+        }
+        Some((stoken.into(), self.authenticate_cookie(stoken).await))
+    }
+
+    /// Get the `Set-Cookie` headers of the login response, empty if the cookie session mode is off.
+    pub(crate) fn login_cookies(&self, stoken: &str) -> Vec<String> {
+        self.config()
+            .cookie_session
+            .as_ref()
+            .map(|cookie_session| cookie_session.set_cookies(stoken))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use crate::core::login::tests::{test_app_info, test_login, test_wx_login};
+
+    #[test]
+    fn read_cookies() {
+        let headers = ["a=1; wx_login_stoken=ST1:x:y:z", "b=\"2\""];
+        assert_eq!(
+            cookie_value(headers, SESSION_COOKIE_NAME),
+            Some("ST1:x:y:z")
+        );
+        assert_eq!(cookie_value(headers, "b"), Some("2"));
+        assert_eq!(cookie_value(headers, "c"), None);
+    }
+
+    #[test]
+    fn cookie_session_with_csrf() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let cookie_session = CookieSession::new()
+                .with_domain("example.com")
+                .with_max_age_secs(3600)
+                .with_same_site(SameSite::Lax);
+            let wx_login = test_wx_login(&app_info, |b| b.with_cookie_session(cookie_session));
+            let (ok, _) = test_login(&wx_login, &app_info, "some_openid", Default::default()).await;
+            let set_cookies = wx_login.login_cookies(&ok.stoken);
+            assert_eq!(
+                set_cookies[0],
+                format!(
+                    "wx_login_stoken={}; Path=/; Domain=example.com; Max-Age=3600; HttpOnly; Secure; SameSite=Lax",
+                    ok.stoken
+                )
+            );
+            assert!(!set_cookies[1].contains("HttpOnly"));
+
+            let cookie_pairs = set_cookies
+                .iter()
+                .map(|c| c.split_once(';').unwrap().0)
+                .collect::<Vec<_>>();
+            let cookies = [cookie_pairs.join("; ")];
+            let cookies = cookies.iter().map(String::as_str).collect::<Vec<_>>();
+            let csrf_token = cookie_value(cookies.iter().copied(), CSRF_COOKIE_NAME).unwrap();
+            let (wx_login, cookies, stoken) = (&wx_login, &cookies, &ok.stoken);
+            let auth = |safe, csrf| async move {
+                let (cookie_stoken, result) = wx_login
+                    .authenticate_cookies(cookies, safe, csrf)
+                    .await
+                    .unwrap();
+                assert_eq!(&cookie_stoken, stoken);
+                result
+            };
+            assert!(auth(true, None).await.is_ok());
+            assert!(auth(false, None).await.is_err());
+            assert!(auth(false, Some("bad")).await.is_err());
+            let login_info = auth(false, Some(csrf_token)).await.unwrap();
+            assert_eq!(login_info.openid, "some_openid");
+            assert!(!login_info.sig_authed);
+            assert!(wx_login
+                .authenticate_cookies(&["other=1"], true, None)
+                .await
+                .is_none());
+        })
+    }
+}
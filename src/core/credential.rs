@@ -0,0 +1,208 @@
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::login::{WxLogin, WxLoginInfo};
+
+/// The default header of the stoken.
+pub const STOKEN_HEADER_NAME: &str = "WX-LOGIN-STOKEN";
+/// The default header of the signature.
+pub const SIG_HEADER_NAME: &str = "WX-LOGIN-SIG";
+/// The scheme of the `Authorization` header.
+pub const AUTHORIZATION_SCHEME: &str = "WxLogin";
+
+/// Where the credentials (stoken and signature) of requests come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialSource {
+    /// Headers of the stoken and the signature, by default `WX-LOGIN-STOKEN` and `WX-LOGIN-SIG`.
+    Headers { stoken: String, sig: String },
+    /// The `Authorization` header, as `WxLogin stoken="<stoken>", sig="<sig>"`.
+    Authorization,
+    /// Query params of the stoken and the signature, for clients which can not set headers,
+    /// e.g. WebSocket, EventSource and image URLs.
+    ///
+    /// The signature is calculated over the URI without the signature param.
+    Query { stoken: String, sig: String },
+}
+
+impl Default for CredentialSource {
+    fn default() -> Self {
+        Self::headers(STOKEN_HEADER_NAME, SIG_HEADER_NAME)
+    }
+}
+
+impl CredentialSource {
+    /// Create a source of headers with the names.
+    pub fn headers(stoken: &str, sig: &str) -> Self {
+        Self::Headers {
+            stoken: stoken.into(),
+            sig: sig.into(),
+        }
+    }
+    /// Create a source of query params with the names.
+    pub fn query(stoken: &str, sig: &str) -> Self {
+        Self::Query {
+            stoken: stoken.into(),
+            sig: sig.into(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self {
+            Self::Headers { stoken, sig } | Self::Query { stoken, sig } => {
+                if stoken.is_empty() || sig.is_empty() || stoken == sig {
+                    Err(format!("bad credential names {stoken:?} and {sig:?}"))?;
+                }
+            }
+            Self::Authorization => {}
+        }
+        Ok(())
+    }
+
+    /// Extract the credentials from the request, `None` if there is no stoken.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn extract<'a>(
+        &self,
+        uri: &str,
+        header: &impl Fn(&str) -> Option<&'a str>,
+    ) -> Option<Credentials> {
+        match self {
+            Self::Headers { stoken, sig } => Some(Credentials {
+                stoken: header(stoken)?.into(),
+                sig: header(sig).map(Into::into),
+                signed_uri: uri.into(),
+            }),
+            Self::Authorization => {
+                let (scheme, params) = header("Authorization")?.trim().split_once(' ')?;
+                if !scheme.eq_ignore_ascii_case(AUTHORIZATION_SCHEME) {
+                    return None;
+                }
+                let param = |name: &str| {
+                    params
+                        .split(',')
+                        .filter_map(|kv| kv.trim().split_once('='))
+                        .find(|(k, _)| k.trim() == name)
+                        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+                };
+                Some(Credentials {
+                    stoken: param("stoken")?,
+                    sig: param("sig"),
+                    signed_uri: uri.into(),
+                })
+            }
+            Self::Query { stoken, sig } => {
+                let (path, query) = uri.split_once('?')?;
+                let param = |name: &str| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(k, _)| k == name)
+                        .map(|(_, v)| v.into_owned())
+                };
+                let rest = query
+                    .split('&')
+                    .filter(|kv| {
+                        let k = kv.split_once('=').map_or(*kv, |(k, _)| k);
+                        k != sig
+                    })
+                    .collect::<Vec<_>>();
+                Some(Credentials {
+                    stoken: param(stoken)?,
+                    sig: param(sig),
+                    signed_uri: match rest.is_empty() {
+                        true => path.into(),
+                        false => format!("{path}?{}", rest.join("&")),
+                    },
+                })
+            }
+        }
+    }
+}
+
+/// The credentials of a request.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(crate) stoken: String,
+    pub(crate) sig: Option<String>,
+    /// The URI covered by the signature.
+    pub(crate) signed_uri: String,
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Extract the credentials of the request from the configured sources in order,
+    /// `None` if there is no stoken in any source.
+    ///
+    /// The uri is the path and query of the request, and `header` gets the value of a header.
+    pub(crate) fn credentials<'a>(
+        &self,
+        uri: &str,
+        header: impl Fn(&str) -> Option<&'a str>,
+    ) -> Option<Credentials> {
+        self.config()
+            .credential_sources
+            .iter()
+            .find_map(|source| source.extract(uri, &header))
+    }
+
+    /// Authenticate login status by the credentials of the request.
+    pub(crate) async fn authenticate_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<WxLoginInfo, Error> {
+        let sig = credentials
+            .sig
+            .as_deref()
+            .ok_or(Error::from("no signature in credentials"));
+        self.authenticate(&credentials.stoken, &credentials.signed_uri, sig)
+            .await
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn extract(
+        source: &CredentialSource,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> Option<Credentials> {
+        let headers: HashMap<_, _> = headers.iter().copied().collect();
+        source.extract(uri, &|name| headers.get(name).copied())
+    }
+
+    #[test]
+    fn extract_credentials() {
+        let creds = extract(
+            &CredentialSource::default(),
+            "/api?a=1",
+            &[("WX-LOGIN-STOKEN", "ST1:x"), ("WX-LOGIN-SIG", "SG1:y")],
+        )
+        .unwrap();
+        assert_eq!(creds.stoken, "ST1:x");
+        assert_eq!(creds.sig.as_deref(), Some("SG1:y"));
+        assert_eq!(creds.signed_uri, "/api?a=1");
+        assert!(extract(&CredentialSource::default(), "/api", &[]).is_none());
+
+        let auth = [("Authorization", r#"WxLogin stoken="ST1:x", sig=SG1:y"#)];
+        let creds = extract(&CredentialSource::Authorization, "/api", &auth).unwrap();
+        assert_eq!(creds.stoken, "ST1:x");
+        assert_eq!(creds.sig.as_deref(), Some("SG1:y"));
+        let bearer = [("Authorization", "Bearer abc")];
+        assert!(extract(&CredentialSource::Authorization, "/api", &bearer).is_none());
+
+        let query = CredentialSource::query("stoken", "sig");
+        let creds = extract(
+            &query,
+            "/ws?stoken=ST1%3Aa%2Bb&room=1&sig=SG1%3A1%3A2%3A3",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(creds.stoken, "ST1:a+b");
+        assert_eq!(creds.sig.as_deref(), Some("SG1:1:2:3"));
+        assert_eq!(creds.signed_uri, "/ws?stoken=ST1%3Aa%2Bb&room=1");
+        let creds = extract(&query, "/ws?stoken=ST1", &[]).unwrap();
+        assert_eq!(creds.sig, None);
+        assert!(extract(&query, "/ws?room=1", &[]).is_none());
+    }
+}
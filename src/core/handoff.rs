@@ -0,0 +1,302 @@
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use std::time::{Duration, SystemTime};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use serde::Deserialize;
+use serde::Serialize;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use tiny_crypto::encoding::{Encoder, BASE64};
+
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    authz::Claims,
+    login::{err_with, ErrWith, WxLogin, WxLoginErr, WxLoginInfo},
+    ticket::{is_ticket, new_ticket, ticket_key},
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const HANDOFF_FAIL_MSG: &str = "网页登录失败";
+
+/// The endpoints handing off login sessions from the mini-program to H5 pages in `<web-view>`,
+/// which can not access the stoken of the mini-program.
+///
+/// - The mini-program requests the mint path with its login session, to get a [HandoffTicket]
+///   to put in the URL of the web-view.
+/// - The H5 page requests (or is opened at) the exchange path with `ticket` by GET query,
+///   to get a cookie-based session of the same user and claims (see [CookieSession](crate::wx_login::CookieSession)
+///   for the cookies), and is redirected to
+///   `redirect` (a path of the same site) if present.
+///
+/// Tickets are single-use and kept by the ticket store.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    pub(crate) mint_path: String,
+    pub(crate) exchange_path: String,
+    pub(crate) ticket_ttl_secs: u64,
+}
+
+impl Handoff {
+    /// Create the endpoints at the mint path and the exchange path.
+    pub fn new(mint_path: &str, exchange_path: &str) -> Self {
+        Self {
+            mint_path: mint_path.into(),
+            exchange_path: exchange_path.into(),
+            ticket_ttl_secs: 60,
+        }
+    }
+    /// Set the valid period of tickets, the default is 60 seconds.
+    pub fn with_ticket_ttl_secs(mut self, secs: u64) -> Self {
+        self.ticket_ttl_secs = secs;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        for path in [&self.mint_path, &self.exchange_path] {
+            if !path.starts_with('/') {
+                Err(format!("handoff path {path:?} is not started with '/'"))?;
+            }
+        }
+        if self.mint_path == self.exchange_path {
+            Err("handoff mint path and exchange path are the same")?;
+        }
+        if self.ticket_ttl_secs == 0 {
+            Err("ticket_ttl_secs of handoff is 0")?;
+        }
+        Ok(())
+    }
+}
+
+/// A ticket handing off the login session to H5 pages.
+#[derive(Serialize, Debug, Clone)]
+pub struct HandoffTicket {
+    pub ticket: String,
+    pub expires_in: u64,
+}
+
+/// The params of the exchange endpoint.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug)]
+pub(crate) struct HandoffExchangeRequest {
+    pub(crate) ticket: String,
+    pub(crate) redirect: Option<String>,
+}
+
+/// The result of the exchange endpoint, setting the session cookies.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug)]
+pub(crate) struct HandoffExchange {
+    pub(crate) openid: String,
+    pub(crate) set_cookies: Vec<String>,
+    pub(crate) redirect: Option<String>,
+}
+
+/// The body of the exchange endpoint if not redirected.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Serialize, Debug)]
+pub(crate) struct HandoffExchangeOk {
+    pub(crate) openid: String,
+}
+
+/// The ticket kept by the ticket store.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Serialize, Deserialize, Debug)]
+struct HandoffState {
+    appid: String,
+    openid: String,
+    #[serde(default)]
+    unionid: Option<String>,
+    session_key: String,
+    claims: Claims,
+}
+
+/// The namespace of handoff tickets in the ticket store.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const HANDOFF_TICKETS: &str = "handoff";
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const HANDOFF_ERR: ErrWith = err_with(HANDOFF_FAIL_MSG);
+
+/// Check if the redirect target is a path of the same site, e.g. not `//evil.com`.
+///
+/// Browsers drop tabs and newlines in URLs and take `\` as `/`, so `/\t/evil.com` leaves the
+/// site as well. Control characters, whitespace and `\` are rejected, also if percent-encoded.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn is_local_path(path: &str) -> bool {
+    let unsafe_char = |c: char| c.is_control() || c.is_whitespace() || c == '\\';
+    let mut escaped_chars = path
+        .split('%')
+        .skip(1)
+        .filter_map(|s| u8::from_str_radix(s.get(..2)?, 16).ok())
+        .map(char::from);
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.chars().any(unsafe_char)
+        && !escaped_chars.any(|c| c.is_ascii() && unsafe_char(c))
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Mint a handoff ticket for the login session, used by the mint endpoint.
+    pub(crate) async fn mint_handoff_ticket(
+        &self,
+        handoff: &Handoff,
+        login_info: &WxLoginInfo,
+    ) -> Result<HandoffTicket, WxLoginErr> {
+        let ticket = new_ticket();
+        let state = HandoffState {
+            appid: login_info.appid.clone(),
+            openid: login_info.openid.clone(),
+            unionid: login_info.unionid.clone(),
+            session_key: BASE64.to_text(&login_info.secret.session_key),
+            claims: login_info.claims.clone(),
+        };
+        let value = serde_json::to_string(&state)
+            .map_err(|e| HANDOFF_ERR.err(500, "ticket-store-fail", e))?;
+        let expires = SystemTime::now() + Duration::from_secs(handoff.ticket_ttl_secs);
+        self.config()
+            .ticket_store
+            .insert(&ticket_key(HANDOFF_TICKETS, &ticket), value, expires)
+            .await
+            .map_err(|e| HANDOFF_ERR.err(500, "ticket-store-fail", e))?;
+        Ok(HandoffTicket {
+            ticket,
+            expires_in: handoff.ticket_ttl_secs,
+        })
+    }
+
+    /// Exchange a handoff ticket for a new session kept in the cookie, used by the exchange endpoint.
+    pub(crate) async fn exchange_handoff_ticket(
+        &self,
+        req: &HandoffExchangeRequest,
+    ) -> Result<HandoffExchange, WxLoginErr> {
+        if let Some(redirect) = &req.redirect {
+            if !is_local_path(redirect) {
+                return Err(HANDOFF_ERR.err(400, "handoff-redirect-invalid", redirect));
+            }
+        }
+        if !is_ticket(&req.ticket) {
+            return Err(HANDOFF_ERR.err(401, "handoff-ticket-invalid", &req.ticket));
+        }
+        let value = self
+            .config()
+            .ticket_store
+            .take(&ticket_key(HANDOFF_TICKETS, &req.ticket))
+            .await
+            .map_err(|e| HANDOFF_ERR.err(500, "ticket-store-fail", e))?
+            .ok_or_else(|| HANDOFF_ERR.err(401, "handoff-ticket-invalid", &req.ticket))?;
+        let state: HandoffState = serde_json::from_str(&value)
+            .map_err(|e| HANDOFF_ERR.err(500, "ticket-store-fail", e))?;
+        let session_key = BASE64
+            .from_text(&state.session_key)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| HANDOFF_ERR.err(500, "ticket-store-fail", "bad session_key"))?;
+        let app_info = self
+            .app_info(&state.appid)
+            .await
+            .map_err(|e| HANDOFF_ERR.err(401, "appid-not-found", e))?;
+        let ok = self
+            .issue_session(
+                &app_info,
+                state.openid,
+                state.unionid,
+                session_key,
+                state.claims,
+            )
+            .await?;
+        tracing::info!(
+            appid = state.appid,
+            openid = ok.openid,
+            "login session handed off"
+        );
+        let cookie_session = self.cookie_session().unwrap_or_default();
+        Ok(HandoffExchange {
+            set_cookies: cookie_session.set_cookies(&ok.stoken),
+            openid: ok.openid,
+            redirect: req.redirect.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::core::{
+        cookie::{cookie_value, SESSION_COOKIE_NAME},
+        login::tests::{test_app_info, test_login, test_wx_login},
+    };
+
+    /// Redirect targets leaving the site.
+    pub(crate) const BAD_REDIRECTS: [&str; 7] = [
+        "https://evil.com",
+        "//evil.com",
+        "/\\evil.com",
+        "/\t/evil.com",
+        "/\x0b/evil.com",
+        "/%09/evil.com",
+        "/%5Cevil.com",
+    ];
+
+    #[test]
+    fn hand_off_session() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let handoff = Handoff::new("/handoff/mint", "/handoff/exchange");
+            let wx_login = test_wx_login(&app_info, |b| b.with_handoff(handoff.clone()));
+            let claims = Claims::new().with_role("admin");
+            let (_, login_info) =
+                test_login(&wx_login, &app_info, "some_openid", claims.clone()).await;
+
+            let ticket = wx_login
+                .mint_handoff_ticket(&handoff, &login_info)
+                .await
+                .unwrap();
+            let req = |redirect: &str| HandoffExchangeRequest {
+                ticket: ticket.ticket.clone(),
+                redirect: Some(redirect.into()),
+            };
+            for redirect in BAD_REDIRECTS {
+                let err = wx_login
+                    .exchange_handoff_ticket(&req(redirect))
+                    .await
+                    .unwrap_err();
+                assert_eq!(err.code, "handoff-redirect-invalid");
+            }
+            let other_flow = wx_login.config().ticket_store.clone();
+            let expires = SystemTime::now() + Duration::from_secs(60);
+            let scan_key = format!("scan:{}", ticket.ticket);
+            other_flow
+                .insert(&scan_key, "{}".into(), expires)
+                .await
+                .unwrap();
+            for other in [&scan_key, "component_verify_ticket:x"] {
+                let other_req = HandoffExchangeRequest {
+                    ticket: other.into(),
+                    redirect: None,
+                };
+                let err = wx_login
+                    .exchange_handoff_ticket(&other_req)
+                    .await
+                    .unwrap_err();
+                assert_eq!(err.code, "handoff-ticket-invalid");
+            }
+            assert!(other_flow.get(&scan_key).await.unwrap().is_some());
+            let exchange = wx_login.exchange_handoff_ticket(&req("/h5")).await.unwrap();
+            assert_eq!(exchange.openid, "some_openid");
+            assert_eq!(exchange.redirect.as_deref(), Some("/h5"));
+            let stoken =
+                cookie_value([exchange.set_cookies[0].as_str()], SESSION_COOKIE_NAME).unwrap();
+            let h5_info = wx_login.authenticate_cookie(stoken).await.unwrap();
+            assert_eq!(h5_info.openid, "some_openid");
+            assert_eq!(h5_info.claims, claims);
+
+            let err = wx_login
+                .exchange_handoff_ticket(&req("/h5"))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code, "handoff-ticket-invalid");
+        })
+    }
+}
@@ -1,10 +1,18 @@
-use crate::core::config::Config;
-use crate::core::security::Authority;
+use crate::core::access_token::{AccessTokenApi, AccessTokenManager};
+use crate::core::authz::Claims;
+use crate::core::config::{AppInfo, Config};
+use crate::core::reload::ConfigHandle;
+use crate::core::security::{random_bytes, Authority, ServerSession};
+use crate::core::session::Session;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use std::{fmt::Display, sync::Arc};
-use tiny_crypto::encoding::{Encoder, BASE64};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+use tiny_crypto::encoding::{Encoder, BASE64, HEX};
 
 /// The error type of Result used in this crate.
 pub use crate::core::security::Error;
@@ -12,12 +20,16 @@ pub use crate::core::security::Error;
 pub use crate::core::security::ServerSession as Secret;
 
 pub(crate) const LOGIN_FAIL_MSG: &str = "登录验证失败";
-#[allow(dead_code)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
 pub(crate) const AUTH_FAIL_MSG: &str = "登录会话验证失败";
+/// The tag of stateless stokens, whose session token carries claims and unionid.
+pub(crate) const STOKEN_TAG: &str = "ST2";
+/// The tag of stateless stokens issued before claims were added, still accepted.
+pub(crate) const STOKEN_TAG_V1: &str = "ST1";
 pub(crate) const WX_JSCODE2SESSION_URL: &str = "https://api.weixin.qq.com/sns/jscode2session";
 
 /// The login ok result.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct WxLoginOk {
     pub openid: String,
     pub stoken: String,
@@ -38,11 +50,18 @@ pub struct WxLoginErr {
 pub struct WxLoginInfoInner {
     pub appid: String,
     pub openid: String,
+    /// The unionid of the user, if the app is bound to an Open Platform account.
+    pub unionid: Option<String>,
+    /// The corpid of apps of WeCom.
+    pub corpid: Option<String>,
+    /// The userid of the member of the corp, if the app is of WeCom, which is kept as `openid` as well.
+    pub userid: Option<String>,
     pub secret: Secret,
+    pub claims: Claims,
     pub sig_authed: bool,
 }
 
-/// The authentication result for login status. 
+/// The authentication result for login status.
 #[derive(Debug, Clone)]
 pub struct WxLoginInfo(Arc<WxLoginInfoInner>);
 impl WxLoginInfo {
@@ -60,30 +79,71 @@ impl std::ops::Deref for WxLoginInfo {
 /// The core struct for login and authentication process.
 #[derive(Debug, Clone)]
 pub struct WxLogin {
-    cfg: Arc<Config>,
+    cfg: ConfigHandle,
+    /// The client with the timeout of `http_timeout_secs`, rebuilt when it changes.
+    http_client: Arc<Mutex<(u64, reqwest::Client)>>,
+    /// The token managers with the `http_timeout_secs` of their clients.
+    token_managers: Arc<Mutex<HashMap<String, TimedTokenManager>>>,
 }
 
 impl WxLogin {
     /// Create a new WxLogin with Config.
     pub fn new(cfg: Arc<Config>) -> Self {
-        Self { cfg }
+        Self::with_config_handle(ConfigHandle::new(Arc::unwrap_or_clone(cfg)))
+    }
+
+    /// Create a new WxLogin with a reloadable ConfigHandle.
+    pub fn with_config_handle(cfg: ConfigHandle) -> Self {
+        let timeout_secs = cfg.load().http_timeout_secs;
+        Self {
+            cfg,
+            http_client: Arc::new(Mutex::new((timeout_secs, new_http_client(timeout_secs)))),
+            token_managers: Default::default(),
+        }
+    }
+
+    /// Get the current Config.
+    pub fn config(&self) -> Arc<Config> {
+        self.cfg.load()
+    }
+
+    /// Get the client calling WeChat server APIs, with the timeout of `http_timeout_secs`.
+    pub(crate) fn http_client(&self) -> reqwest::Client {
+        let timeout_secs = self.config().http_timeout_secs;
+        let mut client = self.http_client.lock().unwrap();
+        if client.0 != timeout_secs {
+            *client = (timeout_secs, new_http_client(timeout_secs));
+        }
+        client.1.clone()
     }
 
     /// Handle login request.
     #[tracing::instrument(err(Debug), ret, skip_all)]
     pub async fn handle_login(&self, appid: String, code: String) -> Result<WxLoginOk, WxLoginErr> {
         tracing::info!("start handle_login({appid}, {code})");
-        let app_info = self.cfg.app_map.get(&appid).ok_or(WxLoginErr {
-            status: 401,
-            code: "appid-not-found".into(),
-            message: LOGIN_FAIL_MSG.into(),
-            detail: "".into(),
-        })?;
-        let client = reqwest::Client::new();
+        let cfg = self.config();
+        let app_info = cfg
+            .app_registry
+            .get_app(&appid)
+            .await
+            .map_err(err_resp(500, "app-registry-fail"))?
+            .ok_or(WxLoginErr {
+                status: 401,
+                code: "appid-not-found".into(),
+                message: LOGIN_FAIL_MSG.into(),
+                detail: "".into(),
+            })?;
+        if app_info.corpid.is_some() {
+            return self.handle_wecom_login(&app_info, &code).await;
+        }
+        if app_info.component_appid.is_some() {
+            return self.handle_component_login(&app_info, &code).await;
+        }
         let url = WX_JSCODE2SESSION_URL;
         let code2sess_req =
             proto::Code2SessionRequest::from(appid.clone(), app_info.secret.0.clone(), code);
-        let res = client
+        let res = self
+            .http_client()
             .get(url)
             .query(&code2sess_req)
             .send()
@@ -95,72 +155,292 @@ impl WxLogin {
             .map_err(err_resp(401, "jscode2session-resp-fail"))?;
         tracing::info!(?code2sess_res);
         let openid = code2sess_res.openid;
-        let session_key: [u8; 16] = BASE64
-            .from_text(&code2sess_res.session_key)
-            .map_err(err_resp(500, "session-key-invalid-base64"))?
-            .try_into()
-            .map_err(|v: Vec<u8>| format!("unexpected key len: {}", v.len()))
+        let session_key = decode_session_key(&code2sess_res.session_key)
             .map_err(err_resp(500, "session-key-invalid-base64"))?;
-        let authority = Authority::new(app_info);
-        let client_sess = authority.make_client_session(&openid, &session_key);
-        Ok(WxLoginOk {
-            openid: openid.clone(),
-            stoken: ["ST1".into(), appid, openid, client_sess.sess_token].join(":"),
-            skey: client_sess.sess_key,
-        })
+        let claims = self
+            .resolve_claims(&appid, &openid)
+            .await
+            .map_err(err_resp(500, "resolve-claims-fail"))?;
+        self.issue_session(
+            &app_info,
+            openid,
+            code2sess_res.unionid,
+            session_key,
+            claims,
+        )
+        .await
+    }
+
+    /// Issue a session token of the user, stateless by default or kept by the session store.
+    pub(crate) async fn issue_session(
+        &self,
+        app_info: &AppInfo,
+        openid: String,
+        unionid: Option<String>,
+        session_key: [u8; 16],
+        claims: Claims,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        match &self.config().session_store {
+            Some(store) => {
+                let sid = HEX.to_text(&random_bytes::<24>());
+                let client_sess_key = random_bytes::<16>();
+                let now = SystemTime::now();
+                let session = Session {
+                    appid: app_info.appid.clone(),
+                    openid: openid.clone(),
+                    unionid,
+                    session_key,
+                    client_sess_key,
+                    claims,
+                    created: now,
+                    last_seen: now,
+                };
+                store
+                    .insert(&sid, session)
+                    .await
+                    .map_err(err_resp(500, "session-store-fail"))?;
+                Ok(WxLoginOk {
+                    openid,
+                    stoken: ["SS1".into(), sid].join(":"),
+                    skey: BASE64.to_text(&client_sess_key),
+                })
+            }
+            None => {
+                let key_app_info = self
+                    .token_key_app_info(app_info)
+                    .map_err(err_resp(500, "component-not-found"))?;
+                let authority = Authority::new(&key_app_info);
+                let client_sess =
+                    authority.make_client_session(&openid, &session_key, claims, unionid);
+                Ok(WxLoginOk {
+                    openid: openid.clone(),
+                    stoken: [
+                        STOKEN_TAG.into(),
+                        app_info.appid.clone(),
+                        openid,
+                        client_sess.sess_token,
+                    ]
+                    .join(":"),
+                    skey: client_sess.sess_key,
+                })
+            }
+        }
     }
 
     /// Authenticate login status.
     #[tracing::instrument(err, ret, skip(self))]
-    pub fn authenticate(
+    pub async fn authenticate(
         &self,
         stoken: &str,
         uri: &str,
         sig: Result<&str, Error>,
     ) -> Result<WxLoginInfo, Error> {
-        let (tag, appid, openid, token_str) =
-            stoken.split(":").next_tuple().ok_or("bad stoken format")?;
-        if tag != "ST1" {
-            return Err(format!("bad stoken tag:{tag}").into());
-        }
-        let app_info = self.cfg.app_map.get(appid).ok_or("appid not found")?;
-        let authority = Authority::new(app_info);
-        let secret = authority.auth_client_session(openid, token_str)?;
-        let mut sig_authed = false;
-        if self.cfg.auth_sig {
+        let cfg = self.config();
+        let mut info = self.resolve_session(stoken).await?;
+        if cfg.auth_sig {
             let (tag, ts_ms_str, nonce_str, sig_str) =
                 sig?.split(":").next_tuple().ok_or("bad sig format")?;
             if tag != "SG1" {
                 return Err(format!("bad sig tag:{tag}").into());
             }
-            authority.auth_client_sig(
-                &BASE64.to_text(&secret.client_sess_key),
+            Authority::auth_client_sig(
+                &BASE64.to_text(&info.secret.client_sess_key),
                 uri,
                 ts_ms_str,
                 nonce_str,
                 sig_str,
-                |dur, _nonce| dur <= Duration::from_secs(self.cfg.sig_valid_secs),
+                |dur, _nonce| dur <= Duration::from_secs(cfg.sig_valid_secs),
             )?;
-            sig_authed = true;
+            info.sig_authed = true;
         }
-        Ok(WxLoginInfo::new(WxLoginInfoInner {
-            appid: appid.into(),
-            openid: openid.into(),
+        Ok(WxLoginInfo::new(info))
+    }
+
+    /// Authenticate login status by the stoken kept in a cookie, without signature.
+    #[tracing::instrument(err, ret, skip(self))]
+    pub(crate) async fn authenticate_cookie(&self, stoken: &str) -> Result<WxLoginInfo, Error> {
+        Ok(WxLoginInfo::new(self.resolve_session(stoken).await?))
+    }
+
+    /// Resolve the session of the stoken, checking its revocation, without signature.
+    async fn resolve_session(&self, stoken: &str) -> Result<WxLoginInfoInner, Error> {
+        let cfg = self.config();
+        let (tag, token_body) = stoken.split_once(':').ok_or("bad stoken format")?;
+        let (app_info, openid, unionid, secret, claims) = match (tag, &cfg.session_store) {
+            (STOKEN_TAG | STOKEN_TAG_V1, _) => {
+                let (appid, openid, token_str) = token_body
+                    .split(':')
+                    .next_tuple()
+                    .ok_or("bad stoken format")?;
+                let app_info = self.app_info(appid).await?;
+                let key_app_info = self.token_key_app_info(&app_info)?;
+                let authority = Authority::new(&key_app_info);
+                let (secret, claims, unionid) = match tag {
+                    STOKEN_TAG => authority.auth_client_session(openid, token_str)?,
+                    _ => authority.auth_client_session_v1(openid, token_str)?,
+                };
+                self.check_revoked(appid, openid, secret.client_sess_time)
+                    .await?;
+                (app_info, openid.to_string(), unionid, secret, claims)
+            }
+            ("SS1", Some(store)) => {
+                let session = store.get(token_body).await?.ok_or("session not found")?;
+                let app_info = self.app_info(&session.appid).await?;
+                self.check_revoked(&session.appid, &session.openid, session.created)
+                    .await?;
+                store.touch(token_body, SystemTime::now()).await?;
+                let secret = ServerSession {
+                    session_key: session.session_key,
+                    client_sess_key: session.client_sess_key,
+                    client_sess_time: session.created,
+                };
+                (
+                    app_info,
+                    session.openid,
+                    session.unionid,
+                    secret,
+                    session.claims,
+                )
+            }
+            _ => return Err(format!("bad stoken tag:{tag}").into()),
+        };
+        let userid = app_info.corpid.as_ref().map(|_| openid.clone());
+        Ok(WxLoginInfoInner {
+            appid: app_info.appid,
+            openid,
+            unionid,
+            corpid: app_info.corpid,
+            userid,
             secret,
-            sig_authed,
-        }))
+            claims,
+            sig_authed: false,
+        })
+    }
+
+    /// Get the shared access_token manager of the app.
+    ///
+    /// The manager is created on first use and recreated if the app secret,
+    /// the configured access_token API or cache changes. Apps of WeCom always use the corp access_token.
+    /// Apps authorized to a component are unsupported and get an error: they have no access_token
+    /// of their own, and their authorizer_access_token (`api_authorizer_token`) is not managed
+    /// by this crate. Use [component_access_token_manager](Self::component_access_token_manager)
+    /// to call APIs of the component instead.
+    pub async fn access_token_manager(
+        &self,
+        appid: &str,
+    ) -> Result<Arc<AccessTokenManager>, Error> {
+        let app_info = self.app_info(appid).await?;
+        if let Some(component_appid) = &app_info.component_appid {
+            return Err(format!(
+                "access_token of app {appid} authorized to component {component_appid} is unsupported"
+            )
+            .into());
+        }
+        let api = match app_info.corpid {
+            Some(_) => AccessTokenApi::WeCom,
+            None => self.config().access_token_api,
+        };
+        Ok(self.shared_token_manager(app_info, api))
+    }
+
+    /// Get the shared manager of the token of the app, recreated if its settings change.
+    pub(crate) fn shared_token_manager(
+        &self,
+        app_info: AppInfo,
+        api: AccessTokenApi,
+    ) -> Arc<AccessTokenManager> {
+        let cfg = self.config();
+        let http_client = self.http_client();
+        let mut managers = self.token_managers.lock().unwrap();
+        if let Some((timeout_secs, manager)) = managers.get(&app_info.appid) {
+            if *timeout_secs == cfg.http_timeout_secs
+                && manager.app_info().secret.0 == app_info.secret.0
+                && manager.app_info().corpid == app_info.corpid
+                && manager.api() == api
+                && Arc::ptr_eq(manager.cache(), &cfg.access_token_cache)
+            {
+                return manager.clone();
+            }
+        }
+        let appid = app_info.appid.clone();
+        let manager = Arc::new(
+            AccessTokenManager::new(app_info)
+                .with_api(api)
+                .with_cache(cfg.access_token_cache.clone())
+                .with_http_client(http_client),
+        );
+        managers.insert(appid, (cfg.http_timeout_secs, manager.clone()));
+        manager
+    }
+
+    pub(crate) async fn app_info(&self, appid: &str) -> Result<AppInfo, Error> {
+        self.config()
+            .app_registry
+            .get_app(appid)
+            .await?
+            .ok_or("appid not found".into())
     }
+
+    /// Resolve the claims of a user by the claims resolver, or the default claims without one.
+    pub(crate) async fn resolve_claims(&self, appid: &str, openid: &str) -> Result<Claims, Error> {
+        match &self.config().claims_resolver {
+            Some(resolver) => resolver.resolve(appid, openid).await,
+            None => Ok(Claims::default()),
+        }
+    }
+}
+
+type TimedTokenManager = (u64, Arc<AccessTokenManager>);
+
+pub(crate) fn new_http_client(timeout_secs: u64) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout_secs))
+        .build()
+        .expect("build http client fail")
 }
 
 fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> WxLoginErr {
+    err_resp_with(status, code, LOGIN_FAIL_MSG)
+}
+
+pub(crate) fn err_resp_with<'a, E: Display>(
+    status: u16,
+    code: &'a str,
+    message: &'a str,
+) -> impl 'a + FnOnce(E) -> WxLoginErr {
     move |e| WxLoginErr {
         status,
         code: code.into(),
-        message: LOGIN_FAIL_MSG.into(),
+        message: message.into(),
         detail: e.to_string(),
     }
 }
 
+/// The errors of a feature sharing one message, e.g. `SCAN_ERR.err(500, "ticket-store-fail", e)`.
+#[derive(Clone, Copy)]
+pub(crate) struct ErrWith(&'static str);
+
+pub(crate) const fn err_with(message: &'static str) -> ErrWith {
+    ErrWith(message)
+}
+
+impl ErrWith {
+    pub(crate) fn err(self, status: u16, code: &str, e: impl Display) -> WxLoginErr {
+        err_resp_with(status, code, self.0)(e)
+    }
+}
+
+pub(crate) const LOGIN_ERR: ErrWith = err_with(LOGIN_FAIL_MSG);
+
+/// Decode the base64 session_key returned by WeChat.
+pub(crate) fn decode_session_key(session_key: &str) -> Result<[u8; 16], Error> {
+    BASE64
+        .from_text(session_key)
+        .map_err(|e| format!("bad session_key: {e}"))?
+        .try_into()
+        .map_err(|v: Vec<u8>| format!("unexpected key len: {}", v.len()).into())
+}
+
 mod proto {
     use super::*;
 
@@ -187,6 +467,89 @@ mod proto {
     pub(crate) struct Code2SessionResponse {
         pub(crate) session_key: String,
         pub(crate) openid: String,
-        pub(crate) _unionid: Option<String>,
+        pub(crate) unionid: Option<String>,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::core::config::ConfigBuilder;
+    use std::time::UNIX_EPOCH;
+    use tiny_crypto::sha1_hex;
+
+    /// The app of test fixtures.
+    pub(crate) fn test_app_info() -> AppInfo {
+        AppInfo::from("some_appid".into(), "some_secret".into())
+    }
+
+    /// Create a WxLogin of the app, with other settings by `configure`.
+    pub(crate) fn test_wx_login(
+        app_info: &AppInfo,
+        configure: impl FnOnce(ConfigBuilder) -> ConfigBuilder,
+    ) -> WxLogin {
+        let cfg = configure(ConfigBuilder::new().with_app_info(app_info.clone())).build();
+        WxLogin::new(Arc::new(cfg))
+    }
+
+    /// Issue a session of the user with claims, and authenticate its stoken with a signature.
+    pub(crate) async fn test_login(
+        wx_login: &WxLogin,
+        app_info: &AppInfo,
+        openid: &str,
+        claims: Claims,
+    ) -> (WxLoginOk, WxLoginInfo) {
+        test_login_with(wx_login, app_info, openid, None, [1; 16], claims).await
+    }
+
+    /// Same as [test_login] with the unionid and session_key.
+    pub(crate) async fn test_login_with(
+        wx_login: &WxLogin,
+        app_info: &AppInfo,
+        openid: &str,
+        unionid: Option<&str>,
+        session_key: [u8; 16],
+        claims: Claims,
+    ) -> (WxLoginOk, WxLoginInfo) {
+        let unionid = unionid.map(Into::into);
+        let ok = wx_login
+            .issue_session(app_info, openid.into(), unionid, session_key, claims)
+            .await
+            .unwrap();
+        let (uri, nonce) = ("/some/api", "1");
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let digest = sha1_hex!(format!("{uri}:{ts_ms}:{nonce}:{}", ok.skey).as_bytes());
+        let sig = format!("SG1:{ts_ms}:{nonce}:{digest}");
+        let login_info = wx_login
+            .authenticate(&ok.stoken, uri, Ok(&sig))
+            .await
+            .unwrap();
+        (ok, login_info)
+    }
+
+    #[test]
+    fn authenticate_baseline_stoken() {
+        // issued by the first release for ("some_appid", "some_secret"), whose session token
+        // is {seed, ts, session_key, tag} without claims or unionid
+        let stoken = "ST1:some_appid:some_openid:AvxYHehxVrtX7EVtOyCqtXQpKSh2GHADJURkG/PsY/o=";
+        let skey = "tQsX0b8C5E2jpxzttcnZXg==";
+        let wx_login = test_wx_login(&test_app_info(), |builder| builder);
+        tokio_test::block_on(async {
+            let login_info = wx_login.authenticate_cookie(stoken).await.unwrap();
+            assert_eq!(login_info.openid, "some_openid");
+            assert_eq!(
+                BASE64.to_text(&login_info.secret.session_key),
+                "HyVFkGl5F5OQWJZZaNzBBg=="
+            );
+            assert_eq!(BASE64.to_text(&login_info.secret.client_sess_key), skey);
+            assert_eq!(login_info.claims, Claims::default());
+            assert_eq!(login_info.unionid, None);
+            assert_eq!(login_info.userid, None);
+            let st2 = stoken.replacen("ST1", STOKEN_TAG, 1);
+            assert!(wx_login.authenticate_cookie(&st2).await.is_err());
+        })
     }
 }
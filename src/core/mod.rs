@@ -1,3 +1,29 @@
+pub(crate) mod access_token;
+pub(crate) mod api;
+pub(crate) mod authz;
+pub(crate) mod component;
 pub(crate) mod config;
+pub(crate) mod connection;
+pub(crate) mod cookie;
+pub(crate) mod credential;
+pub(crate) mod handoff;
 pub(crate) mod login;
-pub(crate) mod security;
\ No newline at end of file
+pub(crate) mod oauth;
+pub(crate) mod open_data;
+pub(crate) mod phone;
+pub(crate) mod push;
+pub(crate) mod registry;
+pub(crate) mod reload;
+pub(crate) mod revocation;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) mod route;
+pub(crate) mod scan_login;
+pub(crate) mod sec_check;
+pub(crate) mod security;
+pub(crate) mod session;
+pub(crate) mod session_key;
+pub(crate) mod subscribe;
+pub(crate) mod ticket;
+pub(crate) mod token_cache;
+pub(crate) mod wecom;
+pub(crate) mod wxacode;
@@ -0,0 +1,596 @@
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use std::time::{Duration, SystemTime};
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    api::{get_json, WX_API_BASE_URL},
+    cookie::cookie_value,
+    handoff::is_local_path,
+    login::{err_with, ErrWith, WxLogin, WxLoginErr, WxLoginOk},
+    security::random_bytes,
+    ticket::{is_ticket, new_ticket, ticket_key},
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const OAUTH_FAIL_MSG: &str = "微信授权登录失败";
+
+/// The name of the cookie binding the OAuth2 state to the browser.
+pub const OAUTH_STATE_COOKIE_NAME: &str = "wx_login_oauth_state";
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const OA_AUTHORIZE_URL: &str = "https://open.weixin.qq.com/connect/oauth2/authorize";
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const OPEN_QRCONNECT_URL: &str = "https://open.weixin.qq.com/connect/qrconnect";
+
+/// The scope of the web authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthScope {
+    /// `snsapi_base`, silent authorization getting the openid only.
+    Base,
+    /// `snsapi_userinfo`, asking the user for the profile, see [OAuthUserInfo].
+    UserInfo,
+    /// `snsapi_login`, the QR login of website apps of the Open Platform, with the profile.
+    Login,
+}
+
+impl OAuthScope {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Base => "snsapi_base",
+            Self::UserInfo => "snsapi_userinfo",
+            Self::Login => "snsapi_login",
+        }
+    }
+}
+
+/// The profile of the user fetched by `sns/userinfo`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct OAuthUserInfo {
+    pub openid: String,
+    pub nickname: String,
+    pub sex: u8,
+    pub province: String,
+    pub city: String,
+    pub country: String,
+    pub headimgurl: String,
+    pub privilege: Vec<String>,
+    pub unionid: Option<String>,
+}
+
+/// The user authorized by OAuth2, before the login session is issued.
+#[derive(Debug, Clone)]
+pub struct OAuthUser {
+    pub appid: String,
+    pub openid: String,
+    pub unionid: Option<String>,
+    /// The scopes granted by the user, separated by `,`.
+    pub scope: String,
+    /// The profile, fetched if `snsapi_userinfo` is granted.
+    pub user_info: Option<OAuthUserInfo>,
+}
+
+/// A handler of users authorized by OAuth2, e.g. to save the profile.
+///
+/// An error returned by the handler fails the login.
+#[async_trait]
+pub trait OAuthUserHandler: Send + Sync + Debug {
+    async fn handle(&self, user: &OAuthUser) -> Result<(), Error>;
+}
+
+/// The endpoints of the web authorization (OAuth2) of an official account, for H5 pages opened in WeChat,
+/// e.g. from the menu of the official account, or of a website app of the Open Platform,
+/// for "Log in with WeChat" of desktop websites by scanning a QR code.
+///
+/// - The page is opened at (or redirected to) the path, with an optional `redirect` (a path of the same site),
+///   which redirects to `connect/oauth2/authorize` (or `connect/qrconnect` for website apps) with a new state,
+///   bound to the browser by a cookie.
+/// - WeChat redirects back to the callback URL with `code` and `state`, which are checked and exchanged
+///   at `sns/oauth2/access_token`. The profile is fetched by `sns/userinfo` for `snsapi_userinfo`
+///   and `snsapi_login`.
+/// - The login session of the openid is issued like mini-programs, kept in cookies of the cookie session mode
+///   and redirected to `redirect` if present, otherwise [WxLoginOk] is returned.
+///
+/// The appid and secret of the official account (or the website app) are from the app registry,
+/// and states are kept by the ticket store. The unionid is kept in the session if present,
+/// linking the user to mini-programs of the same Open Platform account.
+#[derive(Debug, Clone)]
+pub struct OAuthLogin {
+    pub(crate) kind: OAuthKind,
+    pub(crate) appid: String,
+    pub(crate) path: String,
+    pub(crate) callback_url: String,
+    pub(crate) scope: OAuthScope,
+    pub(crate) state_ttl_secs: u64,
+    pub(crate) user_handler: Option<Arc<dyn OAuthUserHandler>>,
+}
+
+impl OAuthLogin {
+    /// Create the endpoints of the official account at the path, and the callback URL
+    /// (under the authorized domain of the official account), whose path is handled as the callback endpoint.
+    pub fn new(appid: &str, path: &str, callback_url: &str) -> Self {
+        Self {
+            kind: OAuthKind::OfficialAccount,
+            appid: appid.into(),
+            path: path.into(),
+            callback_url: callback_url.into(),
+            scope: OAuthScope::Base,
+            state_ttl_secs: 300,
+            user_handler: None,
+        }
+    }
+    /// Create the endpoints of the website app of the Open Platform at the path, and the callback URL
+    /// (under the authorized domain of the website app), with the scope `snsapi_login`.
+    pub fn website(appid: &str, path: &str, callback_url: &str) -> Self {
+        Self {
+            kind: OAuthKind::Website,
+            scope: OAuthScope::Login,
+            ..Self::new(appid, path, callback_url)
+        }
+    }
+    /// Set the scope, the default is `snsapi_base` for official accounts and `snsapi_login` for website apps.
+    pub fn with_scope(mut self, scope: OAuthScope) -> Self {
+        self.scope = scope;
+        self
+    }
+    /// Set the valid period of states, the default is 300 seconds.
+    pub fn with_state_ttl_secs(mut self, secs: u64) -> Self {
+        self.state_ttl_secs = secs;
+        self
+    }
+    /// Set the handler of authorized users.
+    pub fn with_user_handler(mut self, handler: Arc<dyn OAuthUserHandler>) -> Self {
+        self.user_handler = Some(handler);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.appid.is_empty() {
+            Err("empty appid of OAuth login")?;
+        }
+        if !self.path.starts_with('/') {
+            Err(format!(
+                "OAuth login path {:?} is not started with '/'",
+                self.path
+            ))?;
+        }
+        match self.callback_path() {
+            Some(path) if path != self.path => {}
+            _ => Err(format!(
+                "bad OAuth callback URL {:?}, which must be an absolute URL of another path",
+                self.callback_url
+            ))?,
+        }
+        match (self.kind, self.scope) {
+            (OAuthKind::OfficialAccount, OAuthScope::Base | OAuthScope::UserInfo) => {}
+            (OAuthKind::Website, OAuthScope::Login) => {}
+            (kind, scope) => Err(format!("scope {scope:?} is not supported by {kind:?}"))?,
+        }
+        if self.state_ttl_secs == 0 {
+            Err("state_ttl_secs of OAuth login is 0")?;
+        }
+        Ok(())
+    }
+
+    /// Get the path of the callback URL.
+    pub(crate) fn callback_path(&self) -> Option<&str> {
+        let (scheme, rest) = self.callback_url.split_once("://")?;
+        if !["http", "https"].contains(&scheme) {
+            return None;
+        }
+        let path = &rest[rest.find('/')?..];
+        Some(path.split(['?', '#']).next().unwrap_or(path))
+    }
+
+    /// Get the operation of the request path, if it is one of the endpoints.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn op(&self, path: &str) -> Option<OAuthOp> {
+        if path == self.path {
+            Some(OAuthOp::Authorize)
+        } else if Some(path) == self.callback_path() {
+            Some(OAuthOp::Callback)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn authorize_url(&self, state: &str) -> String {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("appid", &self.appid)
+            .append_pair("redirect_uri", &self.callback_url)
+            .append_pair("response_type", "code")
+            .append_pair("scope", self.scope.as_str())
+            .append_pair("state", state)
+            .finish();
+        let url = match self.kind {
+            OAuthKind::OfficialAccount => OA_AUTHORIZE_URL,
+            OAuthKind::Website => OPEN_QRCONNECT_URL,
+        };
+        format!("{url}?{query}#wechat_redirect")
+    }
+}
+
+/// The kind of apps authorizing users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OAuthKind {
+    OfficialAccount,
+    Website,
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OAuthOp {
+    Authorize,
+    Callback,
+}
+
+/// The params of the endpoints.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct OAuthRequest {
+    pub(crate) redirect: Option<String>,
+    pub(crate) code: Option<String>,
+    pub(crate) state: Option<String>,
+}
+
+/// The result of the endpoints, redirecting or returning the login result, with cookies.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug)]
+pub(crate) struct OAuthResponse {
+    pub(crate) set_cookies: Vec<String>,
+    pub(crate) redirect: Option<String>,
+    pub(crate) login: Option<WxLoginOk>,
+}
+
+/// The state kept by the ticket store.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Serialize, Deserialize, Debug)]
+struct OAuthState {
+    appid: String,
+    redirect: Option<String>,
+}
+
+/// The namespace of OAuth states in the ticket store.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const OAUTH_STATES: &str = "oauth";
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const OAUTH_ERR: ErrWith = err_with(OAUTH_FAIL_MSG);
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Handle the requests of the OAuth2 endpoints, with the `Cookie` headers of the request.
+    pub(crate) async fn handle_oauth(
+        &self,
+        oauth: &OAuthLogin,
+        op: OAuthOp,
+        req: &OAuthRequest,
+        cookies: &[&str],
+    ) -> Result<OAuthResponse, WxLoginErr> {
+        match op {
+            OAuthOp::Authorize => self.oauth_authorize(oauth, req).await,
+            OAuthOp::Callback => self.oauth_callback(oauth, req, cookies).await,
+        }
+    }
+
+    async fn oauth_authorize(
+        &self,
+        oauth: &OAuthLogin,
+        req: &OAuthRequest,
+    ) -> Result<OAuthResponse, WxLoginErr> {
+        if let Some(redirect) = &req.redirect {
+            if !is_local_path(redirect) {
+                return Err(OAUTH_ERR.err(400, "oauth-redirect-invalid", redirect));
+            }
+        }
+        let state = new_ticket();
+        let value = serde_json::to_string(&OAuthState {
+            appid: oauth.appid.clone(),
+            redirect: req.redirect.clone(),
+        })
+        .map_err(|e| OAUTH_ERR.err(500, "ticket-store-fail", e))?;
+        let expires = SystemTime::now() + Duration::from_secs(oauth.state_ttl_secs);
+        self.config()
+            .ticket_store
+            .insert(&ticket_key(OAUTH_STATES, &state), value, expires)
+            .await
+            .map_err(|e| OAUTH_ERR.err(500, "ticket-store-fail", e))?;
+        Ok(OAuthResponse {
+            set_cookies: vec![self.oauth_state_cookie(&state, oauth.state_ttl_secs)],
+            redirect: Some(oauth.authorize_url(&state)),
+            login: None,
+        })
+    }
+
+    async fn oauth_callback(
+        &self,
+        oauth: &OAuthLogin,
+        req: &OAuthRequest,
+        cookies: &[&str],
+    ) -> Result<OAuthResponse, WxLoginErr> {
+        let state = req
+            .state
+            .as_deref()
+            .filter(|state| is_ticket(state))
+            .ok_or_else(|| OAUTH_ERR.err(400, "oauth-state-invalid", "no valid state"))?;
+        if cookie_value(cookies.iter().copied(), OAUTH_STATE_COOKIE_NAME) != Some(state) {
+            return Err(OAUTH_ERR.err(403, "oauth-state-mismatch", state));
+        }
+        let value = self
+            .config()
+            .ticket_store
+            .take(&ticket_key(OAUTH_STATES, state))
+            .await
+            .map_err(|e| OAUTH_ERR.err(500, "ticket-store-fail", e))?
+            .ok_or_else(|| OAUTH_ERR.err(400, "oauth-state-invalid", state))?;
+        let state: OAuthState =
+            serde_json::from_str(&value).map_err(|e| OAUTH_ERR.err(500, "ticket-store-fail", e))?;
+        if state.appid != oauth.appid {
+            return Err(OAUTH_ERR.err(400, "oauth-state-invalid", &state.appid));
+        }
+        let code = req
+            .code
+            .as_deref()
+            .filter(|code| !code.is_empty())
+            .ok_or_else(|| OAUTH_ERR.err(401, "oauth-denied", "no code"))?;
+        let app_info = self
+            .app_info(&oauth.appid)
+            .await
+            .map_err(|e| OAUTH_ERR.err(401, "appid-not-found", e))?;
+        let token: proto::AccessTokenResponse = get_json(
+            &self.http_client(),
+            &format!("{WX_API_BASE_URL}/sns/oauth2/access_token"),
+            &[
+                ("appid", oauth.appid.as_str()),
+                ("secret", app_info.secret.0.as_str()),
+                ("code", code),
+                ("grant_type", "authorization_code"),
+            ],
+        )
+        .await
+        .map_err(|e| OAUTH_ERR.err(401, "oauth-code-exchange-fail", e))?;
+        let with_user_info = token
+            .scope
+            .split(',')
+            .any(|s| s == "snsapi_userinfo" || s == "snsapi_login");
+        let user_info = match with_user_info {
+            true => Some(
+                get_json::<_, OAuthUserInfo>(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/sns/userinfo"),
+                    &[
+                        ("access_token", token.access_token.as_str()),
+                        ("openid", token.openid.as_str()),
+                        ("lang", "zh_CN"),
+                    ],
+                )
+                .await
+                .map_err(|e| OAUTH_ERR.err(500, "oauth-userinfo-fail", e))?,
+            ),
+            false => None,
+        };
+        let user = OAuthUser {
+            appid: oauth.appid.clone(),
+            unionid: token
+                .unionid
+                .or_else(|| user_info.as_ref()?.unionid.clone()),
+            openid: token.openid,
+            scope: token.scope,
+            user_info,
+        };
+        if let Some(handler) = &oauth.user_handler {
+            handler
+                .handle(&user)
+                .await
+                .map_err(|e| OAUTH_ERR.err(500, "oauth-user-handler-fail", e))?;
+        }
+        let claims = self
+            .resolve_claims(&user.appid, &user.openid)
+            .await
+            .map_err(|e| OAUTH_ERR.err(500, "resolve-claims-fail", e))?;
+        // there is no session_key for web authorization, so a random one is kept in the session
+        let ok = self
+            .issue_session(
+                &app_info,
+                user.openid,
+                user.unionid,
+                random_bytes::<16>(),
+                claims,
+            )
+            .await?;
+        tracing::info!(appid = oauth.appid, openid = ok.openid, "OAuth login ok");
+        let mut set_cookies = self
+            .cookie_session()
+            .unwrap_or_default()
+            .set_cookies(&ok.stoken);
+        set_cookies.push(self.oauth_state_cookie("", 0));
+        Ok(OAuthResponse {
+            set_cookies,
+            redirect: state.redirect,
+            login: Some(ok),
+        })
+    }
+
+    /// Make the `Set-Cookie` header of the state cookie, which is SameSite=Lax to be sent
+    /// when WeChat redirects back.
+    fn oauth_state_cookie(&self, state: &str, max_age_secs: u64) -> String {
+        let secure = match self.cookie_session().unwrap_or_default().secure {
+            true => "; Secure",
+            false => "",
+        };
+        format!(
+            "{OAUTH_STATE_COOKIE_NAME}={state}; Path=/; Max-Age={max_age_secs}; HttpOnly{secure}; SameSite=Lax"
+        )
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod proto {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    pub(crate) struct AccessTokenResponse {
+        pub(crate) access_token: String,
+        pub(crate) openid: String,
+        #[serde(default)]
+        pub(crate) scope: String,
+        pub(crate) unionid: Option<String>,
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use crate::core::{
+        api::parse_response,
+        handoff::tests::BAD_REDIRECTS,
+        login::tests::{test_app_info, test_login_with, test_wx_login},
+    };
+
+    #[test]
+    fn oauth_endpoints() {
+        let oauth = OAuthLogin::new("some_appid", "/oauth", "https://example.com/oauth/cb?x=1")
+            .with_scope(OAuthScope::UserInfo);
+        assert!(oauth.validate().is_ok());
+        assert_eq!(oauth.op("/oauth"), Some(OAuthOp::Authorize));
+        assert_eq!(oauth.op("/oauth/cb"), Some(OAuthOp::Callback));
+        assert_eq!(oauth.op("/other"), None);
+        assert_eq!(
+            oauth.authorize_url("abc"),
+            "https://open.weixin.qq.com/connect/oauth2/authorize?appid=some_appid\
+             &redirect_uri=https%3A%2F%2Fexample.com%2Foauth%2Fcb%3Fx%3D1&response_type=code\
+             &scope=snsapi_userinfo&state=abc#wechat_redirect"
+        );
+        assert!(OAuthLogin::new("some_appid", "/oauth", "/oauth/cb")
+            .validate()
+            .is_err());
+        let website = OAuthLogin::website("web_appid", "/qr", "https://example.com/qr/cb");
+        assert!(website.validate().is_ok());
+        assert_eq!(
+            website.authorize_url("abc"),
+            "https://open.weixin.qq.com/connect/qrconnect?appid=web_appid\
+             &redirect_uri=https%3A%2F%2Fexample.com%2Fqr%2Fcb&response_type=code\
+             &scope=snsapi_login&state=abc#wechat_redirect"
+        );
+        assert!(website.with_scope(OAuthScope::Base).validate().is_err());
+        assert!(
+            OAuthLogin::new("some_appid", "/oauth", "https://example.com/oauth")
+                .validate()
+                .is_err()
+        );
+
+        let res: proto::AccessTokenResponse = parse_response(
+            br#"{"access_token":"AT","expires_in":7200,"refresh_token":"RT","openid":"OPENID","scope":"snsapi_userinfo","is_snapshotuser":0}"#,
+        )
+        .unwrap();
+        assert_eq!(res.openid, "OPENID");
+        assert_eq!(res.unionid, None);
+        let info: OAuthUserInfo = parse_response(
+            br#"{"openid":"OPENID","nickname":"NICK","sex":1,"headimgurl":"http://x/0","privilege":[],"unionid":"UNIONID"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.unionid.as_deref(), Some("UNIONID"));
+    }
+
+    #[test]
+    fn check_oauth_state() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let oauth = OAuthLogin::new("some_appid", "/oauth", "https://example.com/oauth/cb");
+            let wx_login = test_wx_login(&app_info, |b| b.with_oauth_login(oauth.clone()));
+            for redirect in BAD_REDIRECTS {
+                let req = OAuthRequest {
+                    redirect: Some(redirect.into()),
+                    ..Default::default()
+                };
+                let err = wx_login
+                    .handle_oauth(&oauth, OAuthOp::Authorize, &req, &[])
+                    .await
+                    .unwrap_err();
+                assert_eq!(err.code, "oauth-redirect-invalid");
+            }
+            let req = OAuthRequest {
+                redirect: Some("/h5".into()),
+                ..Default::default()
+            };
+            let resp = wx_login
+                .handle_oauth(&oauth, OAuthOp::Authorize, &req, &[])
+                .await
+                .unwrap();
+            let state_cookie = resp.set_cookies[0].split_once(';').unwrap().0;
+            let state = state_cookie.split_once('=').unwrap().1;
+            assert!(resp.redirect.unwrap().contains(&format!("state={state}")));
+
+            let callback = |code: Option<&str>, state: &str| OAuthRequest {
+                redirect: None,
+                code: code.map(Into::into),
+                state: Some(state.into()),
+            };
+            let err = wx_login
+                .handle_oauth(&oauth, OAuthOp::Callback, &callback(Some("c"), state), &[])
+                .await
+                .unwrap_err();
+            assert_eq!(err.code, "oauth-state-mismatch");
+            let store = wx_login.config().ticket_store.clone();
+            let expires = SystemTime::now() + Duration::from_secs(60);
+            let handoff_key = format!("handoff:{state}");
+            store
+                .insert(&handoff_key, "{}".into(), expires)
+                .await
+                .unwrap();
+            for other in [handoff_key.as_str(), "component_verify_ticket:x"] {
+                let cookie = format!("{OAUTH_STATE_COOKIE_NAME}={other}");
+                let err = wx_login
+                    .handle_oauth(
+                        &oauth,
+                        OAuthOp::Callback,
+                        &callback(Some("c"), other),
+                        &[&cookie],
+                    )
+                    .await
+                    .unwrap_err();
+                assert_eq!(err.code, "oauth-state-invalid");
+            }
+            assert!(store.get(&handoff_key).await.unwrap().is_some());
+            let err = wx_login
+                .handle_oauth(
+                    &oauth,
+                    OAuthOp::Callback,
+                    &callback(None, state),
+                    &[state_cookie],
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(err.code, "oauth-denied");
+            let err = wx_login
+                .handle_oauth(
+                    &oauth,
+                    OAuthOp::Callback,
+                    &callback(Some("c"), state),
+                    &[state_cookie],
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(err.code, "oauth-state-invalid");
+
+            let (_, login_info) = test_login_with(
+                &wx_login,
+                &app_info,
+                "some_openid",
+                Some("some_unionid"),
+                [1; 16],
+                Default::default(),
+            )
+            .await;
+            assert_eq!(login_info.unionid.as_deref(), Some("some_unionid"));
+        })
+    }
+}
@@ -0,0 +1,476 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tiny_crypto::encoding::{Encoder, BASE64};
+
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    config::Config,
+    login::{err_resp_with, WxLogin, WxLoginErr, WxLoginInfo},
+    security::check_signature,
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const OPEN_DATA_FAIL_MSG: &str = "开放数据校验失败";
+
+const AES_BLOCK_SIZE: usize = 16;
+
+/// The error of decrypting open data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenDataError {
+    /// The encryptedData or iv is not valid base64.
+    BadBase64(String),
+    /// The iv is not 16 bytes.
+    BadIv,
+    /// The encrypted data is empty or not a multiple of the AES block size.
+    BadLength(usize),
+    /// The PKCS#7 padding of the decrypted data is malformed, usually caused by a wrong session_key.
+    BadPadding,
+    /// The decrypted data is not UTF-8.
+    BadUtf8(String),
+    /// The decrypted data is not the expected JSON.
+    BadJson(String),
+    /// The data is encrypted for another app.
+    AppidMismatch { expected: String, actual: String },
+    /// The data is encrypted longer than the max age ago.
+    Expired { timestamp: u64 },
+    /// The data is encrypted later than now, beyond the allowed clock skew.
+    FromFuture { timestamp: u64 },
+}
+impl std::fmt::Display for OpenDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenDataError::BadBase64(err) => write!(f, "bad base64: {err}"),
+            OpenDataError::BadIv => write!(f, "iv is not 16B len"),
+            OpenDataError::BadLength(len) => write!(f, "bad encrypted data len: {len}"),
+            OpenDataError::BadPadding => write!(f, "bad padding of decrypted data"),
+            OpenDataError::BadUtf8(err) => write!(f, "decrypted data is not utf-8: {err}"),
+            OpenDataError::BadJson(err) => write!(f, "decrypted data is bad json: {err}"),
+            OpenDataError::AppidMismatch { expected, actual } => {
+                write!(f, "watermark appid {actual} is not {expected}")
+            }
+            OpenDataError::Expired { timestamp } => {
+                write!(f, "watermark timestamp {timestamp} is expired")
+            }
+            OpenDataError::FromFuture { timestamp } => {
+                write!(f, "watermark timestamp {timestamp} is in the future")
+            }
+        }
+    }
+}
+impl std::error::Error for OpenDataError {}
+impl From<OpenDataError> for Error {
+    fn from(err: OpenDataError) -> Self {
+        Error::from(err.to_string())
+    }
+}
+
+/// The watermark of data returned or encrypted by WeChat, telling which app it is for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Watermark {
+    pub appid: String,
+    pub timestamp: u64,
+}
+
+/// The open data encrypted by WeChat, which carries a [Watermark].
+pub trait OpenData: DeserializeOwned {
+    fn watermark(&self) -> &Watermark;
+}
+
+/// The user info from `wx.getUserInfo`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    #[serde(default)]
+    pub open_id: Option<String>,
+    #[serde(default)]
+    pub union_id: Option<String>,
+    pub nick_name: String,
+    #[serde(default)]
+    pub gender: u8,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub city: String,
+    #[serde(default)]
+    pub province: String,
+    #[serde(default)]
+    pub country: String,
+    #[serde(default)]
+    pub avatar_url: String,
+    pub watermark: Watermark,
+}
+
+/// The group info from `wx.getShareInfo` or `wx.getGroupEnterInfo`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShareInfo {
+    #[serde(rename = "openGId", alias = "opengid", default)]
+    pub open_gid: Option<String>,
+    #[serde(rename = "chatType", default)]
+    pub chat_type: Option<u8>,
+    pub watermark: Watermark,
+}
+
+/// The step counts of the last 30 days from `wx.getWeRunData`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WeRunData {
+    pub step_info_list: Vec<StepInfo>,
+    pub watermark: Watermark,
+}
+
+/// The step count of a day.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StepInfo {
+    pub timestamp: u64,
+    pub step: u32,
+}
+
+macro_rules! impl_open_data {
+    ($($t:ty),*) => {
+        $(impl OpenData for $t {
+            fn watermark(&self) -> &Watermark {
+                &self.watermark
+            }
+        })*
+    };
+}
+impl_open_data!(UserInfo, ShareInfo, WeRunData);
+
+/// Decrypt open data and check its watermark.
+///
+/// The data must be encrypted for `appid` within `max_age`.
+pub fn decrypt_open_data<T: OpenData>(
+    encrypted_data_base64: &str,
+    iv_base64: &str,
+    session_key: &[u8; 16],
+    appid: &str,
+    max_age: Duration,
+) -> Result<T, OpenDataError> {
+    let decrypted = decrypt_to_string(encrypted_data_base64, iv_base64, session_key)?;
+    let data: T =
+        serde_json::from_str(&decrypted).map_err(|e| OpenDataError::BadJson(e.to_string()))?;
+    check_watermark(data.watermark(), appid, max_age, SystemTime::now())?;
+    Ok(data)
+}
+
+/// An extractor of open data posted by the logged-in user, decrypted with the session_key
+/// of the session and checked by watermark.
+///
+/// The request body is JSON with fields `encryptedData` and `iv` returned by WeChat APIs,
+/// and optionally `rawData` and `signature` which are verified if present. The max age of
+/// the data is set by [ConfigBuilder::with_open_data_max_age_secs](crate::wx_login::ConfigBuilder::with_open_data_max_age_secs).
+#[derive(Debug, Clone)]
+pub struct WxOpenData<T>(pub T);
+impl<T> std::ops::Deref for WxOpenData<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The request body of [WxOpenData].
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OpenDataRequest {
+    pub(crate) encrypted_data: String,
+    pub(crate) iv: String,
+    pub(crate) raw_data: Option<String>,
+    pub(crate) signature: Option<String>,
+}
+
+impl<T: OpenData> WxOpenData<T> {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn from_request(
+        login_info: &WxLoginInfo,
+        req: &OpenDataRequest,
+        max_age: Duration,
+    ) -> Result<Self, WxLoginErr> {
+        let session_key = &login_info.secret.session_key;
+        match (&req.raw_data, &req.signature) {
+            (Some(raw_data), Some(signature)) => {
+                if !check_signature(signature, raw_data, session_key) {
+                    return Err(
+                        err_resp_with(400, "open-data-sig-fail", OPEN_DATA_FAIL_MSG)(
+                            "bad signature of rawData",
+                        ),
+                    );
+                }
+            }
+            (None, None) => {}
+            _ => {
+                return Err(
+                    err_resp_with(400, "open-data-sig-fail", OPEN_DATA_FAIL_MSG)(
+                        "rawData and signature must be present together",
+                    ),
+                )
+            }
+        }
+        decrypt_open_data(
+            &req.encrypted_data,
+            &req.iv,
+            session_key,
+            &login_info.appid,
+            max_age,
+        )
+        .map(WxOpenData)
+        .map_err(open_data_err)
+    }
+}
+
+/// Map the error of decrypting open data to the error response, 403 if the watermark is not valid.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn open_data_err(e: OpenDataError) -> WxLoginErr {
+    match e {
+        OpenDataError::AppidMismatch { .. }
+        | OpenDataError::Expired { .. }
+        | OpenDataError::FromFuture { .. } => {
+            err_resp_with(403, "open-data-watermark-fail", OPEN_DATA_FAIL_MSG)(e)
+        }
+        _ => err_resp_with(400, "open-data-decrypt-fail", OPEN_DATA_FAIL_MSG)(e),
+    }
+}
+
+/// Get the max age of open data from the config of the middleware.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn open_data_max_age(wx_login: Option<&WxLogin>) -> Duration {
+    let secs = wx_login
+        .map(|wx_login| wx_login.config().open_data_max_age_secs)
+        .unwrap_or(Config::default().open_data_max_age_secs);
+    Duration::from_secs(secs)
+}
+
+/// Decrypt encrypted data returned from WeChat server.
+pub fn decrpyt_data(
+    encrypted_data_base64: &str,
+    iv_base64: &str,
+    session_key: &[u8; 16],
+) -> Result<String, Error> {
+    Ok(decrypt_to_string(
+        encrypted_data_base64,
+        iv_base64,
+        session_key,
+    )?)
+}
+
+/// Decrypt open data to string without checking its content.
+pub(crate) fn decrypt_to_string(
+    encrypted_data_base64: &str,
+    iv_base64: &str,
+    session_key: &[u8; 16],
+) -> Result<String, OpenDataError> {
+    let encrypted = BASE64
+        .from_text(encrypted_data_base64)
+        .map_err(|e| OpenDataError::BadBase64(e.to_string()))?;
+    let iv: [u8; 16] = BASE64
+        .from_text(iv_base64)
+        .map_err(|e| OpenDataError::BadBase64(e.to_string()))?
+        .try_into()
+        .or(Err(OpenDataError::BadIv))?;
+    if encrypted.is_empty() || encrypted.len() % AES_BLOCK_SIZE != 0 {
+        return Err(OpenDataError::BadLength(encrypted.len()));
+    }
+    let mut cipher = libaes::Cipher::new_128(session_key);
+    cipher.set_auto_padding(false);
+    let mut decrypted = cipher.cbc_decrypt(&iv, &encrypted);
+    let len = unpad_pkcs7(&decrypted, AES_BLOCK_SIZE).ok_or(OpenDataError::BadPadding)?;
+    decrypted.truncate(len);
+    String::from_utf8(decrypted).map_err(|e| OpenDataError::BadUtf8(e.to_string()))
+}
+
+/// Validate the PKCS#7 padding and return the length of data without padding.
+pub(crate) fn unpad_pkcs7(data: &[u8], block_size: usize) -> Option<usize> {
+    let pad = *data.last()? as usize;
+    if pad == 0 || pad > block_size || pad > data.len() {
+        return None;
+    }
+    let (content, padding) = data.split_at(data.len() - pad);
+    padding
+        .iter()
+        .all(|&b| b as usize == pad)
+        .then_some(content.len())
+}
+
+/// The allowed clock skew in seconds between WeChat server and this server.
+const WATERMARK_SKEW_SECS: u64 = 60;
+
+fn check_watermark(
+    watermark: &Watermark,
+    appid: &str,
+    max_age: Duration,
+    now: SystemTime,
+) -> Result<(), OpenDataError> {
+    if watermark.appid != appid {
+        return Err(OpenDataError::AppidMismatch {
+            expected: appid.into(),
+            actual: watermark.appid.clone(),
+        });
+    }
+    let encrypted_at = UNIX_EPOCH + Duration::from_secs(watermark.timestamp);
+    if encrypted_at > now + Duration::from_secs(WATERMARK_SKEW_SECS) {
+        return Err(OpenDataError::FromFuture {
+            timestamp: watermark.timestamp,
+        });
+    }
+    if now.duration_since(encrypted_at).unwrap_or_default() > max_age {
+        return Err(OpenDataError::Expired {
+            timestamp: watermark.timestamp,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION_KEY: &str = "/rwbJA677wrIqaPPLIzwSg==";
+    const ENCRYPTED: &str = "CfmlE917TYmWSMDAJ3MZLJTc1ZdTS5S/XUDnf785IlA+4IR80ABSTj+eGqIbqEshNZCAxkid3LnY6VJipJVZN0OeUqWykj0lVFpH7F39jY1a+CkpSwWwMTlCN6Bc57AX/a9phKunccXSLM7X0Nw2VPLxqlRsUrSYfXN5oZpGHbJRVbDsw95mw59N9jPpTY01EhAJZGtKE+W/YOWTXWPQ6IkhRx9WSJxVuVK0nCXvIqQw6zQuSesCurokvMcPWMArKBubLY9vznZ5MUfj51Mptx6UUQoizHbtyNVKEeotMPup6cqh7axP/Y6ae/6Yb7XQW1mEF6SrxzK0C1RgAI2F9JfbKY8Ubl3hlXNydrgHoP+9j/C7aRIRpeWeCUeSOOIqoZzuwN/CYolLIhkjK1POeg==";
+    const IV: &str = "J3IBEDAC0mBW1nQK5F1jFQ==";
+
+    fn session_key() -> [u8; 16] {
+        BASE64.from_text(SESSION_KEY).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn decrypt_data_test() {
+        let decrypted = decrpyt_data(ENCRYPTED, IV, &session_key()).unwrap();
+        let plain = r#"{"nickName":"韦彬","gender":0,"language":"zh_CN","city":"","province":"","country":"","avatarUrl":"https://thirdwx.qlogo.cn/mmopen/vi_32/DYAIOgq83eq9ld3vawfuoLSHlN39xryF4Tdpsz5fBGfdeiarQkVKxvCnjrsVlmWU59KYJd7vvaKhNgPfREQ9iang/132","watermark":{"timestamp":1708708886,"appid":"wx25581781a863c770"}}"#;
+        assert_eq!(decrypted, plain);
+    }
+    #[test]
+    fn decrypt_and_check_watermark() {
+        let timestamp = 1708708886;
+        let max_age = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(timestamp))
+            .unwrap()
+            + Duration::from_secs(60);
+        let user_info: UserInfo =
+            decrypt_open_data(ENCRYPTED, IV, &session_key(), "wx25581781a863c770", max_age)
+                .unwrap();
+        assert_eq!(user_info.nick_name, "韦彬");
+        assert_eq!(user_info.watermark.timestamp, timestamp);
+
+        let err = decrypt_open_data::<UserInfo>(ENCRYPTED, IV, &session_key(), "other", max_age);
+        assert!(matches!(err, Err(OpenDataError::AppidMismatch { .. })));
+        let err = decrypt_open_data::<UserInfo>(
+            ENCRYPTED,
+            IV,
+            &session_key(),
+            "wx25581781a863c770",
+            Duration::from_secs(60),
+        );
+        assert_eq!(err, Err(OpenDataError::Expired { timestamp }));
+        let watermark = Watermark {
+            appid: "some_appid".into(),
+            timestamp,
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(timestamp);
+        let skew = Duration::from_secs(WATERMARK_SKEW_SECS);
+        assert!(check_watermark(&watermark, "some_appid", max_age, now - skew).is_ok());
+        assert_eq!(
+            check_watermark(
+                &watermark,
+                "some_appid",
+                max_age,
+                now - skew - Duration::from_secs(1)
+            ),
+            Err(OpenDataError::FromFuture { timestamp })
+        );
+        let err = decrypt_open_data::<WeRunData>(
+            ENCRYPTED,
+            IV,
+            &session_key(),
+            "wx25581781a863c770",
+            max_age,
+        );
+        assert!(matches!(err, Err(OpenDataError::BadJson(_))));
+        let err =
+            decrypt_open_data::<UserInfo>(ENCRYPTED, IV, &[0; 16], "wx25581781a863c770", max_age);
+        assert!(matches!(
+            err,
+            Err(OpenDataError::BadPadding | OpenDataError::BadUtf8(_))
+        ));
+        let err = decrypt_open_data::<UserInfo>("AAAA", IV, &session_key(), "appid", max_age);
+        assert_eq!(err, Err(OpenDataError::BadLength(3)));
+    }
+
+    #[test]
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    fn open_data_from_request() {
+        use crate::core::{
+            config::AppInfo,
+            login::tests::{test_login_with, test_wx_login},
+        };
+        use tiny_crypto::sha1_hex;
+
+        let app_info = AppInfo::from("wx25581781a863c770".into(), "some_secret".into());
+        let wx_login = test_wx_login(&app_info, |b| b);
+        let (_, login_info) = tokio_test::block_on(test_login_with(
+            &wx_login,
+            &app_info,
+            "some_openid",
+            None,
+            session_key(),
+            Default::default(),
+        ));
+        let raw_data = r#"{"nickName":"韦彬"}"#;
+        let mut req = OpenDataRequest {
+            encrypted_data: ENCRYPTED.into(),
+            iv: IV.into(),
+            raw_data: Some(raw_data.into()),
+            signature: Some(sha1_hex!(raw_data.as_bytes(), SESSION_KEY.as_bytes())),
+        };
+        let max_age = Duration::from_secs(u32::MAX.into());
+        let user_info = WxOpenData::<UserInfo>::from_request(&login_info, &req, max_age).unwrap();
+        assert_eq!(user_info.nick_name, "韦彬");
+
+        req.signature = Some("bad_signature".into());
+        let err = WxOpenData::<UserInfo>::from_request(&login_info, &req, max_age).unwrap_err();
+        assert_eq!(err.code, "open-data-sig-fail");
+        req.signature = None;
+        let err = WxOpenData::<UserInfo>::from_request(&login_info, &req, max_age).unwrap_err();
+        assert_eq!(err.code, "open-data-sig-fail");
+        req.raw_data = None;
+        let err =
+            WxOpenData::<UserInfo>::from_request(&login_info, &req, Duration::ZERO).unwrap_err();
+        assert_eq!(
+            (err.status, err.code.as_str()),
+            (403, "open-data-watermark-fail")
+        );
+        let watermark_errs = [
+            OpenDataError::AppidMismatch {
+                expected: "some_appid".into(),
+                actual: "other_appid".into(),
+            },
+            OpenDataError::Expired { timestamp: 0 },
+            OpenDataError::FromFuture {
+                timestamp: u64::MAX,
+            },
+        ];
+        for e in watermark_errs {
+            let err = open_data_err(e);
+            assert_eq!(
+                (err.status, err.code.as_str()),
+                (403, "open-data-watermark-fail")
+            );
+        }
+        let err = open_data_err(OpenDataError::BadPadding);
+        assert_eq!(
+            (err.status, err.code.as_str()),
+            (400, "open-data-decrypt-fail")
+        );
+    }
+
+    #[test]
+    fn validate_pkcs7_padding() {
+        assert_eq!(unpad_pkcs7(b"abc\x01", 16), Some(3));
+        assert_eq!(unpad_pkcs7(b"a\x03\x03\x03", 16), Some(1));
+        assert_eq!(unpad_pkcs7(b"a\x02\x03\x03", 16), None);
+        assert_eq!(unpad_pkcs7(b"abc\x00", 16), None);
+        assert_eq!(unpad_pkcs7(b"abc\x11", 16), None);
+        assert_eq!(unpad_pkcs7(b"", 16), None);
+    }
+}
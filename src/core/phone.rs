@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    api::{post_json, WX_API_BASE_URL},
+    login::{err_resp_with, WxLogin, WxLoginErr, WxLoginInfo},
+    open_data::{OpenData, Watermark},
+};
+
+pub(crate) const PHONE_FAIL_MSG: &str = "获取手机号失败";
+
+/// The errcode of an invalid or used getPhoneNumber code.
+const ERRCODE_INVALID_CODE: i64 = 40029;
+
+/// The phone number of a user.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PhoneInfo {
+    /// The phone number with country code for foreign numbers.
+    pub phone_number: String,
+    /// The phone number without country code.
+    pub pure_phone_number: String,
+    pub country_code: String,
+    pub watermark: Watermark,
+}
+impl OpenData for PhoneInfo {
+    fn watermark(&self) -> &Watermark {
+        &self.watermark
+    }
+}
+
+impl WxLogin {
+    /// Exchange the `code` from the `getPhoneNumber` button of the logged-in user for the phone number.
+    ///
+    /// It calls `wxa/business/getuserphonenumber` with the access_token of the session's app,
+    /// and checks that the phone number is issued for the same app.
+    #[tracing::instrument(err(Debug), skip(self, login_info), fields(appid = login_info.appid))]
+    pub async fn get_phone_number(
+        &self,
+        login_info: &WxLoginInfo,
+        code: &str,
+    ) -> Result<PhoneInfo, WxLoginErr> {
+        let manager = self
+            .access_token_manager(&login_info.appid)
+            .await
+            .map_err(err_resp_with(500, "access-token-fail", PHONE_FAIL_MSG))?;
+        let res: proto::PhoneNumberResponse = manager
+            .call(|token| async move {
+                post_json(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/wxa/business/getuserphonenumber"),
+                    &[("access_token", token)],
+                    &proto::PhoneNumberRequest { code },
+                )
+                .await
+            })
+            .await
+            .map_err(|e| match e.errcode() {
+                Some(ERRCODE_INVALID_CODE) => {
+                    err_resp_with(400, "phone-code-invalid", PHONE_FAIL_MSG)(e)
+                }
+                _ => err_resp_with(500, "phone-number-call-fail", PHONE_FAIL_MSG)(e),
+            })?;
+        check_phone_info(&login_info.appid, res.phone_info)
+    }
+}
+
+fn check_phone_info(appid: &str, phone_info: PhoneInfo) -> Result<PhoneInfo, WxLoginErr> {
+    if phone_info.watermark.appid != appid {
+        return Err(err_resp_with(403, "phone-appid-mismatch", PHONE_FAIL_MSG)(
+            format!(
+                "watermark appid {} is not {appid}",
+                phone_info.watermark.appid
+            ),
+        ));
+    }
+    Ok(phone_info)
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub(crate) struct PhoneNumberRequest<'a> {
+        pub(crate) code: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct PhoneNumberResponse {
+        pub(crate) phone_info: PhoneInfo,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::api::parse_response;
+
+    #[test]
+    fn parse_and_check_phone_info() {
+        let body = br#"{
+            "errcode": 0,
+            "errmsg": "ok",
+            "phone_info": {
+                "phoneNumber": "+86 13800138000",
+                "purePhoneNumber": "13800138000",
+                "countryCode": "86",
+                "watermark": {"timestamp": 1637744274, "appid": "some_appid"}
+            }
+        }"#;
+        let res: proto::PhoneNumberResponse = parse_response(body).unwrap();
+        let phone_info = check_phone_info("some_appid", res.phone_info.clone()).unwrap();
+        assert_eq!(phone_info.pure_phone_number, "13800138000");
+        assert_eq!(phone_info.watermark.timestamp, 1637744274);
+        let err = check_phone_info("other_appid", res.phone_info).unwrap_err();
+        assert_eq!(err.status, 403);
+        assert_eq!(err.code, "phone-appid-mismatch");
+    }
+}
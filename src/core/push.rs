@@ -0,0 +1,581 @@
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tiny_crypto::encoding::{Encoder, BASE64};
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use tiny_crypto::sha1_hex;
+
+use crate::core::{login::Error, sec_check::SecCheckSuggest, security::secret_utils::SecretString};
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    login::{err_resp_with, WxLogin, WxLoginErr},
+    open_data::unpad_pkcs7,
+    security::constant_time_eq,
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const PUSH_FAIL_MSG: &str = "消息推送处理失败";
+
+/// The max difference in seconds between the push timestamp and now, to reject replays.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const PUSH_TS_SKEW_SECS: u64 = 300;
+
+/// The block size of PKCS#7 padding used by safe-mode messages.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const PUSH_PAD_BLOCK_SIZE: usize = 32;
+
+/// The handler of messages pushed by WeChat server.
+///
+/// Handlers are called one by one for every message, and WeChat retries the push later
+/// if any of them returns error.
+#[async_trait]
+pub trait PushHandler: Send + Sync + Debug {
+    async fn handle(&self, msg: &PushMessage) -> Result<(), Error>;
+}
+
+/// The endpoint receiving messages pushed by WeChat server for an app.
+///
+/// The path, token and EncodingAESKey must be the same as the message push settings of the app
+/// on the WeChat console, and the data format must be JSON. If the EncodingAESKey is set,
+/// messages in safe mode are decrypted and checked.
+#[derive(Debug, Clone)]
+pub struct PushEndpoint {
+    pub(crate) path: String,
+    pub(crate) appid: String,
+    pub(crate) token: SecretString,
+    pub(crate) encoding_aes_key: Option<SecretString>,
+    pub(crate) handlers: Vec<Arc<dyn PushHandler>>,
+    /// Whether it receives the authorization events of a component in XML, see [Component](crate::wx_login::Component).
+    pub(crate) component: bool,
+}
+
+impl PushEndpoint {
+    /// Create a PushEndpoint of the app at the path with the token.
+    pub fn new(path: &str, appid: &str, token: &str) -> Self {
+        Self {
+            path: path.into(),
+            appid: appid.into(),
+            token: SecretString(token.into()),
+            encoding_aes_key: None,
+            handlers: vec![],
+            component: false,
+        }
+    }
+    /// Set the EncodingAESKey to decrypt messages in safe mode.
+    pub fn with_encoding_aes_key(mut self, key: &str) -> Self {
+        self.encoding_aes_key = Some(SecretString(key.into()));
+        self
+    }
+    /// Add a handler of pushed messages.
+    pub fn with_handler(mut self, handler: Arc<dyn PushHandler>) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !self.path.starts_with('/') {
+            Err(format!("push path {:?} is not started with '/'", self.path))?;
+        }
+        if self.appid.is_empty() || self.token.0.is_empty() {
+            Err(format!("empty appid or token of push path {}", self.path))?;
+        }
+        if self.encoding_aes_key.is_some() {
+            self.aes_key()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn aes_key(&self) -> Result<Option<[u8; 32]>, Error> {
+        self.encoding_aes_key
+            .as_ref()
+            .map(|key| {
+                BASE64
+                    .from_text(&format!("{}=", key.0))
+                    .ok()
+                    .and_then(|key| key.try_into().ok())
+                    .ok_or(Error::from("EncodingAESKey is not 43 chars base64"))
+            })
+            .transpose()
+    }
+
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn sign(&self, parts: &mut [&str]) -> String {
+        parts.sort_unstable();
+        sha1_hex!(parts.concat().as_bytes())
+    }
+
+    /// Decrypt a safe-mode message, which is random(16B) + msg_len(4B) + msg + appid.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn decrypt(&self, key: &[u8; 32], encrypted_base64: &str) -> Result<String, Error> {
+        let encrypted = BASE64
+            .from_text(encrypted_base64)
+            .map_err(|e| format!("bad base64: {e}"))?;
+        if encrypted.is_empty() || encrypted.len() % 16 != 0 {
+            Err(format!("bad encrypted len: {}", encrypted.len()))?;
+        }
+        let mut cipher = libaes::Cipher::new_256(key);
+        cipher.set_auto_padding(false);
+        let decrypted = cipher.cbc_decrypt(&key[..16], &encrypted);
+        let len = unpad_pkcs7(&decrypted, PUSH_PAD_BLOCK_SIZE).ok_or("bad padding")?;
+        let content = decrypted.get(16..len).ok_or("bad content len")?;
+        let (msg_len, content) = content.split_at_checked(4).ok_or("bad content len")?;
+        let msg_len = u32::from_be_bytes(msg_len.try_into().unwrap()) as usize;
+        let (msg, appid) = content.split_at_checked(msg_len).ok_or("bad msg len")?;
+        if appid != self.appid.as_bytes() {
+            Err(format!(
+                "appid {} is not {}",
+                String::from_utf8_lossy(appid),
+                self.appid
+            ))?;
+        }
+        String::from_utf8(msg.into()).map_err(|e| e.to_string().into())
+    }
+}
+
+/// The query params of push requests.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug)]
+pub(crate) struct PushQuery {
+    pub(crate) signature: String,
+    pub(crate) timestamp: String,
+    pub(crate) nonce: String,
+    pub(crate) echostr: Option<String>,
+    pub(crate) msg_signature: Option<String>,
+}
+
+/// A message pushed by WeChat server.
+#[derive(Debug, Clone)]
+pub struct PushMessage {
+    /// The original ID of the mini-program.
+    pub to_user_name: String,
+    /// The openid of the user, or a system account.
+    pub from_user_name: String,
+    pub create_time: u64,
+    pub msg_type: String,
+    pub event: PushEvent,
+    /// The whole message for fields not covered by [PushEvent].
+    pub raw: Value,
+}
+
+/// The typed event of a [PushMessage].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushEvent {
+    /// `subscribe_msg_popup_event`: the user accepted or rejected subscribe messages in a popup.
+    SubscribeMsgPopup(Vec<SubscribeMsgStatus>),
+    /// `subscribe_msg_change_event`: the user changed subscriptions in settings.
+    SubscribeMsgChange(Vec<SubscribeMsgStatus>),
+    /// `subscribe_msg_sent_event`: the result of sending subscribe messages.
+    SubscribeMsgSent(Vec<SubscribeMsgSentResult>),
+    /// `wxa_media_check`: the asynchronous result of media content security check.
+    MediaCheck(MediaCheckResult),
+    /// `user_authorization_revoke`: the user revoked the authorization of the mini-program.
+    UserAuthorizationRevoke {
+        appid: String,
+        openid: String,
+        revoke_info: String,
+    },
+    /// `user_authorization_cancellation`: the user cancelled the WeChat account.
+    UserAuthorizationCancellation { appid: String, openid: String },
+    /// Other events or messages, with the event name or message type.
+    Other(String),
+}
+
+/// The subscription status of a template.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SubscribeMsgStatus {
+    pub template_id: String,
+    /// "accept" or "reject".
+    pub subscribe_status_string: String,
+    #[serde(default)]
+    pub popup_scene: Option<String>,
+}
+
+/// The result of sending a subscribe message.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SubscribeMsgSentResult {
+    pub template_id: String,
+    #[serde(rename = "MsgID")]
+    pub msg_id: String,
+    #[serde(deserialize_with = "number_or_string")]
+    pub error_code: i64,
+    pub error_status: String,
+}
+
+/// The result of media content security check.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MediaCheckResult {
+    pub appid: String,
+    pub trace_id: String,
+    pub result: SecCheckSuggest,
+}
+
+impl PushMessage {
+    /// Parse a message in JSON format.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn parse(msg: &str) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Header {
+            to_user_name: String,
+            from_user_name: String,
+            create_time: u64,
+            msg_type: String,
+            #[serde(default)]
+            event: String,
+        }
+        #[derive(Deserialize)]
+        #[serde(bound(deserialize = "T: Deserialize<'de>"))]
+        struct List<T> {
+            #[serde(rename = "List", deserialize_with = "one_or_many")]
+            list: Vec<T>,
+        }
+        #[derive(Deserialize)]
+        struct Revoke {
+            #[serde(rename = "AppID")]
+            appid: String,
+            #[serde(rename = "OpenID")]
+            openid: String,
+            #[serde(rename = "RevokeInfo", default)]
+            revoke_info: String,
+        }
+
+        let raw: Value = serde_json::from_str(msg).map_err(|e| format!("bad json: {e}"))?;
+        let header = Header::deserialize(&raw).map_err(|e| format!("bad message: {e}"))?;
+        let event = match (header.msg_type.as_str(), header.event.as_str()) {
+            ("event", "subscribe_msg_popup_event") => {
+                PushEvent::SubscribeMsgPopup(List::deserialize(&raw).map_err(bad_event)?.list)
+            }
+            ("event", "subscribe_msg_change_event") => {
+                PushEvent::SubscribeMsgChange(List::deserialize(&raw).map_err(bad_event)?.list)
+            }
+            ("event", "subscribe_msg_sent_event") => {
+                PushEvent::SubscribeMsgSent(List::deserialize(&raw).map_err(bad_event)?.list)
+            }
+            ("event", "wxa_media_check") => {
+                PushEvent::MediaCheck(MediaCheckResult::deserialize(&raw).map_err(bad_event)?)
+            }
+            ("event", "user_authorization_revoke") => {
+                let revoke = Revoke::deserialize(&raw).map_err(bad_event)?;
+                PushEvent::UserAuthorizationRevoke {
+                    appid: revoke.appid,
+                    openid: revoke.openid,
+                    revoke_info: revoke.revoke_info,
+                }
+            }
+            ("event", "user_authorization_cancellation") => {
+                let revoke = Revoke::deserialize(&raw).map_err(bad_event)?;
+                PushEvent::UserAuthorizationCancellation {
+                    appid: revoke.appid,
+                    openid: revoke.openid,
+                }
+            }
+            ("event", event) => PushEvent::Other(event.into()),
+            (msg_type, _) => PushEvent::Other(msg_type.into()),
+        };
+        Ok(Self {
+            to_user_name: header.to_user_name,
+            from_user_name: header.from_user_name,
+            create_time: header.create_time,
+            msg_type: header.msg_type,
+            event,
+            raw,
+        })
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn bad_event(e: serde_json::Error) -> Error {
+    format!("bad event: {e}").into()
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn one_or_many<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Vec<T>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+fn number_or_string<'de, D: Deserializer<'de>>(d: D) -> Result<i64, D::Error> {
+    match Value::deserialize(d)? {
+        Value::Number(n) => n.as_i64().ok_or(serde::de::Error::custom("bad number")),
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+        _ => Err(serde::de::Error::custom("expect number or string")),
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Handle a request to the push endpoint, returning the response body.
+    ///
+    /// A GET request is the verification of the endpoint and echoes `echostr`,
+    /// a POST request carries a message which is dispatched to handlers.
+    pub(crate) async fn handle_push(
+        &self,
+        endpoint: &PushEndpoint,
+        query: &PushQuery,
+        body: Option<&[u8]>,
+    ) -> Result<String, WxLoginErr> {
+        let sig_fail = err_resp_with(401, "push-sig-fail", PUSH_FAIL_MSG);
+        let signature = endpoint.sign(&mut [&endpoint.token.0, &query.timestamp, &query.nonce]);
+        if !constant_time_eq(signature.as_bytes(), query.signature.as_bytes()) {
+            return Err(sig_fail("bad signature"));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match query.timestamp.parse::<u64>() {
+            Ok(ts) if ts.abs_diff(now) <= PUSH_TS_SKEW_SECS => {}
+            _ => return Err(sig_fail("timestamp out of window")),
+        }
+        let body = match body {
+            Some(body) => body,
+            None => return Ok(query.echostr.clone().unwrap_or_default()),
+        };
+        if endpoint.component {
+            return self.handle_component_push(endpoint, query, body).await;
+        }
+        #[derive(Deserialize)]
+        struct Encrypted {
+            #[serde(rename = "Encrypt")]
+            encrypt: String,
+        }
+        let encrypted = serde_json::from_slice::<Encrypted>(body).ok();
+        let msg = match (endpoint.aes_key(), encrypted) {
+            (Ok(Some(key)), Some(Encrypted { encrypt })) => {
+                let msg_signature = endpoint.sign(&mut [
+                    &endpoint.token.0,
+                    &query.timestamp,
+                    &query.nonce,
+                    &encrypt,
+                ]);
+                if !query
+                    .msg_signature
+                    .as_ref()
+                    .is_some_and(|sig| constant_time_eq(sig.as_bytes(), msg_signature.as_bytes()))
+                {
+                    return Err(sig_fail("bad msg_signature"));
+                }
+                endpoint.decrypt(&key, &encrypt).map_err(err_resp_with(
+                    400,
+                    "push-decrypt-fail",
+                    PUSH_FAIL_MSG,
+                ))?
+            }
+            (Ok(None), None) => String::from_utf8(body.into()).map_err(err_resp_with(
+                400,
+                "push-parse-fail",
+                PUSH_FAIL_MSG,
+            ))?,
+            (Ok(None), Some(_)) => {
+                return Err(err_resp_with(400, "push-decrypt-fail", PUSH_FAIL_MSG)(
+                    "no EncodingAESKey for encrypted message",
+                ))
+            }
+            (Ok(Some(_)), None) => {
+                return Err(err_resp_with(400, "push-decrypt-fail", PUSH_FAIL_MSG)(
+                    "message is not encrypted in safe mode",
+                ))
+            }
+            (Err(e), _) => return Err(err_resp_with(500, "push-config-fail", PUSH_FAIL_MSG)(e)),
+        };
+        let msg = PushMessage::parse(&msg).map_err(err_resp_with(
+            400,
+            "push-parse-fail",
+            PUSH_FAIL_MSG,
+        ))?;
+        tracing::info!(appid = endpoint.appid, ?msg.event, "push message received");
+        self.revoke_on_push(endpoint, &msg.event)
+            .await
+            .map_err(err_resp_with(500, "push-revoke-fail", PUSH_FAIL_MSG))?;
+        let mut result = Ok(());
+        for handler in &endpoint.handlers {
+            if let Err(e) = handler.handle(&msg).await {
+                tracing::error!(
+                    appid = endpoint.appid,
+                    ?handler,
+                    "handle push message fail: {e}"
+                );
+                result = Err(err_resp_with(500, "push-handler-fail", PUSH_FAIL_MSG)(e));
+            }
+        }
+        result.map(|_| "success".into())
+    }
+
+    /// Revoke sessions of the user who revoked the authorization of the app or cancelled the account.
+    async fn revoke_on_push(
+        &self,
+        endpoint: &PushEndpoint,
+        event: &PushEvent,
+    ) -> Result<(), Error> {
+        let (appid, openid, reason) = match event {
+            PushEvent::UserAuthorizationRevoke { appid, openid, .. } => {
+                (appid, openid, "user_authorization_revoke")
+            }
+            PushEvent::UserAuthorizationCancellation { appid, openid } => {
+                (appid, openid, "user_authorization_cancellation")
+            }
+            _ => return Ok(()),
+        };
+        if *appid != endpoint.appid {
+            tracing::warn!(
+                appid = endpoint.appid,
+                "ignore {reason} of another app {appid}"
+            );
+            return Ok(());
+        }
+        let cfg = self.config();
+        if cfg.revocation_store.is_none() && cfg.session_store.is_none() {
+            tracing::warn!(appid, openid, "no revocation mechanism for {reason}");
+            return Ok(());
+        }
+        self.revoke_user_sessions(appid, openid, reason).await
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<PushEvent>>);
+
+    #[async_trait]
+    impl PushHandler for Recorder {
+        async fn handle(&self, msg: &PushMessage) -> Result<(), Error> {
+            self.0.lock().unwrap().push(msg.event.clone());
+            Ok(())
+        }
+    }
+
+    pub(crate) fn encrypt(key: &[u8; 32], msg: &str, appid: &str) -> String {
+        let mut plain = vec![7u8; 16];
+        plain.extend((msg.len() as u32).to_be_bytes());
+        plain.extend(msg.as_bytes());
+        plain.extend(appid.as_bytes());
+        let pad = PUSH_PAD_BLOCK_SIZE - plain.len() % PUSH_PAD_BLOCK_SIZE;
+        plain.resize(plain.len() + pad, pad as u8);
+        let mut cipher = libaes::Cipher::new_256(key);
+        cipher.set_auto_padding(false);
+        BASE64.to_text(&cipher.cbc_encrypt(&key[..16], &plain))
+    }
+
+    fn now_secs() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    pub(crate) fn now_timestamp() -> String {
+        now_secs().to_string()
+    }
+
+    #[test]
+    fn verify_decrypt_and_dispatch() {
+        let key = [5u8; 32];
+        let encoding_aes_key = BASE64.to_text(&key).trim_end_matches('=').to_string();
+        let recorder = Arc::new(Recorder::default());
+        let endpoint = PushEndpoint::new("/push", "some_appid", "some_token")
+            .with_encoding_aes_key(&encoding_aes_key)
+            .with_handler(recorder.clone());
+        endpoint.validate().unwrap();
+        let wx_login = WxLogin::new(Default::default());
+        let ts = now_timestamp();
+        let query = |encrypt: Option<&str>| PushQuery {
+            signature: endpoint.sign(&mut ["some_token", &ts, "nonce"]),
+            timestamp: ts.clone(),
+            nonce: "nonce".into(),
+            echostr: Some("echo".into()),
+            msg_signature: encrypt
+                .map(|encrypt| endpoint.sign(&mut ["some_token", &ts, "nonce", encrypt])),
+        };
+
+        tokio_test::block_on(async {
+            let res = wx_login.handle_push(&endpoint, &query(None), None).await;
+            assert_eq!(res.unwrap(), "echo");
+            let mut bad_query = query(None);
+            bad_query.nonce = "other".into();
+            let err = wx_login.handle_push(&endpoint, &bad_query, None).await;
+            assert_eq!(err.unwrap_err().code, "push-sig-fail");
+            for ts in [
+                now_secs() - PUSH_TS_SKEW_SECS - 10,
+                now_secs() + PUSH_TS_SKEW_SECS + 10,
+            ] {
+                let ts = ts.to_string();
+                let stale_query = PushQuery {
+                    signature: endpoint.sign(&mut ["some_token", &ts, "nonce"]),
+                    timestamp: ts,
+                    ..query(None)
+                };
+                let err = wx_login.handle_push(&endpoint, &stale_query, None).await;
+                assert_eq!(err.unwrap_err().code, "push-sig-fail");
+            }
+
+            let msg = r#"{"ToUserName":"gh_123","FromUserName":"some_openid","CreateTime":1700000000,"MsgType":"event","Event":"user_authorization_revoke","OpenID":"some_openid","AppID":"some_appid","RevokeInfo":"1"}"#;
+            let encrypted = encrypt(&key, msg, "some_appid");
+            let body = format!(r#"{{"ToUserName":"gh_123","Encrypt":"{encrypted}"}}"#);
+            let res = wx_login
+                .handle_push(&endpoint, &query(Some(&encrypted)), Some(body.as_bytes()))
+                .await;
+            assert_eq!(res.unwrap(), "success");
+            assert_eq!(
+                recorder.0.lock().unwrap().pop(),
+                Some(PushEvent::UserAuthorizationRevoke {
+                    appid: "some_appid".into(),
+                    openid: "some_openid".into(),
+                    revoke_info: "1".into(),
+                })
+            );
+
+            let encrypted = encrypt(&key, msg, "other_appid");
+            let body = format!(r#"{{"ToUserName":"gh_123","Encrypt":"{encrypted}"}}"#);
+            let err = wx_login
+                .handle_push(&endpoint, &query(Some(&encrypted)), Some(body.as_bytes()))
+                .await;
+            assert_eq!(err.unwrap_err().code, "push-decrypt-fail");
+        });
+    }
+
+    #[test]
+    fn parse_events() {
+        let msg = PushMessage::parse(
+            r#"{"ToUserName":"gh_123","FromUserName":"some_openid","CreateTime":1700000000,"MsgType":"event","Event":"subscribe_msg_popup_event","List":{"TemplateId":"tpl","SubscribeStatusString":"accept","PopupScene":"0"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            msg.event,
+            PushEvent::SubscribeMsgPopup(vec![SubscribeMsgStatus {
+                template_id: "tpl".into(),
+                subscribe_status_string: "accept".into(),
+                popup_scene: Some("0".into()),
+            }])
+        );
+        let msg = PushMessage::parse(
+            r#"{"ToUserName":"gh_123","FromUserName":"some_openid","CreateTime":1700000000,"MsgType":"event","Event":"subscribe_msg_sent_event","List":[{"TemplateId":"tpl","MsgID":"1","ErrorCode":"0","ErrorStatus":"success"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(&msg.event, PushEvent::SubscribeMsgSent(list) if list[0].error_code == 0));
+        let msg = PushMessage::parse(
+            r#"{"ToUserName":"gh_123","FromUserName":"some_openid","CreateTime":1700000000,"MsgType":"text","Content":"hi"}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.event, PushEvent::Other("text".into()));
+        assert_eq!(msg.raw["Content"], "hi");
+    }
+}
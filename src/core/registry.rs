@@ -0,0 +1,217 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::core::{config::AppInfo, login::Error};
+
+/// The registry to look up [AppInfo] by appid on login and authentication.
+///
+/// Implement it to load apps dynamically (e.g. from a database), and wrap it with
+/// [CachedAppRegistry] to avoid a lookup for every request.
+#[async_trait]
+pub trait AppRegistry: Send + Sync + Debug {
+    /// Get the app-info of appid, `Ok(None)` means the app does not exist.
+    async fn get_app(&self, appid: &str) -> Result<Option<AppInfo>, Error>;
+    /// List all apps if the registry is static, to report changed apps on config reload.
+    ///
+    /// The default implementation returns `None`.
+    fn apps(&self) -> Option<Vec<AppInfo>> {
+        None
+    }
+}
+
+/// The default [AppRegistry] holding a static map of apps.
+#[derive(Debug, Default, Clone)]
+pub struct StaticAppRegistry {
+    apps: BTreeMap<String, AppInfo>,
+}
+impl StaticAppRegistry {
+    /// Create a StaticAppRegistry from the map of appid to app-info.
+    pub fn new(apps: HashMap<String, AppInfo>) -> Self {
+        Self {
+            apps: apps.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl AppRegistry for StaticAppRegistry {
+    async fn get_app(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        Ok(self.apps.get(appid).cloned())
+    }
+    fn apps(&self) -> Option<Vec<AppInfo>> {
+        Some(self.apps.values().cloned().collect())
+    }
+}
+
+/// An [AppRegistry] wrapper caching the lookup results of the inner registry.
+///
+/// Found apps are cached for `ttl` (default 300 seconds) and not found ones are cached
+/// for `negative_ttl` (default 30 seconds). Errors are never cached.
+///
+/// Concurrent misses of the same appid share one lookup of the inner registry, and expired
+/// results are evicted at most once per the longer TTL.
+#[derive(Debug)]
+pub struct CachedAppRegistry<R> {
+    inner: R,
+    ttl: Duration,
+    negative_ttl: Duration,
+    cache: Mutex<AppCache>,
+    lookups: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+#[derive(Debug)]
+struct AppCache {
+    apps: HashMap<String, (Option<AppInfo>, Instant)>,
+    next_eviction: Instant,
+}
+
+impl AppCache {
+    fn get(&self, appid: &str) -> Option<Option<AppInfo>> {
+        let (app_info, expire) = self.apps.get(appid)?;
+        (*expire > Instant::now()).then(|| app_info.clone())
+    }
+}
+
+impl<R: AppRegistry> CachedAppRegistry<R> {
+    /// Create a CachedAppRegistry wrapping the inner registry.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            ttl: Duration::from_secs(300),
+            negative_ttl: Duration::from_secs(30),
+            cache: Mutex::new(AppCache {
+                apps: Default::default(),
+                next_eviction: Instant::now(),
+            }),
+            lookups: Default::default(),
+        }
+    }
+    /// Set the cache period of found apps.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    /// Set the cache period of not found apps.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+    /// Remove the cached result of appid, e.g. after its secret changed.
+    pub fn invalidate(&self, appid: &str) {
+        self.cache.lock().unwrap().apps.remove(appid);
+    }
+    /// Remove all cached results.
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().apps.clear();
+    }
+    fn insert(&self, appid: &str, app_info: Option<AppInfo>) {
+        let ttl = match app_info {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        };
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        if cache.next_eviction <= now {
+            cache.apps.retain(|_, (_, expire)| *expire > now);
+            cache.next_eviction = now + self.ttl.max(self.negative_ttl);
+        }
+        cache.apps.insert(appid.into(), (app_info, now + ttl));
+    }
+}
+
+#[async_trait]
+impl<R: AppRegistry> AppRegistry for CachedAppRegistry<R> {
+    async fn get_app(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        if let Some(app_info) = self.cache.lock().unwrap().get(appid) {
+            return Ok(app_info);
+        }
+        let lookup = self
+            .lookups
+            .lock()
+            .unwrap()
+            .entry(appid.into())
+            .or_default()
+            .clone();
+        let _guard = lookup.lock().await;
+        // the app may be looked up by another task while waiting for the guard
+        if let Some(app_info) = self.cache.lock().unwrap().get(appid) {
+            return Ok(app_info);
+        }
+        let app_info = self.inner.get_app(appid).await;
+        let mut lookups = self.lookups.lock().unwrap();
+        if lookups.get(appid).is_some_and(|l| Arc::ptr_eq(l, &lookup)) {
+            lookups.remove(appid);
+        }
+        let app_info = app_info?;
+        self.insert(appid, app_info.clone());
+        Ok(app_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CountingRegistry {
+        count: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AppRegistry for CountingRegistry {
+        async fn get_app(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            Ok((appid == "some_appid").then(|| AppInfo::from(appid.into(), "secret".into())))
+        }
+    }
+
+    #[test]
+    fn cached_registry() {
+        tokio_test::block_on(async {
+            let registry = CachedAppRegistry::new(CountingRegistry::default())
+                .with_negative_ttl(Duration::ZERO);
+            for _ in 0..3 {
+                assert!(registry.get_app("some_appid").await.unwrap().is_some());
+            }
+            assert_eq!(registry.inner.count.load(Ordering::SeqCst), 1);
+            registry.invalidate("some_appid");
+            assert!(registry.get_app("some_appid").await.unwrap().is_some());
+            assert_eq!(registry.inner.count.load(Ordering::SeqCst), 2);
+            for _ in 0..2 {
+                assert!(registry.get_app("other_appid").await.unwrap().is_none());
+            }
+            assert_eq!(registry.inner.count.load(Ordering::SeqCst), 4);
+        })
+    }
+    #[test]
+    fn single_flight_and_lazy_eviction() {
+        tokio_test::block_on(async {
+            let registry = CachedAppRegistry::new(CountingRegistry::default())
+                .with_ttl(Duration::from_millis(50))
+                .with_negative_ttl(Duration::from_millis(50));
+            let lookups = (0..5).map(|_| registry.get_app("some_appid"));
+            for app_info in futures_util::future::join_all(lookups).await {
+                assert!(app_info.unwrap().is_some());
+            }
+            assert_eq!(registry.inner.count.load(Ordering::SeqCst), 1);
+            assert!(registry.lookups.lock().unwrap().is_empty());
+
+            for i in 0..10 {
+                registry.get_app(&format!("appid_{i}")).await.unwrap();
+            }
+            assert_eq!(registry.cache.lock().unwrap().apps.len(), 11);
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            registry.get_app("other_appid").await.unwrap();
+            assert_eq!(registry.cache.lock().unwrap().apps.len(), 1);
+        })
+    }
+}
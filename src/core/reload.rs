@@ -0,0 +1,172 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::core::{
+    config::{Config, ConfigBuilder},
+    login::Error,
+};
+
+type ConfigLoader = dyn Fn() -> Result<Config, Error> + Send + Sync;
+
+/// A shared handle of [Config] which can be swapped atomically at runtime.
+///
+/// The middlewares created with the handle read the current config on every request,
+/// so a [reload](Self::reload) takes effect on subsequent requests without restarting.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    current: Arc<ArcSwap<Config>>,
+    loader: Option<Arc<ConfigLoader>>,
+}
+
+impl Debug for ConfigHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ConfigHandle")
+            .field(&self.current.load())
+            .finish()
+    }
+}
+
+impl ConfigHandle {
+    /// Create a handle of a fixed config, which can only be changed by [store](Self::store).
+    pub fn new(cfg: Config) -> Self {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(cfg)),
+            loader: None,
+        }
+    }
+
+    /// Create a handle reloading config by the loader function.
+    pub fn with_loader(
+        loader: impl Fn() -> Result<Config, Error> + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let cfg = loader()?;
+        Ok(Self {
+            current: Arc::new(ArcSwap::from_pointee(cfg)),
+            loader: Some(Arc::new(loader)),
+        })
+    }
+
+    /// Create a handle reloading config from a TOML or JSON file.
+    ///
+    /// See [ConfigBuilder::with_file] for the file format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        Self::with_loader(move || Config::from_file(&path))
+    }
+
+    /// Create a handle reloading config from environment variables.
+    pub fn from_env_var() -> Result<Self, Error> {
+        Self::with_loader(|| ConfigBuilder::new().with_env_var().try_build())
+    }
+
+    /// Get the current config.
+    pub fn load(&self) -> Arc<Config> {
+        self.current.load_full()
+    }
+
+    /// Replace the current config.
+    pub fn store(&self, cfg: Config) {
+        let old = self.current.swap(Arc::new(cfg));
+        let new = self.current.load();
+        let diff = old.diff(&new);
+        if diff.is_empty() {
+            tracing::info!("config reloaded without changes");
+        } else {
+            tracing::info!("config reloaded with changes: {}", diff.join(", "));
+        }
+    }
+
+    /// Reload the config from its source, the current config is kept if failed.
+    pub fn reload(&self) -> Result<(), Error> {
+        let loader = self
+            .loader
+            .as_ref()
+            .ok_or("config has no source to reload")?;
+        match loader() {
+            Ok(cfg) => {
+                self.store(cfg);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("reload config fail: {e}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Spawn a task reloading the config whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&self) -> Result<tokio::task::JoinHandle<()>, Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sighup = signal(SignalKind::hangup()).map_err(|e| e.to_string())?;
+        let handle = self.clone();
+        Ok(tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                tracing::info!("SIGHUP received, reload config");
+                let _ = handle.reload();
+            }
+        }))
+    }
+
+    /// Spawn a task checking the modified time of the file every `interval`,
+    /// and reloading the config when it changes.
+    pub fn watch_file(
+        &self,
+        path: impl AsRef<Path>,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        fn modified(path: &PathBuf) -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        }
+        let path = path.as_ref().to_path_buf();
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut last_modified = modified(&path);
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let now_modified = modified(&path);
+                if now_modified != last_modified {
+                    tracing::info!("{} changed, reload config", path.display());
+                    last_modified = now_modified;
+                    let _ = handle.reload();
+                }
+            }
+        })
+    }
+}
+
+impl From<Config> for ConfigHandle {
+    fn from(cfg: Config) -> Self {
+        Self::new(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn reload_with_loader() {
+        let secs = Arc::new(AtomicU64::new(600));
+        let loader_secs = secs.clone();
+        let handle = ConfigHandle::with_loader(move || {
+            Ok(ConfigBuilder::new()
+                .with_sig_valid_secs(loader_secs.load(Ordering::SeqCst))
+                .build())
+        })
+        .unwrap();
+        assert_eq!(handle.load().sig_valid_secs, 600);
+        secs.store(300, Ordering::SeqCst);
+        handle.reload().unwrap();
+        assert_eq!(handle.load().sig_valid_secs, 300);
+        assert!(ConfigHandle::new(Config::default()).reload().is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+
+use crate::core::login::{Error, WxLogin};
+
+/// The tracing target of audit events, e.g. sessions revoked.
+pub const AUDIT_TARGET: &str = "wx_login::audit";
+
+/// The storage of revoked users, which invalidates all sessions of a user created before
+/// the revocation, including self-contained stokens which can not be removed.
+///
+/// If set with [ConfigBuilder::with_revocation_store](crate::wx_login::ConfigBuilder::with_revocation_store),
+/// it is checked on every authentication.
+#[async_trait]
+pub trait RevocationStore: Send + Sync + Debug {
+    /// Revoke all sessions of the user created before `at`, or in the same second as `at`.
+    async fn revoke(&self, appid: &str, openid: &str, at: SystemTime) -> Result<(), Error>;
+    /// Get the latest revocation time of the user, if ever revoked.
+    async fn revoked_at(&self, appid: &str, openid: &str) -> Result<Option<SystemTime>, Error>;
+}
+
+/// An in-memory [RevocationStore] evicting revocations by TTL.
+///
+/// A revocation is kept for `max_ttl` (default 7 days, the max lifetime of sessions of
+/// [MemorySessionStore](crate::wx_login::MemorySessionStore)), after which sessions of the user
+/// created before it are accepted again. Self-contained stokens have no lifetime of their own,
+/// so set it to how long users may keep them.
+#[derive(Debug)]
+pub struct MemoryRevocationStore {
+    max_ttl: Duration,
+    revocations: Mutex<HashMap<(String, String), SystemTime>>,
+}
+
+impl Default for MemoryRevocationStore {
+    fn default() -> Self {
+        Self {
+            max_ttl: Duration::from_secs(7 * 24 * 3600),
+            revocations: Default::default(),
+        }
+    }
+}
+
+impl MemoryRevocationStore {
+    /// Create an empty MemoryRevocationStore with default TTL.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Set the period revocations are kept for, which should cover the max lifetime of sessions.
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+    /// Get the number of revocations, including expired ones not evicted yet.
+    pub fn len(&self) -> usize {
+        self.revocations.lock().unwrap().len()
+    }
+    /// Check if there is no revocation.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Evict all expired revocations.
+    pub fn evict_expired(&self) {
+        let now = SystemTime::now();
+        self.revocations
+            .lock()
+            .unwrap()
+            .retain(|_, at| !self.is_expired(*at, now));
+    }
+    fn is_expired(&self, at: SystemTime, now: SystemTime) -> bool {
+        now.duration_since(at).unwrap_or_default() > self.max_ttl
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn revoke(&self, appid: &str, openid: &str, at: SystemTime) -> Result<(), Error> {
+        self.evict_expired();
+        let mut revocations = self.revocations.lock().unwrap();
+        let revoked_at = revocations
+            .entry((appid.into(), openid.into()))
+            .or_insert(at);
+        *revoked_at = (*revoked_at).max(at);
+        Ok(())
+    }
+
+    async fn revoked_at(&self, appid: &str, openid: &str) -> Result<Option<SystemTime>, Error> {
+        let mut revocations = self.revocations.lock().unwrap();
+        let key = (appid.to_string(), openid.to_string());
+        match revocations.get(&key) {
+            Some(&at) if self.is_expired(at, SystemTime::now()) => {
+                revocations.remove(&key);
+                Ok(None)
+            }
+            at => Ok(at.copied()),
+        }
+    }
+}
+
+impl WxLogin {
+    /// Revoke all sessions of the user, by the revocation store and the session store.
+    ///
+    /// Fails if there is no revocation store and the session store can not remove sessions
+    /// by user, as nothing would enforce the revocation.
+    ///
+    /// Sessions created in the same second as the revocation are revoked too, even if created
+    /// after it, as stokens keep the creation time in seconds: the user may log in again from
+    /// the next second.
+    ///
+    /// An audit event is emitted with target [AUDIT_TARGET].
+    pub async fn revoke_user_sessions(
+        &self,
+        appid: &str,
+        openid: &str,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let cfg = self.config();
+        let mut removed = None;
+        if let Some(store) = &cfg.session_store {
+            removed = store.remove_user_sessions(appid, openid).await?;
+        }
+        match &cfg.revocation_store {
+            Some(revocations) => revocations.revoke(appid, openid, SystemTime::now()).await?,
+            None if removed.is_none() => {
+                Err("no revocation store, and the session store can not remove sessions by user")?
+            }
+            None => {}
+        }
+        let removed = removed.unwrap_or_default();
+        tracing::warn!(
+            target: AUDIT_TARGET,
+            action = "revoke-user-sessions",
+            appid,
+            openid,
+            reason,
+            removed,
+            "user sessions revoked"
+        );
+        Ok(())
+    }
+
+    /// Check if the session of the user created at `created` is revoked.
+    ///
+    /// Times are compared in whole seconds, as stokens keep the creation time in seconds
+    /// and stores may truncate the revocation time: a session created in the same second
+    /// as the revocation is revoked too.
+    pub(crate) async fn check_revoked(
+        &self,
+        appid: &str,
+        openid: &str,
+        created: SystemTime,
+    ) -> Result<(), Error> {
+        if let Some(revocations) = &self.config().revocation_store {
+            if let Some(revoked_at) = revocations.revoked_at(appid, openid).await? {
+                if unix_secs(created) <= unix_secs(revoked_at) {
+                    Err("session revoked")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        login::tests::{test_app_info, test_login, test_wx_login},
+        session::{MemorySessionStore, Session, SessionStore},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn revoke_sessions() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let wx_login = test_wx_login(&app_info, |b| {
+                b.with_auth_sig(false)
+                    .with_revocation_store(Arc::new(MemoryRevocationStore::new()))
+            });
+            let before = SystemTime::now() - Duration::from_secs(10);
+            let (ok, _) = test_login(&wx_login, &app_info, "some_openid", Default::default()).await;
+            assert!(wx_login.authenticate(&ok.stoken, "/", Ok("")).await.is_ok());
+            wx_login
+                .check_revoked("some_appid", "some_openid", before)
+                .await
+                .unwrap();
+
+            wx_login
+                .revoke_user_sessions("some_appid", "some_openid", "test")
+                .await
+                .unwrap();
+            assert!(wx_login
+                .authenticate(&ok.stoken, "/", Ok(""))
+                .await
+                .is_err());
+            assert!(wx_login
+                .check_revoked("some_appid", "other_openid", before)
+                .await
+                .is_ok());
+
+            let store = Arc::new(MemorySessionStore::new());
+            let wx_login = test_wx_login(&app_info, |b| {
+                b.with_auth_sig(false).with_session_store(store.clone())
+            });
+            let (ok, _) = test_login(&wx_login, &app_info, "some_openid", Default::default()).await;
+            assert!(wx_login.authenticate(&ok.stoken, "/", Ok("")).await.is_ok());
+            wx_login
+                .revoke_user_sessions("some_appid", "some_openid", "test")
+                .await
+                .unwrap();
+            assert!(store.is_empty());
+            assert!(wx_login
+                .authenticate(&ok.stoken, "/", Ok(""))
+                .await
+                .is_err());
+        })
+    }
+    /// A session store which can not find sessions by user.
+    #[derive(Debug, Default)]
+    struct SidOnlyStore(MemorySessionStore);
+
+    #[async_trait]
+    impl SessionStore for SidOnlyStore {
+        async fn insert(&self, sid: &str, session: Session) -> Result<(), Error> {
+            self.0.insert(sid, session).await
+        }
+        async fn get(&self, sid: &str) -> Result<Option<Session>, Error> {
+            self.0.get(sid).await
+        }
+        async fn touch(&self, sid: &str, last_seen: SystemTime) -> Result<(), Error> {
+            self.0.touch(sid, last_seen).await
+        }
+        async fn remove(&self, sid: &str) -> Result<(), Error> {
+            self.0.remove(sid).await
+        }
+    }
+
+    #[test]
+    fn revoke_needs_enforcement() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let store = Arc::new(SidOnlyStore::default());
+            let wx_login = test_wx_login(&app_info, |b| b.with_session_store(store.clone()));
+            assert!(wx_login
+                .revoke_user_sessions("some_appid", "some_openid", "test")
+                .await
+                .is_err());
+
+            let wx_login = test_wx_login(&app_info, |b| {
+                b.with_session_store(store.clone())
+                    .with_revocation_store(Arc::new(MemoryRevocationStore::new()))
+            });
+            wx_login
+                .revoke_user_sessions("some_appid", "some_openid", "test")
+                .await
+                .unwrap();
+        })
+    }
+
+    #[test]
+    fn evict_revocations() {
+        tokio_test::block_on(async {
+            let store = MemoryRevocationStore::new().with_max_ttl(Duration::from_secs(60));
+            let now = SystemTime::now();
+            store
+                .revoke("some_appid", "some_openid", now - Duration::from_secs(59))
+                .await
+                .unwrap();
+            store
+                .revoke("some_appid", "old_openid", now - Duration::from_secs(61))
+                .await
+                .unwrap();
+            assert_eq!(store.len(), 2);
+            assert_eq!(
+                store.revoked_at("some_appid", "old_openid").await.unwrap(),
+                None
+            );
+            assert_eq!(store.len(), 1);
+            assert!(store
+                .revoked_at("some_appid", "some_openid")
+                .await
+                .unwrap()
+                .is_some());
+
+            store
+                .revoke("some_appid", "old_openid", now - Duration::from_secs(61))
+                .await
+                .unwrap();
+            assert_eq!(store.len(), 2);
+            store.evict_expired();
+            assert_eq!(store.len(), 1);
+        })
+    }
+
+    #[test]
+    fn revoke_in_whole_seconds() {
+        tokio_test::block_on(async {
+            let revocations = Arc::new(MemoryRevocationStore::new());
+            let wx_login = test_wx_login(&test_app_info(), |b| {
+                b.with_revocation_store(revocations.clone())
+            });
+            // a whole second within the TTL of revocations
+            let second = UNIX_EPOCH + Duration::from_secs(unix_secs(SystemTime::now()) - 60);
+            let ms = Duration::from_millis;
+            revocations
+                .revoke("some_appid", "some_openid", second + ms(500))
+                .await
+                .unwrap();
+            // sessions created in the revoked second are revoked, even after the revocation
+            for created in [
+                second - ms(1),
+                second,
+                second + ms(499),
+                second + ms(501),
+                second + ms(999),
+            ] {
+                assert!(wx_login
+                    .check_revoked("some_appid", "some_openid", created)
+                    .await
+                    .is_err());
+            }
+            assert!(wx_login
+                .check_revoked("some_appid", "some_openid", second + ms(1000))
+                .await
+                .is_ok());
+        })
+    }
+}
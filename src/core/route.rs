@@ -0,0 +1,151 @@
+use serde::Deserialize;
+
+use crate::core::{
+    config::Config,
+    handoff::Handoff,
+    login::{Error, WxLogin, WxLoginInfo},
+    oauth::{OAuthLogin, OAuthOp},
+    push::PushEndpoint,
+    scan_login::{ScanLogin, ScanLoginOp},
+};
+
+/// An endpoint served by the middleware itself, matched by the request path.
+///
+/// The adapters dispatch [PublicRoute]s before authenticating the request, so a bad or
+/// missing session never stands in the way of them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Route<'a> {
+    Public(PublicRoute<'a>),
+    Authenticated(AuthenticatedRoute<'a>),
+}
+
+/// The endpoints called without a login session.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PublicRoute<'a> {
+    /// Messages pushed by WeChat server, including the authorization events of components.
+    Push(&'a PushEndpoint),
+    Login,
+    HandoffExchange,
+    OAuth(&'a OAuthLogin, OAuthOp),
+    /// The scan-to-login endpoints of the web page: ticket, QR code and poll.
+    ScanLogin(&'a ScanLogin, ScanLoginOp),
+}
+
+/// The endpoints called by the logged-in user.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum AuthenticatedRoute<'a> {
+    HandoffMint(&'a Handoff),
+    PhoneNumber,
+    CheckSession,
+    ScanConfirm(&'a ScanLogin),
+}
+
+/// The params of the login endpoint.
+#[derive(Deserialize)]
+pub(crate) struct LoginRequest {
+    pub(crate) appid: String,
+    pub(crate) code: String,
+}
+
+/// The params of the phone number endpoint.
+#[derive(Deserialize)]
+pub(crate) struct PhoneNumberRequest {
+    pub(crate) code: String,
+}
+
+impl Config {
+    /// Match the request path to an endpoint of the middleware.
+    pub(crate) fn route(&self, path: &str) -> Option<Route<'_>> {
+        if let Some(endpoint) = self.all_push_endpoints().find(|e| e.path == path) {
+            return Some(Route::Public(PublicRoute::Push(endpoint)));
+        }
+        if path == self.login_path {
+            return Some(Route::Public(PublicRoute::Login));
+        }
+        if let Some(handoff) = &self.handoff {
+            if path == handoff.mint_path {
+                return Some(Route::Authenticated(AuthenticatedRoute::HandoffMint(
+                    handoff,
+                )));
+            }
+            if path == handoff.exchange_path {
+                return Some(Route::Public(PublicRoute::HandoffExchange));
+            }
+        }
+        if let Some((oauth, op)) = self
+            .oauth_logins
+            .iter()
+            .find_map(|oauth| Some((oauth, oauth.op(path)?)))
+        {
+            return Some(Route::Public(PublicRoute::OAuth(oauth, op)));
+        }
+        if Some(path) == self.phone_number_path.as_deref() {
+            return Some(Route::Authenticated(AuthenticatedRoute::PhoneNumber));
+        }
+        if Some(path) == self.check_session_path.as_deref() {
+            return Some(Route::Authenticated(AuthenticatedRoute::CheckSession));
+        }
+        let scan = self.scan_login.as_ref()?;
+        match scan.op(path)? {
+            ScanLoginOp::Confirm => {
+                Some(Route::Authenticated(AuthenticatedRoute::ScanConfirm(scan)))
+            }
+            op => Some(Route::Public(PublicRoute::ScanLogin(scan, op))),
+        }
+    }
+}
+
+impl WxLogin {
+    /// Authenticate a request by its credentials, or by the session cookie if it has none,
+    /// returning the stoken found.
+    pub(crate) async fn authenticate_request<'a>(
+        &self,
+        uri: &str,
+        header: impl Fn(&str) -> Option<&'a str>,
+        cookies: &[&str],
+        safe_method: bool,
+    ) -> (Option<String>, Result<WxLoginInfo, Error>) {
+        if let Some(credentials) = self.credentials(uri, &header) {
+            let auth_info = self.authenticate_credentials(&credentials).await;
+            return (Some(credentials.stoken), auth_info);
+        }
+        let csrf_header = self
+            .cookie_session()
+            .and_then(|c| header(&c.csrf_header_name));
+        match self
+            .authenticate_cookies(cookies, safe_method, csrf_header)
+            .await
+        {
+            Some((stoken, auth_info)) => (Some(stoken), auth_info),
+            None => (None, Err(Error::from("no stoken in credentials"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::ConfigBuilder;
+
+    #[test]
+    fn route_public_before_authenticated() {
+        let cfg = ConfigBuilder::new()
+            .with_push_endpoint(PushEndpoint::new("/push", "some_appid", "some_token"))
+            .with_handoff(Handoff::new("/handoff/mint", "/handoff/exchange"))
+            .with_scan_login(ScanLogin::new("/scan", "some_appid"))
+            .with_phone_number_path("/phone")
+            .build();
+        let public = |path| matches!(cfg.route(path), Some(Route::Public(_)));
+        let authenticated = |path| matches!(cfg.route(path), Some(Route::Authenticated(_)));
+        assert!(public("/push"));
+        assert!(public(&cfg.login_path));
+        assert!(public("/handoff/exchange"));
+        assert!(public("/scan/ticket"));
+        assert!(public("/scan/qrcode"));
+        assert!(public("/scan/poll"));
+        assert!(authenticated("/handoff/mint"));
+        assert!(authenticated("/scan/confirm"));
+        assert!(authenticated("/phone"));
+        assert!(cfg.route("/other").is_none());
+    }
+}
@@ -0,0 +1,673 @@
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use tiny_crypto::encoding::{Encoder, HEX};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::{
+    authz::Claims,
+    login::{err_with, ErrWith, WxLogin, WxLoginErr, WxLoginInfo},
+    security::random_bytes,
+    ticket::{is_ticket, new_ticket, ticket_key},
+    wxacode::{encode_scene, WxaCode, WxaCodeImage},
+};
+use crate::core::{
+    login::{Error, WxLoginOk},
+    wxacode::EnvVersion,
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const SCAN_LOGIN_FAIL_MSG: &str = "扫码登录失败";
+
+/// The max waiting period of a long-poll.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const SCAN_LOGIN_MAX_WAIT_SECS: u64 = 30;
+/// The interval of checking the ticket during a long-poll.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const SCAN_LOGIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The endpoints of scan-to-login for web, by scanning a mini-program code of the app.
+///
+/// Endpoints under the path:
+/// - `{path}/ticket`: the web side creates a ticket, returning [ScanLoginTicket].
+/// - `{path}/qrcode?ticket=...`: the web side gets the mini-program code of the ticket,
+///   whose scene is `t=<ticket>`, opening the page of the mini-program.
+/// - `{path}/confirm`: the mini-program scans (`action` "scan"), confirms (default) or cancels
+///   (`action` "cancel") the `ticket` with a login session.
+/// - `{path}/poll?ticket=...&secret=...&wait=...`: the web side polls the [ScanLoginState] of the
+///   ticket, waiting at most `wait` seconds (up to 30) for changes, and receives its own session
+///   once confirmed. The ticket is removed after it is confirmed or cancelled and polled.
+#[derive(Debug, Clone)]
+pub struct ScanLogin {
+    pub(crate) path: String,
+    pub(crate) appid: String,
+    pub(crate) page: Option<String>,
+    pub(crate) env_version: Option<EnvVersion>,
+    pub(crate) ticket_ttl_secs: u64,
+}
+
+impl ScanLogin {
+    /// Create the endpoints of the app under the path.
+    pub fn new(path: &str, appid: &str) -> Self {
+        Self {
+            path: path.into(),
+            appid: appid.into(),
+            page: None,
+            env_version: None,
+            ticket_ttl_secs: 300,
+        }
+    }
+    /// Set the page of the mini-program confirming the ticket, the default is the home page.
+    pub fn with_page(mut self, page: &str) -> Self {
+        self.page = Some(page.into());
+        self
+    }
+    /// Set the mini-program version opened by the code, the default is release.
+    pub fn with_env_version(mut self, env_version: EnvVersion) -> Self {
+        self.env_version = Some(env_version);
+        self
+    }
+    /// Set the valid period of tickets, the default is 300 seconds.
+    pub fn with_ticket_ttl_secs(mut self, secs: u64) -> Self {
+        self.ticket_ttl_secs = secs;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !self.path.starts_with('/') || self.path.ends_with('/') {
+            Err(format!(
+                "scan login path {:?} is not started with '/' or ended with '/'",
+                self.path
+            ))?;
+        }
+        if self.appid.is_empty() {
+            Err("empty appid of scan login")?;
+        }
+        if self.ticket_ttl_secs == 0 {
+            Err("ticket_ttl_secs of scan login is 0")?;
+        }
+        Ok(())
+    }
+
+    /// Get the operation of the request path, if it is one of the endpoints.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) fn op(&self, path: &str) -> Option<ScanLoginOp> {
+        match path.strip_prefix(&self.path)? {
+            "/ticket" => Some(ScanLoginOp::Ticket),
+            "/qrcode" => Some(ScanLoginOp::QrCode),
+            "/confirm" => Some(ScanLoginOp::Confirm),
+            "/poll" => Some(ScanLoginOp::Poll),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScanLoginOp {
+    Ticket,
+    QrCode,
+    Confirm,
+    Poll,
+}
+
+/// The status of a scan-to-login ticket.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanLoginStatus {
+    /// Waiting for scanning.
+    Pending,
+    /// Scanned by the mini-program, waiting for confirmation.
+    Scanned,
+    /// Confirmed by the mini-program.
+    Confirmed,
+    /// Cancelled by the mini-program.
+    Cancelled,
+    /// Expired, already used or not found.
+    Expired,
+}
+
+/// A new scan-to-login ticket.
+///
+/// The secret is only known by the web side and required to poll, so that
+/// others seeing the code can not take the session.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanLoginTicket {
+    pub ticket: String,
+    pub secret: String,
+    pub scene: String,
+    pub expires_in: u64,
+}
+
+/// The state of a scan-to-login ticket, with the login result for the web side once confirmed.
+#[derive(Serialize, Debug)]
+pub struct ScanLoginState {
+    pub status: ScanLoginStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login: Option<WxLoginOk>,
+}
+
+/// The action of the mini-program on a ticket.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ScanLoginAction {
+    Scan,
+    Confirm,
+    Cancel,
+}
+
+/// The params of scan-to-login endpoints.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct ScanLoginRequest {
+    pub(crate) ticket: Option<String>,
+    pub(crate) secret: Option<String>,
+    pub(crate) wait: Option<u64>,
+    pub(crate) action: Option<ScanLoginAction>,
+}
+
+/// The response of scan-to-login endpoints.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) enum ScanLoginResponse {
+    Ticket(ScanLoginTicket),
+    QrCode(WxaCodeImage),
+    State(ScanLoginState),
+}
+
+/// The ticket kept by the ticket store.
+///
+/// No session is kept, the session of the web side is issued when the confirmed ticket is polled.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+#[derive(Serialize, Deserialize, Debug)]
+struct TicketState {
+    secret_hash: String,
+    status: ScanLoginStatus,
+    openid: Option<String>,
+    #[serde(default)]
+    unionid: Option<String>,
+    #[serde(default)]
+    claims: Claims,
+}
+
+/// The namespace of scan-to-login tickets in the ticket store.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const SCAN_TICKETS: &str = "scan";
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+const SCAN_ERR: ErrWith = err_with(SCAN_LOGIN_FAIL_MSG);
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn hash_secret(secret: &str) -> String {
+    HEX.to_text(&hmac_sha256::Hash::hash(secret.as_bytes()))
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+fn ticket_scene(ticket: &str) -> Result<String, WxLoginErr> {
+    encode_scene(&[("t", ticket)]).map_err(|e| SCAN_ERR.err(400, "scan-ticket-invalid", e))
+}
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+impl WxLogin {
+    /// Handle requests to scan-to-login endpoints.
+    pub(crate) async fn handle_scan_login(
+        &self,
+        scan: &ScanLogin,
+        op: ScanLoginOp,
+        req: ScanLoginRequest,
+        login_info: Result<WxLoginInfo, WxLoginErr>,
+    ) -> Result<ScanLoginResponse, WxLoginErr> {
+        if op == ScanLoginOp::Ticket {
+            return self
+                .create_scan_ticket(scan)
+                .await
+                .map(ScanLoginResponse::Ticket);
+        }
+        let ticket = req
+            .ticket
+            .ok_or_else(|| SCAN_ERR.err(400, "scan-ticket-missing", "no ticket"))?;
+        if !is_ticket(&ticket) {
+            return Err(SCAN_ERR.err(400, "scan-ticket-invalid", ticket));
+        }
+        match op {
+            ScanLoginOp::QrCode => self
+                .scan_ticket_qrcode(scan, &ticket)
+                .await
+                .map(ScanLoginResponse::QrCode),
+            ScanLoginOp::Confirm => self
+                .confirm_scan_ticket(
+                    &login_info?,
+                    scan,
+                    &ticket,
+                    req.action.unwrap_or(ScanLoginAction::Confirm),
+                )
+                .await
+                .map(ScanLoginResponse::State),
+            _ => {
+                let secret = req
+                    .secret
+                    .ok_or_else(|| SCAN_ERR.err(400, "scan-secret-missing", "no secret"))?;
+                let wait = Duration::from_secs(req.wait.unwrap_or(0).min(SCAN_LOGIN_MAX_WAIT_SECS));
+                self.poll_scan_ticket(scan, &ticket, &secret, wait)
+                    .await
+                    .map(ScanLoginResponse::State)
+            }
+        }
+    }
+
+    async fn create_scan_ticket(&self, scan: &ScanLogin) -> Result<ScanLoginTicket, WxLoginErr> {
+        let ticket = new_ticket();
+        let secret = HEX.to_text(&random_bytes::<16>());
+        let state = TicketState {
+            secret_hash: hash_secret(&secret),
+            status: ScanLoginStatus::Pending,
+            openid: None,
+            unionid: None,
+            claims: Claims::default(),
+        };
+        let value =
+            serde_json::to_string(&state).map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        let expires = SystemTime::now() + Duration::from_secs(scan.ticket_ttl_secs);
+        self.config()
+            .ticket_store
+            .insert(&ticket_key(SCAN_TICKETS, &ticket), value, expires)
+            .await
+            .map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        Ok(ScanLoginTicket {
+            scene: ticket_scene(&ticket)?,
+            ticket,
+            secret,
+            expires_in: scan.ticket_ttl_secs,
+        })
+    }
+
+    async fn scan_ticket_qrcode(
+        &self,
+        scan: &ScanLogin,
+        ticket: &str,
+    ) -> Result<WxaCodeImage, WxLoginErr> {
+        let state = self.load_scan_ticket(ticket).await?;
+        if !matches!(
+            state,
+            Some((
+                _,
+                TicketState {
+                    status: ScanLoginStatus::Pending,
+                    ..
+                }
+            ))
+        ) {
+            return Err(SCAN_ERR.err(404, "scan-ticket-not-found", ticket));
+        }
+        let mut code = WxaCode::new(&ticket_scene(ticket)?);
+        if let Some(page) = &scan.page {
+            code = code.with_page(page).with_check_path(false);
+        }
+        if let Some(env_version) = scan.env_version {
+            code = code.with_env_version(env_version);
+        }
+        self.get_wxa_code_unlimited(&scan.appid, &code)
+            .await
+            .map_err(|e| SCAN_ERR.err(500, "scan-qrcode-call-fail", e))
+    }
+
+    async fn confirm_scan_ticket(
+        &self,
+        login_info: &WxLoginInfo,
+        scan: &ScanLogin,
+        ticket: &str,
+        action: ScanLoginAction,
+    ) -> Result<ScanLoginState, WxLoginErr> {
+        if login_info.appid != scan.appid {
+            return Err(SCAN_ERR.err(403, "scan-appid-mismatch", &login_info.appid));
+        }
+        let (value, mut state) = self
+            .load_scan_ticket(ticket)
+            .await?
+            .ok_or_else(|| SCAN_ERR.err(404, "scan-ticket-not-found", ticket))?;
+        let scanned_by_other = state
+            .openid
+            .as_ref()
+            .is_some_and(|openid| *openid != login_info.openid);
+        if !matches!(
+            state.status,
+            ScanLoginStatus::Pending | ScanLoginStatus::Scanned
+        ) || scanned_by_other
+        {
+            return Err(SCAN_ERR.err(409, "scan-ticket-used", ticket));
+        }
+        state.openid = Some(login_info.openid.clone());
+        state.status = match action {
+            ScanLoginAction::Scan => ScanLoginStatus::Scanned,
+            ScanLoginAction::Cancel => ScanLoginStatus::Cancelled,
+            ScanLoginAction::Confirm => {
+                state.unionid = login_info.unionid.clone();
+                state.claims = login_info.claims.clone();
+                ScanLoginStatus::Confirmed
+            }
+        };
+        let new_value =
+            serde_json::to_string(&state).map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        // the ticket may be confirmed or cancelled concurrently, only one update succeeds
+        let updated = self
+            .config()
+            .ticket_store
+            .update_if(&ticket_key(SCAN_TICKETS, ticket), &value, new_value)
+            .await
+            .map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        if !updated {
+            return Err(SCAN_ERR.err(409, "scan-ticket-used", ticket));
+        }
+        tracing::info!(
+            appid = login_info.appid,
+            openid = login_info.openid,
+            ?action,
+            "scan login ticket updated"
+        );
+        Ok(ScanLoginState {
+            status: state.status,
+            login: None,
+        })
+    }
+
+    async fn poll_scan_ticket(
+        &self,
+        scan: &ScanLogin,
+        ticket: &str,
+        secret: &str,
+        wait: Duration,
+    ) -> Result<ScanLoginState, WxLoginErr> {
+        let deadline = Instant::now() + wait;
+        let mut first_status = None;
+        loop {
+            let state = self.load_scan_ticket(ticket).await?.map(|(_, state)| state);
+            if let Some(state) = &state {
+                if state.secret_hash != hash_secret(secret) {
+                    return Err(SCAN_ERR.err(403, "scan-secret-mismatch", ticket));
+                }
+            }
+            let status = state.map_or(ScanLoginStatus::Expired, |state| state.status);
+            match status {
+                ScanLoginStatus::Confirmed | ScanLoginStatus::Cancelled => {
+                    return self.take_scan_ticket(scan, ticket).await;
+                }
+                ScanLoginStatus::Expired => break,
+                _ if *first_status.get_or_insert(status) != status => break,
+                _ if Instant::now() >= deadline => break,
+                _ => tokio::time::sleep(SCAN_LOGIN_POLL_INTERVAL).await,
+            }
+        }
+        // the ticket may be confirmed or cancelled since the last check
+        let status = self
+            .load_scan_ticket(ticket)
+            .await?
+            .map_or(ScanLoginStatus::Expired, |(_, state)| state.status);
+        match status {
+            ScanLoginStatus::Confirmed | ScanLoginStatus::Cancelled => {
+                self.take_scan_ticket(scan, ticket).await
+            }
+            _ => Ok(ScanLoginState {
+                status,
+                login: None,
+            }),
+        }
+    }
+
+    /// Take the confirmed or cancelled ticket once, issuing the session of the web side if confirmed.
+    async fn take_scan_ticket(
+        &self,
+        scan: &ScanLogin,
+        ticket: &str,
+    ) -> Result<ScanLoginState, WxLoginErr> {
+        let taken = self
+            .config()
+            .ticket_store
+            .take(&ticket_key(SCAN_TICKETS, ticket))
+            .await
+            .map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        let state: TicketState = match taken {
+            Some(value) => serde_json::from_str(&value)
+                .map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?,
+            None => {
+                return Ok(ScanLoginState {
+                    status: ScanLoginStatus::Expired,
+                    login: None,
+                })
+            }
+        };
+        let login = match (state.status, state.openid) {
+            (ScanLoginStatus::Confirmed, Some(openid)) => {
+                let app_info = self
+                    .app_info(&scan.appid)
+                    .await
+                    .map_err(|e| SCAN_ERR.err(500, "app-registry-fail", e))?;
+                // the web side can not decrypt open data, so its session_key is random
+                let ok: WxLoginOk = self
+                    .issue_session(
+                        &app_info,
+                        openid,
+                        state.unionid,
+                        random_bytes::<16>(),
+                        state.claims,
+                    )
+                    .await?;
+                Some(ok)
+            }
+            _ => None,
+        };
+        Ok(ScanLoginState {
+            status: state.status,
+            login,
+        })
+    }
+
+    async fn load_scan_ticket(
+        &self,
+        ticket: &str,
+    ) -> Result<Option<(String, TicketState)>, WxLoginErr> {
+        let value = self
+            .config()
+            .ticket_store
+            .get(&ticket_key(SCAN_TICKETS, ticket))
+            .await
+            .map_err(|e| SCAN_ERR.err(500, "ticket-store-fail", e))?;
+        value
+            .map(|value| Ok((value.clone(), serde_json::from_str(&value)?)))
+            .transpose()
+            .map_err(|e: serde_json::Error| SCAN_ERR.err(500, "ticket-store-fail", e))
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::core::{
+        authz::{login_info_from_auth_result, Claims},
+        login::tests::{test_app_info, test_login, test_wx_login},
+        ticket::{MemoryTicketStore, TicketStore},
+    };
+
+    /// A ticket store confirming the ticket right after it is read once armed,
+    /// as if the mini-program confirmed it between two checks of a poll.
+    #[derive(Debug, Default)]
+    struct ConfirmingStore {
+        inner: MemoryTicketStore,
+        armed: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl TicketStore for ConfirmingStore {
+        async fn insert(
+            &self,
+            ticket: &str,
+            value: String,
+            expires: SystemTime,
+        ) -> Result<(), Error> {
+            self.inner.insert(ticket, value, expires).await
+        }
+        async fn get(&self, ticket: &str) -> Result<Option<String>, Error> {
+            let value = self.inner.get(ticket).await?;
+            if let Some(value) = value
+                .as_ref()
+                .filter(|_| self.armed.swap(false, Ordering::SeqCst))
+            {
+                let mut state: TicketState = serde_json::from_str(value).unwrap();
+                state.status = ScanLoginStatus::Confirmed;
+                state.openid = Some("some_openid".into());
+                let confirmed = serde_json::to_string(&state).unwrap();
+                assert!(self.inner.update_if(ticket, value, confirmed).await?);
+            }
+            Ok(value)
+        }
+        async fn update_if(
+            &self,
+            ticket: &str,
+            expected: &str,
+            value: String,
+        ) -> Result<bool, Error> {
+            self.inner.update_if(ticket, expected, value).await
+        }
+        async fn take(&self, ticket: &str) -> Result<Option<String>, Error> {
+            self.inner.take(ticket).await
+        }
+    }
+
+    fn req(ticket: &str, secret: &str, action: Option<ScanLoginAction>) -> ScanLoginRequest {
+        ScanLoginRequest {
+            ticket: Some(ticket.into()),
+            secret: Some(secret.into()),
+            wait: None,
+            action,
+        }
+    }
+
+    #[test]
+    fn scan_to_login() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let scan = ScanLogin::new("/scan-login", "some_appid");
+            let wx_login = test_wx_login(&app_info, |b| {
+                b.with_auth_sig(false).with_scan_login(scan.clone())
+            });
+            let (_, user) = test_login(&wx_login, &app_info, "some_openid", Claims::new()).await;
+            let (_, other) = test_login(&wx_login, &app_info, "other", Claims::new()).await;
+            let login_info = |openid: &str| match openid {
+                "some_openid" => Ok(user.clone()),
+                "other" => Ok(other.clone()),
+                _ => login_info_from_auth_result(None),
+            };
+            assert_eq!(scan.op("/scan-login/poll"), Some(ScanLoginOp::Poll));
+            assert_eq!(scan.op("/scan-login"), None);
+
+            let handle = |op, req, login_info| {
+                let wx_login = wx_login.clone();
+                let scan = scan.clone();
+                async move { wx_login.handle_scan_login(&scan, op, req, login_info).await }
+            };
+            let state = |res: Result<ScanLoginResponse, WxLoginErr>| match res {
+                Ok(ScanLoginResponse::State(state)) => state,
+                _ => panic!("not a state"),
+            };
+            let Ok(ScanLoginResponse::Ticket(ticket)) =
+                handle(ScanLoginOp::Ticket, Default::default(), login_info("")).await
+            else {
+                panic!("not a ticket")
+            };
+            assert_eq!(ticket.scene, format!("t={}", ticket.ticket));
+            let (t, s) = (ticket.ticket.as_str(), ticket.secret.as_str());
+
+            let polled = handle(ScanLoginOp::Poll, req(t, s, None), login_info("")).await;
+            assert_eq!(state(polled).status, ScanLoginStatus::Pending);
+            let err = handle(ScanLoginOp::Poll, req(t, "bad", None), login_info(""))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.code, "scan-secret-mismatch");
+
+            let scanned = handle(
+                ScanLoginOp::Confirm,
+                req(t, "", Some(ScanLoginAction::Scan)),
+                login_info("some_openid"),
+            )
+            .await;
+            assert_eq!(state(scanned).status, ScanLoginStatus::Scanned);
+            let err = handle(ScanLoginOp::Confirm, req(t, "", None), login_info("other"))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.code, "scan-ticket-used");
+            let (confirmed, cancelled) = futures_util::future::join(
+                handle(
+                    ScanLoginOp::Confirm,
+                    req(t, "", None),
+                    login_info("some_openid"),
+                ),
+                handle(
+                    ScanLoginOp::Confirm,
+                    req(t, "", Some(ScanLoginAction::Cancel)),
+                    login_info("some_openid"),
+                ),
+            )
+            .await;
+            assert_eq!(state(confirmed).status, ScanLoginStatus::Confirmed);
+            assert_eq!(cancelled.err().unwrap().code, "scan-ticket-used");
+            let key = ticket_key(SCAN_TICKETS, t);
+            let value = wx_login.config().ticket_store.get(&key).await.unwrap();
+            assert!(!value.unwrap().contains("stoken"));
+
+            let polled = state(handle(ScanLoginOp::Poll, req(t, s, None), login_info("")).await);
+            assert_eq!(polled.status, ScanLoginStatus::Confirmed);
+            let login = polled.login.unwrap();
+            assert_eq!(login.openid, "some_openid");
+            assert!(wx_login
+                .authenticate(&login.stoken, "/", Ok(""))
+                .await
+                .is_ok());
+
+            let polled = handle(ScanLoginOp::Poll, req(t, s, None), login_info("")).await;
+            assert_eq!(state(polled).status, ScanLoginStatus::Expired);
+            let err = handle(
+                ScanLoginOp::Confirm,
+                req(t, "", None),
+                login_info("some_openid"),
+            )
+            .await
+            .err()
+            .unwrap();
+            assert_eq!(err.code, "scan-ticket-not-found");
+        })
+    }
+
+    #[test]
+    fn poll_confirmed_after_last_check() {
+        tokio_test::block_on(async {
+            let app_info = test_app_info();
+            let scan = ScanLogin::new("/scan-login", "some_appid");
+            let store = Arc::new(ConfirmingStore::default());
+            let wx_login = test_wx_login(&app_info, |b| {
+                b.with_scan_login(scan.clone())
+                    .with_ticket_store(store.clone())
+            });
+            let ticket = wx_login.create_scan_ticket(&scan).await.unwrap();
+            let (t, s) = (ticket.ticket.as_str(), ticket.secret.as_str());
+
+            store.armed.store(true, Ordering::SeqCst);
+            let polled = wx_login
+                .poll_scan_ticket(&scan, t, s, Duration::ZERO)
+                .await
+                .unwrap();
+            assert_eq!(polled.status, ScanLoginStatus::Confirmed);
+            assert_eq!(polled.login.unwrap().openid, "some_openid");
+            let key = ticket_key(SCAN_TICKETS, t);
+            assert!(store.get(&key).await.unwrap().is_none());
+        })
+    }
+}
@@ -0,0 +1,302 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::core::{
+    api::{post_json, ApiError, WX_API_BASE_URL},
+    login::{err_resp_with, WxLogin, WxLoginErr, WxLoginInfo},
+};
+
+pub(crate) const SEC_CHECK_FAIL_MSG: &str = "内容安全检查失败";
+
+/// The label of normal content.
+pub const SEC_CHECK_LABEL_NORMAL: i64 = 100;
+
+/// The verdict of content security check.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecCheckVerdict {
+    /// The content is normal.
+    Pass,
+    /// The content is uncertain and should be reviewed manually.
+    Review,
+    /// The content is risky and should be rejected.
+    Risky,
+}
+
+/// The suggestion of content security check.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecCheckSuggest {
+    pub suggest: SecCheckVerdict,
+    /// The label of the content, e.g. 100 normal, 10001 ad, 20001 politics, 20002 porn.
+    pub label: i64,
+}
+
+/// The scene of the text to check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecCheckScene {
+    /// Profile, e.g. nickname and signature.
+    Profile,
+    /// Comment.
+    Comment,
+    /// Forum post.
+    Forum,
+    /// Social log.
+    SocialLog,
+}
+
+impl SecCheckScene {
+    fn code(self) -> u8 {
+        match self {
+            SecCheckScene::Profile => 1,
+            SecCheckScene::Comment => 2,
+            SecCheckScene::Forum => 3,
+            SecCheckScene::SocialLog => 4,
+        }
+    }
+}
+
+/// A text to check by `wxa/msg_sec_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextSecCheck {
+    content: String,
+    scene: SecCheckScene,
+    title: Option<String>,
+    nickname: Option<String>,
+    signature: Option<String>,
+}
+
+impl TextSecCheck {
+    /// Create a check of the content in the scene.
+    pub fn new(content: impl Into<String>, scene: SecCheckScene) -> Self {
+        Self {
+            content: content.into(),
+            scene,
+            title: None,
+            nickname: None,
+            signature: None,
+        }
+    }
+    /// Set the title of the content.
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    /// Set the nickname of the user, only for the profile scene.
+    pub fn with_nickname(mut self, nickname: impl Into<String>) -> Self {
+        self.nickname = Some(nickname.into());
+        self
+    }
+    /// Set the signature of the user, only for the profile scene.
+    pub fn with_signature(mut self, signature: impl Into<String>) -> Self {
+        self.signature = Some(signature.into());
+        self
+    }
+}
+
+/// The detail of a check strategy.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecCheckDetail {
+    pub strategy: String,
+    pub errcode: i64,
+    pub suggest: Option<SecCheckVerdict>,
+    pub label: Option<i64>,
+    pub keyword: Option<String>,
+    pub prob: Option<i64>,
+}
+
+/// The result of content security check.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecCheckResult {
+    pub trace_id: String,
+    pub result: SecCheckSuggest,
+    #[serde(default)]
+    pub detail: Vec<SecCheckDetail>,
+}
+
+impl SecCheckResult {
+    /// Get the verdict of the check.
+    pub fn verdict(&self) -> SecCheckVerdict {
+        self.result.suggest
+    }
+    /// Get the labels of the content, the overall one first, then those of strategies
+    /// which did not pass.
+    pub fn labels(&self) -> Vec<i64> {
+        let mut labels = vec![self.result.label];
+        for label in self
+            .detail
+            .iter()
+            .filter(|d| d.suggest.is_some_and(|s| s != SecCheckVerdict::Pass))
+            .filter_map(|d| d.label)
+        {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+        labels
+    }
+}
+
+/// A request body checked by content security check, to be extracted by [WxSecChecked].
+pub trait SecCheckContent: DeserializeOwned {
+    /// Get the text to check.
+    fn sec_check(&self) -> TextSecCheck;
+}
+
+/// An extractor of a JSON request body posted by the logged-in user, with its text checked
+/// by `wxa/msg_sec_check` using the openid of the session.
+///
+/// Risky content is rejected (StatusCode 400), otherwise the body and the result of the check
+/// are extracted, so that content to review can be held by the handler.
+#[derive(Debug, Clone)]
+pub struct WxSecChecked<T>(pub T, pub SecCheckResult);
+impl<T> std::ops::Deref for WxSecChecked<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: SecCheckContent> WxSecChecked<T> {
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) async fn from_request(
+        wx_login: Option<&WxLogin>,
+        login_info: &WxLoginInfo,
+        body: T,
+    ) -> Result<Self, WxLoginErr> {
+        let wx_login = wx_login.ok_or_else(|| {
+            err_resp_with(500, "sec-check-config-fail", SEC_CHECK_FAIL_MSG)(
+                "middleware not configured",
+            )
+        })?;
+        let res = wx_login.check_text(login_info, &body.sec_check()).await?;
+        if res.verdict() == SecCheckVerdict::Risky {
+            return Err(err_resp_with(400, "sec-check-risky", SEC_CHECK_FAIL_MSG)(
+                format!("risky content, label {}", res.result.label),
+            ));
+        }
+        Ok(WxSecChecked(body, res))
+    }
+}
+
+impl WxLogin {
+    /// Check the text posted by the user of the app by `wxa/msg_sec_check` (v2).
+    pub async fn msg_sec_check(
+        &self,
+        appid: &str,
+        openid: &str,
+        check: &TextSecCheck,
+    ) -> Result<SecCheckResult, ApiError> {
+        let manager = self
+            .access_token_manager(appid)
+            .await
+            .map_err(|e| ApiError::Http(e.to_string()))?;
+        let res: SecCheckResult = manager
+            .call(|token| async move {
+                post_json(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/wxa/msg_sec_check"),
+                    &[("access_token", token)],
+                    &proto::MsgSecCheckRequest::new(openid, check),
+                )
+                .await
+            })
+            .await?;
+        if res.verdict() != SecCheckVerdict::Pass {
+            tracing::info!(
+                appid,
+                openid,
+                trace_id = res.trace_id,
+                verdict = ?res.result.suggest,
+                label = res.result.label,
+                "text not passed security check"
+            );
+        }
+        Ok(res)
+    }
+
+    /// Check the text posted by the logged-in user.
+    pub async fn check_text(
+        &self,
+        login_info: &WxLoginInfo,
+        check: &TextSecCheck,
+    ) -> Result<SecCheckResult, WxLoginErr> {
+        self.msg_sec_check(&login_info.appid, &login_info.openid, check)
+            .await
+            .map_err(err_resp_with(
+                500,
+                "sec-check-call-fail",
+                SEC_CHECK_FAIL_MSG,
+            ))
+    }
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub(crate) struct MsgSecCheckRequest<'a> {
+        content: &'a str,
+        version: u8,
+        scene: u8,
+        openid: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nickname: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature: Option<&'a str>,
+    }
+
+    impl<'a> MsgSecCheckRequest<'a> {
+        pub(crate) fn new(openid: &'a str, check: &'a TextSecCheck) -> Self {
+            Self {
+                content: &check.content,
+                version: 2,
+                scene: check.scene.code(),
+                openid,
+                title: check.title.as_deref(),
+                nickname: check.nickname.as_deref(),
+                signature: check.signature.as_deref(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::api::parse_response;
+
+    #[test]
+    fn build_request_and_parse_result() {
+        let check = TextSecCheck::new("some text", SecCheckScene::Forum).with_title("some title");
+        assert_eq!(
+            serde_json::to_value(proto::MsgSecCheckRequest::new("some_openid", &check)).unwrap(),
+            serde_json::json!({
+                "content": "some text",
+                "version": 2,
+                "scene": 3,
+                "openid": "some_openid",
+                "title": "some title",
+            })
+        );
+
+        let res: SecCheckResult = parse_response(
+            br#"{"errcode":0,"errmsg":"ok","trace_id":"some_trace_id",
+                "result":{"suggest":"risky","label":20001},
+                "detail":[{"strategy":"content_model","errcode":0,"suggest":"risky","label":20001,"prob":90},
+                          {"strategy":"keyword","errcode":0,"suggest":"review","label":20006,"level":20,"keyword":"some"},
+                          {"strategy":"keyword","errcode":0,"suggest":"pass","label":100}]}"#,
+        )
+        .unwrap();
+        assert_eq!(res.verdict(), SecCheckVerdict::Risky);
+        assert_eq!(res.labels(), vec![20001, 20006]);
+        assert_eq!(res.detail[1].keyword.as_deref(), Some("some"));
+
+        let res: SecCheckResult = parse_response(
+            br#"{"errcode":0,"errmsg":"ok","trace_id":"some_trace_id","result":{"suggest":"pass","label":100}}"#,
+        )
+        .unwrap();
+        assert_eq!(res.verdict(), SecCheckVerdict::Pass);
+        assert_eq!(res.labels(), vec![SEC_CHECK_LABEL_NORMAL]);
+    }
+}
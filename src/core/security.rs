@@ -7,7 +7,7 @@ use tiny_crypto::{
     sym::{Aes128, Cipher},
 };
 
-use crate::core::config::AppInfo;
+use crate::core::{authz::Claims, config::AppInfo};
 
 const SESSION_TOKEN_TAG: u32 = 0x68686868;
 
@@ -94,23 +94,29 @@ impl<'a> Authority<'a> {
         token_str: &str,
         key: &[u8; 16],
         iv: &[u8; 16],
+        decode: impl FnOnce(&[u8]) -> bincode::Result<SessionToken>,
     ) -> Result<SessionToken, Error> {
         let token_enc = BASE64
             .from_text(token_str)
             .map_err(|e| Error::from(e.to_string()))?;
         let token_bin = Aes128::from_key_array(key).decrypt_with_iv(iv, &token_enc);
-        let sess_token: SessionToken =
-            bincode::deserialize(&token_bin).map_err(|e| Error::from(e.to_string()))?;
+        let sess_token = decode(&token_bin).map_err(|e| Error::from(e.to_string()))?;
         if sess_token.tag != SESSION_TOKEN_TAG {
             return Err(Error::from(format!("bad token tag: {:#x}", sess_token.tag)));
         }
         Ok(sess_token)
     }
 
-    pub fn make_client_session(&self, openid: &str, session_key: &[u8; 16]) -> ClientSession {
+    pub fn make_client_session(
+        &self,
+        openid: &str,
+        session_key: &[u8; 16],
+        claims: Claims,
+        unionid: Option<String>,
+    ) -> ClientSession {
         let token_key = self.make_token_key(openid);
         let token_iv = self.make_token_iv(openid);
-        let sess_token = SessionToken::new(session_key);
+        let sess_token = SessionToken::new(session_key, claims, unionid);
         ClientSession {
             sess_key: self.make_client_sess_key_str(session_key, sess_token.seed),
             sess_token: self.make_client_sess_token_str(&token_key, &token_iv, &sess_token),
@@ -121,19 +127,46 @@ impl<'a> Authority<'a> {
         &self,
         openid: &str,
         token_str: &str,
-    ) -> Result<ServerSession, Error> {
+    ) -> Result<(ServerSession, Claims, Option<String>), Error> {
+        self.auth_session_token(openid, token_str, |token_bin| {
+            bincode::deserialize::<SessionToken>(token_bin)
+        })
+    }
+
+    /// Authenticate a session token of the `ST1` stoken, issued before claims were added.
+    pub fn auth_client_session_v1(
+        &self,
+        openid: &str,
+        token_str: &str,
+    ) -> Result<(ServerSession, Claims, Option<String>), Error> {
+        self.auth_session_token(openid, token_str, |token_bin| {
+            bincode::deserialize::<SessionTokenV1>(token_bin).map(Into::into)
+        })
+    }
+
+    fn auth_session_token(
+        &self,
+        openid: &str,
+        token_str: &str,
+        decode: impl FnOnce(&[u8]) -> bincode::Result<SessionToken>,
+    ) -> Result<(ServerSession, Claims, Option<String>), Error> {
         let token_key = self.make_token_key(openid);
         let token_iv = self.make_token_iv(openid);
-        let sess_token = self.auth_client_sess_token_str(token_str, &token_key, &token_iv)?;
-        Ok(ServerSession {
-            session_key: sess_token.session_key,
-            client_sess_key: self.make_client_sess_key(&sess_token.session_key, sess_token.seed),
-            client_sess_time: UNIX_EPOCH + Duration::from_secs(sess_token.ts as u64),
-        })
+        let sess_token =
+            self.auth_client_sess_token_str(token_str, &token_key, &token_iv, decode)?;
+        Ok((
+            ServerSession {
+                session_key: sess_token.session_key,
+                client_sess_key: self
+                    .make_client_sess_key(&sess_token.session_key, sess_token.seed),
+                client_sess_time: UNIX_EPOCH + Duration::from_secs(sess_token.ts as u64),
+            },
+            sess_token.claims,
+            sess_token.unionid,
+        ))
     }
 
     pub fn auth_client_sig(
-        &self,
         skey: &str,
         url: &str,
         ts_ms_str: &str,
@@ -165,11 +198,13 @@ struct SessionToken {
     seed: u32,
     ts: u32,
     session_key: [u8; 16],
+    claims: Claims,
     tag: u32,
+    unionid: Option<String>,
 }
 
 impl SessionToken {
-    fn new(session_key: &[u8; 16]) -> Self {
+    fn new(session_key: &[u8; 16], claims: Claims, unionid: Option<String>) -> Self {
         Self {
             seed: fastrand::u32(..),
             ts: SystemTime::now()
@@ -177,38 +212,57 @@ impl SessionToken {
                 .unwrap()
                 .as_secs() as u32,
             session_key: *session_key,
+            claims,
             tag: SESSION_TOKEN_TAG,
+            unionid,
         }
     }
 }
 
+/// The session token of `ST1` stokens, issued before claims were added.
+#[derive(Deserialize)]
+struct SessionTokenV1 {
+    seed: u32,
+    ts: u32,
+    session_key: [u8; 16],
+    tag: u32,
+}
+
+impl From<SessionTokenV1> for SessionToken {
+    fn from(token: SessionTokenV1) -> Self {
+        Self {
+            seed: token.seed,
+            ts: token.ts,
+            session_key: token.session_key,
+            claims: Claims::default(),
+            tag: token.tag,
+            unionid: None,
+        }
+    }
+}
+
+/// Generate cryptographically secure random bytes.
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("system random source is unavailable");
+    bytes
+}
+
+/// Compare bytes in constant time, so that signatures can not be guessed by timing.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Check the signature signed by client using skey.
 pub fn check_signature(sig_str: &str, data: &str, session_key: &[u8; 16]) -> bool {
     sha1_hex!(data.as_bytes(), BASE64.to_text(session_key).as_bytes()) == sig_str
 }
 
-/// Decrypt encrypted data returned from WeChat server.
-pub fn decrpyt_data(
-    encrypted_data_base64: &str,
-    iv_base64: &str,
-    session_key: &[u8; 16],
-) -> Result<String, Error> {
-    let encrypted = BASE64
-        .from_text(encrypted_data_base64)
-        .map_err(|e| e.to_string())?;
-    let iv: [u8; 16] = BASE64
-        .from_text(iv_base64)
-        .map_err(|e| e.to_string())?
-        .try_into()
-        .or(Err("iv is not 16B len"))?;
-    let decrypted = Aes128::from_key_array(session_key).decrypt_with_iv(&iv, &encrypted);
-    String::from_utf8(decrypted).map_err(|e| e.to_string().into())
-}
-
 pub mod secret_utils {
     use std::cmp::min;
 
-    #[derive(Default, Clone)]
+    #[derive(Default, Clone, PartialEq, Eq)]
     pub struct SecretString(pub String);
 
     impl std::fmt::Debug for SecretString {
@@ -239,6 +293,8 @@ mod tests {
         let app_info = AppInfo {
             appid: "some_appid".into(),
             secret: SecretString("some_secret".into()),
+            corpid: None,
+            component_appid: None,
         };
         let openid = "some-openid";
         let auth = Authority::new(&app_info);
@@ -247,9 +303,15 @@ mod tests {
             .unwrap()
             .try_into()
             .unwrap();
-        let client_sess = auth.make_client_session(openid, &session_key);
+        let claims = Claims::new().with_role("admin").with_permission("read");
+        let client_sess = auth.make_client_session(
+            openid,
+            &session_key,
+            claims.clone(),
+            Some("some-unionid".into()),
+        );
         println!("client_sess: {:?}", client_sess);
-        let server_sess = auth
+        let (server_sess, server_claims, unionid) = auth
             .auth_client_session(openid, &client_sess.sess_token)
             .unwrap();
         println!("server_sess: {:?}", server_sess);
@@ -257,6 +319,20 @@ mod tests {
             client_sess.sess_key,
             BASE64.to_text(&server_sess.client_sess_key)
         );
+        assert_eq!(claims, server_claims);
+        assert_eq!(unionid.as_deref(), Some("some-unionid"));
+
+        let v1 = (1u32, 2u32, session_key, SESSION_TOKEN_TAG);
+        let token_str = BASE64.to_text(
+            &Aes128::from_key_array(&auth.make_token_key(openid)).encrypt_with_iv(
+                &auth.make_token_iv(openid),
+                &bincode::serialize(&v1).unwrap(),
+            ),
+        );
+        assert!(auth.auth_client_session(openid, &token_str).is_err());
+        let (server_sess, v1_claims, _) = auth.auth_client_session_v1(openid, &token_str).unwrap();
+        assert_eq!(server_sess.session_key, session_key);
+        assert_eq!(v1_claims, Claims::default());
     }
     #[test]
     fn secret_string() {
@@ -278,17 +354,4 @@ mod tests {
             .unwrap();
         assert!(check_signature(sig_str, data, &key));
     }
-    #[test]
-    fn decrypt_data_test() {
-        let encrypted = "CfmlE917TYmWSMDAJ3MZLJTc1ZdTS5S/XUDnf785IlA+4IR80ABSTj+eGqIbqEshNZCAxkid3LnY6VJipJVZN0OeUqWykj0lVFpH7F39jY1a+CkpSwWwMTlCN6Bc57AX/a9phKunccXSLM7X0Nw2VPLxqlRsUrSYfXN5oZpGHbJRVbDsw95mw59N9jPpTY01EhAJZGtKE+W/YOWTXWPQ6IkhRx9WSJxVuVK0nCXvIqQw6zQuSesCurokvMcPWMArKBubLY9vznZ5MUfj51Mptx6UUQoizHbtyNVKEeotMPup6cqh7axP/Y6ae/6Yb7XQW1mEF6SrxzK0C1RgAI2F9JfbKY8Ubl3hlXNydrgHoP+9j/C7aRIRpeWeCUeSOOIqoZzuwN/CYolLIhkjK1POeg==";
-        let iv = "J3IBEDAC0mBW1nQK5F1jFQ==";
-        let key: [u8; 16] = BASE64
-            .from_text("/rwbJA677wrIqaPPLIzwSg==")
-            .unwrap()
-            .try_into()
-            .unwrap();
-        let decrypted = decrpyt_data(encrypted, iv, &key).unwrap();
-        let plain = r#"{"nickName":"韦彬","gender":0,"language":"zh_CN","city":"","province":"","country":"","avatarUrl":"https://thirdwx.qlogo.cn/mmopen/vi_32/DYAIOgq83eq9ld3vawfuoLSHlN39xryF4Tdpsz5fBGfdeiarQkVKxvCnjrsVlmWU59KYJd7vvaKhNgPfREQ9iang/132","watermark":{"timestamp":1708708886,"appid":"wx25581781a863c770"}}"#;
-        assert_eq!(decrypted, plain);
-    }
 }
@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+
+use crate::core::{authz::Claims, login::Error};
+
+/// A login session kept on the server side by a [SessionStore].
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub appid: String,
+    pub openid: String,
+    pub unionid: Option<String>,
+    pub session_key: [u8; 16],
+    pub client_sess_key: [u8; 16],
+    pub claims: Claims,
+    pub created: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// The storage of server-side sessions keyed by opaque session IDs.
+///
+/// If set with [ConfigBuilder::with_session_store](crate::wx_login::ConfigBuilder::with_session_store),
+/// login hands out a random session ID as the stoken instead of the self-contained token,
+/// so sessions are small and can be revoked by removing them from the store.
+#[async_trait]
+pub trait SessionStore: Send + Sync + Debug {
+    /// Save a new session.
+    async fn insert(&self, sid: &str, session: Session) -> Result<(), Error>;
+    /// Get a session, `Ok(None)` means the session does not exist or is expired.
+    async fn get(&self, sid: &str) -> Result<Option<Session>, Error>;
+    /// Update the last-seen time of a session.
+    async fn touch(&self, sid: &str, last_seen: SystemTime) -> Result<(), Error>;
+    /// Remove a session.
+    async fn remove(&self, sid: &str) -> Result<(), Error>;
+    /// Remove all sessions of the user, return the number of removed sessions,
+    /// or `None` if the store can not find sessions by user.
+    ///
+    /// The default implementation returns `None`, stores which can not find sessions
+    /// by user rely on a [RevocationStore](crate::wx_login::RevocationStore) to revoke them.
+    async fn remove_user_sessions(
+        &self,
+        _appid: &str,
+        _openid: &str,
+    ) -> Result<Option<usize>, Error> {
+        Ok(None)
+    }
+}
+
+/// An in-memory [SessionStore] evicting sessions by TTL.
+///
+/// A session expires if it is idle for `idle_ttl` (default 2 hours), or it has lived
+/// for `max_ttl` (default 7 days).
+#[derive(Debug)]
+pub struct MemorySessionStore {
+    idle_ttl: Duration,
+    max_ttl: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self {
+            idle_ttl: Duration::from_secs(2 * 3600),
+            max_ttl: Duration::from_secs(7 * 24 * 3600),
+            sessions: Default::default(),
+        }
+    }
+}
+
+impl MemorySessionStore {
+    /// Create a MemorySessionStore with default TTL.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Set the max idle period of sessions.
+    pub fn with_idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = ttl;
+        self
+    }
+    /// Set the max lifetime of sessions.
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+    /// Get the number of sessions, including expired ones not evicted yet.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+    /// Check if there is no session.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Evict all expired sessions.
+    pub fn evict_expired(&self) {
+        let now = SystemTime::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, session| !self.is_expired(session, now));
+    }
+    fn is_expired(&self, session: &Session, now: SystemTime) -> bool {
+        let elapsed = |t: SystemTime| now.duration_since(t).unwrap_or_default();
+        elapsed(session.last_seen) > self.idle_ttl || elapsed(session.created) > self.max_ttl
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(&self, sid: &str, session: Session) -> Result<(), Error> {
+        self.evict_expired();
+        self.sessions.lock().unwrap().insert(sid.into(), session);
+        Ok(())
+    }
+
+    async fn get(&self, sid: &str) -> Result<Option<Session>, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(sid) {
+            Some(session) if self.is_expired(session, SystemTime::now()) => {
+                sessions.remove(sid);
+                Ok(None)
+            }
+            session => Ok(session.cloned()),
+        }
+    }
+
+    async fn touch(&self, sid: &str, last_seen: SystemTime) -> Result<(), Error> {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(sid) {
+            session.last_seen = last_seen;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, sid: &str) -> Result<(), Error> {
+        self.sessions.lock().unwrap().remove(sid);
+        Ok(())
+    }
+
+    async fn remove_user_sessions(
+        &self,
+        appid: &str,
+        openid: &str,
+    ) -> Result<Option<usize>, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let len = sessions.len();
+        sessions.retain(|_, session| session.appid != appid || session.openid != openid);
+        Ok(Some(len - sessions.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(created: SystemTime) -> Session {
+        Session {
+            appid: "some_appid".into(),
+            openid: "some_openid".into(),
+            unionid: None,
+            session_key: [1; 16],
+            client_sess_key: [2; 16],
+            claims: Claims::default(),
+            created,
+            last_seen: created,
+        }
+    }
+
+    #[test]
+    fn memory_store_ttl() {
+        tokio_test::block_on(async {
+            let store = MemorySessionStore::new()
+                .with_idle_ttl(Duration::from_secs(60))
+                .with_max_ttl(Duration::from_secs(600));
+            let now = SystemTime::now();
+            store.insert("fresh", session(now)).await.unwrap();
+            store
+                .insert("idle", session(now - Duration::from_secs(120)))
+                .await
+                .unwrap();
+            let mut old = session(now - Duration::from_secs(1200));
+            old.last_seen = now;
+            store.insert("old", old).await.unwrap();
+            assert!(store.get("fresh").await.unwrap().is_some());
+            assert!(store.get("idle").await.unwrap().is_none());
+            assert!(store.get("old").await.unwrap().is_none());
+            assert_eq!(store.len(), 1);
+            store.remove("fresh").await.unwrap();
+            assert!(store.is_empty());
+        })
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use tiny_crypto::encoding::{Encoder, BASE64, HEX};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::login::{err_resp_with, WxLoginErr, WxLoginInfo};
+use crate::core::{
+    api::{get_json, ApiError, WX_API_BASE_URL},
+    login::{decode_session_key, WxLogin},
+};
+
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) const CHECK_SESSION_FAIL_MSG: &str = "会话检查失败";
+
+/// The errcode of an invalid signature, i.e. the session_key is not valid anymore.
+const ERRCODE_INVALID_SIGNATURE: i64 = 87009;
+
+/// The validity of the session_key of a login session.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionKeyStatus {
+    pub valid: bool,
+}
+
+impl WxLogin {
+    /// Check if the session_key of the user is still valid by `wxa/checksession`.
+    ///
+    /// The session_key becomes invalid when the client calls `wx.login` again,
+    /// so open data encrypted afterwards can not be decrypted with it.
+    pub async fn check_session_key(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_key: &[u8; 16],
+    ) -> Result<bool, ApiError> {
+        let manager = self.access_token_manager(appid).await.map_err(api_err)?;
+        let res = manager
+            .call(|token| async move {
+                get_json::<_, proto::EmptyResponse>(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/wxa/checksession"),
+                    &proto::SessionKeyRequest::new(token, openid, session_key),
+                )
+                .await
+            })
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.errcode() == Some(ERRCODE_INVALID_SIGNATURE) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reset the session_key of the user by `wxa/resetusersessionkey`, and return the new one.
+    ///
+    /// The given session_key must be still valid.
+    pub async fn reset_session_key(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_key: &[u8; 16],
+    ) -> Result<[u8; 16], ApiError> {
+        let manager = self.access_token_manager(appid).await.map_err(api_err)?;
+        let res: proto::ResetSessionKeyResponse = manager
+            .call(|token| async move {
+                get_json(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/wxa/resetusersessionkey"),
+                    &proto::SessionKeyRequest::new(token, openid, session_key),
+                )
+                .await
+            })
+            .await?;
+        tracing::info!(appid, openid, "session_key reset");
+        decode_session_key(&res.session_key).map_err(|e| ApiError::Http(e.to_string()))
+    }
+
+    /// Check the session_key of the login session, used by the check-session endpoint.
+    #[cfg(any(feature = "axum", feature = "actix-web"))]
+    pub(crate) async fn handle_check_session(
+        &self,
+        login_info: &WxLoginInfo,
+    ) -> Result<SessionKeyStatus, WxLoginErr> {
+        let valid = self
+            .check_session_key(
+                &login_info.appid,
+                &login_info.openid,
+                &login_info.secret.session_key,
+            )
+            .await
+            .map_err(err_resp_with(
+                500,
+                "check-session-call-fail",
+                CHECK_SESSION_FAIL_MSG,
+            ))?;
+        Ok(SessionKeyStatus { valid })
+    }
+}
+
+fn api_err(e: crate::core::login::Error) -> ApiError {
+    ApiError::Http(e.to_string())
+}
+
+/// The signature proving the possession of session_key, which is hmac_sha256(session_key, ""),
+/// keyed by the session_key string in base64 as returned by jscode2session.
+fn session_key_signature(session_key: &[u8; 16]) -> String {
+    HEX.to_text(&hmac_sha256::HMAC::mac(
+        b"",
+        BASE64.to_text(session_key).as_bytes(),
+    ))
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub(crate) struct SessionKeyRequest<'a> {
+        access_token: String,
+        openid: &'a str,
+        signature: String,
+        sig_method: &'a str,
+    }
+
+    impl<'a> SessionKeyRequest<'a> {
+        pub(crate) fn new(access_token: String, openid: &'a str, session_key: &[u8; 16]) -> Self {
+            Self {
+                access_token,
+                openid,
+                signature: session_key_signature(session_key),
+                sig_method: "hmac_sha256",
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct EmptyResponse {}
+
+    #[derive(Deserialize)]
+    pub(crate) struct ResetSessionKeyResponse {
+        pub(crate) session_key: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_with_session_key() {
+        // the session_key of the open data example of WeChat docs, and the expected value
+        // computed by Python: hmac.new(b"tiihtNczf5v6AKRyjwEUhQ==", b"", hashlib.sha256).hexdigest()
+        let session_key = decode_session_key("tiihtNczf5v6AKRyjwEUhQ==").unwrap();
+        assert_eq!(
+            session_key_signature(&session_key),
+            "7154f9f8b4b76b92f541cbf0e37d48840fa06e5e42da92a6f80168a1516a4735"
+        );
+    }
+}
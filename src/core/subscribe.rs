@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    api::{post_json, ApiError, WX_API_BASE_URL},
+    login::{WxLogin, WxLoginInfo},
+};
+
+/// The errcode of sending to a user who refused the subscription.
+pub const ERRCODE_USER_REFUSED: i64 = 43101;
+/// The errcode of bad template data.
+pub const ERRCODE_BAD_TEMPLATE_DATA: i64 = 47003;
+
+/// The mini-program version opened by the message.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MiniProgramState {
+    Developer,
+    Trial,
+    Formal,
+}
+
+/// The language of the message.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    #[serde(rename = "zh_CN")]
+    ZhCN,
+    #[serde(rename = "en_US")]
+    EnUS,
+    #[serde(rename = "zh_HK")]
+    ZhHK,
+    #[serde(rename = "zh_TW")]
+    ZhTW,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+struct DataValue {
+    value: String,
+}
+
+/// A subscribe message of a template.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SubscribeMessage {
+    template_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+    data: BTreeMap<String, DataValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    miniprogram_state: Option<MiniProgramState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<Lang>,
+}
+
+impl SubscribeMessage {
+    /// Create a message of the template.
+    pub fn new(template_id: &str) -> Self {
+        Self {
+            template_id: template_id.into(),
+            page: None,
+            data: BTreeMap::new(),
+            miniprogram_state: None,
+            lang: None,
+        }
+    }
+    /// Set the page opened by the message, e.g. "index?foo=bar".
+    pub fn with_page(mut self, page: &str) -> Self {
+        self.page = Some(page.into());
+        self
+    }
+    /// Set the value of a template keyword, e.g. ("thing1", "some text").
+    pub fn with_data(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.data.insert(
+            key.into(),
+            DataValue {
+                value: value.into(),
+            },
+        );
+        self
+    }
+    /// Set the mini-program version opened by the message, the default is formal.
+    pub fn with_miniprogram_state(mut self, state: MiniProgramState) -> Self {
+        self.miniprogram_state = Some(state);
+        self
+    }
+    /// Set the language of the message, the default is zh_CN.
+    pub fn with_lang(mut self, lang: Lang) -> Self {
+        self.lang = Some(lang);
+        self
+    }
+}
+
+/// The error of sending subscribe messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscribeMessageError {
+    /// The user refused or has not subscribed the template (43101).
+    UserRefused,
+    /// The template data does not match the template (47003).
+    BadTemplateData(String),
+    /// Other errors of calling the API.
+    Api(ApiError),
+}
+impl std::fmt::Display for SubscribeMessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubscribeMessageError::UserRefused => write!(f, "user refused the subscription"),
+            SubscribeMessageError::BadTemplateData(errmsg) => {
+                write!(f, "bad template data: {errmsg}")
+            }
+            SubscribeMessageError::Api(err) => err.fmt(f),
+        }
+    }
+}
+impl std::error::Error for SubscribeMessageError {}
+impl From<ApiError> for SubscribeMessageError {
+    fn from(err: ApiError) -> Self {
+        match err {
+            ApiError::WeChat {
+                errcode: ERRCODE_USER_REFUSED,
+                ..
+            } => SubscribeMessageError::UserRefused,
+            ApiError::WeChat {
+                errcode: ERRCODE_BAD_TEMPLATE_DATA,
+                errmsg,
+            } => SubscribeMessageError::BadTemplateData(errmsg),
+            err => SubscribeMessageError::Api(err),
+        }
+    }
+}
+
+/// A client sending subscribe messages to a user, usually created by
+/// [WxLogin::subscribe_message_client] for the logged-in user.
+#[derive(Debug, Clone)]
+pub struct SubscribeMessageClient {
+    wx_login: WxLogin,
+    appid: String,
+    openid: String,
+}
+
+impl SubscribeMessageClient {
+    /// Create a client sending messages to the user of the app.
+    pub fn new(wx_login: WxLogin, appid: &str, openid: &str) -> Self {
+        Self {
+            wx_login,
+            appid: appid.into(),
+            openid: openid.into(),
+        }
+    }
+
+    /// Send a message by `message/subscribe/send`.
+    pub async fn send(&self, msg: &SubscribeMessage) -> Result<(), SubscribeMessageError> {
+        let manager = self
+            .wx_login
+            .access_token_manager(&self.appid)
+            .await
+            .map_err(|e| ApiError::Http(e.to_string()))?;
+        let _: proto::SendResponse = manager
+            .call(|token| async move {
+                post_json(
+                    &self.wx_login.http_client(),
+                    &format!("{WX_API_BASE_URL}/cgi-bin/message/subscribe/send"),
+                    &[("access_token", token)],
+                    &proto::SendRequest {
+                        touser: &self.openid,
+                        msg,
+                    },
+                )
+                .await
+            })
+            .await?;
+        tracing::info!(
+            appid = self.appid,
+            openid = self.openid,
+            template_id = msg.template_id,
+            "subscribe message sent"
+        );
+        Ok(())
+    }
+}
+
+impl WxLogin {
+    /// Create a client sending subscribe messages to the logged-in user.
+    pub fn subscribe_message_client(&self, login_info: &WxLoginInfo) -> SubscribeMessageClient {
+        SubscribeMessageClient::new(self.clone(), &login_info.appid, &login_info.openid)
+    }
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub(crate) struct SendRequest<'a> {
+        pub(crate) touser: &'a str,
+        #[serde(flatten)]
+        pub(crate) msg: &'a SubscribeMessage,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub(crate) struct SendResponse {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::api::parse_response;
+
+    #[test]
+    fn build_message_and_map_errors() {
+        let msg = SubscribeMessage::new("some_template")
+            .with_page("index?foo=bar")
+            .with_data("thing1", "some text")
+            .with_data("time2", "2024-01-01 12:00")
+            .with_miniprogram_state(MiniProgramState::Trial)
+            .with_lang(Lang::EnUS);
+        let req = proto::SendRequest {
+            touser: "some_openid",
+            msg: &msg,
+        };
+        assert_eq!(
+            serde_json::to_value(&req).unwrap(),
+            serde_json::json!({
+                "touser": "some_openid",
+                "template_id": "some_template",
+                "page": "index?foo=bar",
+                "data": {"thing1": {"value": "some text"}, "time2": {"value": "2024-01-01 12:00"}},
+                "miniprogram_state": "trial",
+                "lang": "en_US",
+            })
+        );
+
+        let err = |body: &[u8]| {
+            SubscribeMessageError::from(parse_response::<proto::SendResponse>(body).unwrap_err())
+        };
+        assert_eq!(
+            err(br#"{"errcode":43101,"errmsg":"user refuse to accept the msg"}"#),
+            SubscribeMessageError::UserRefused
+        );
+        assert!(matches!(
+            err(br#"{"errcode":47003,"errmsg":"argument invalid! data.thing1.value invalid"}"#),
+            SubscribeMessageError::BadTemplateData(_)
+        ));
+        assert!(matches!(
+            err(br#"{"errcode":40003,"errmsg":"invalid openid"}"#),
+            SubscribeMessageError::Api(ApiError::WeChat { errcode: 40003, .. })
+        ));
+        assert!(parse_response::<proto::SendResponse>(br#"{"errcode":0,"errmsg":"ok"}"#).is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, fmt::Debug, sync::Mutex, time::SystemTime};
+
+use async_trait::async_trait;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use tiny_crypto::encoding::{Encoder, HEX};
+
+use crate::core::login::Error;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+use crate::core::security::random_bytes;
+
+/// The storage of short-lived tickets, e.g. those of scan-to-login, keyed by random ticket IDs.
+///
+/// Values are opaque JSON strings, and tickets must not be returned after they expire.
+/// Set with [ConfigBuilder::with_ticket_store](crate::wx_login::ConfigBuilder::with_ticket_store)
+/// to share tickets between replicas.
+#[async_trait]
+pub trait TicketStore: Send + Sync + Debug {
+    /// Save a new ticket until it expires.
+    async fn insert(&self, ticket: &str, value: String, expires: SystemTime) -> Result<(), Error>;
+    /// Get a ticket, `Ok(None)` means the ticket does not exist or is expired.
+    async fn get(&self, ticket: &str) -> Result<Option<String>, Error>;
+    /// Replace the value of a ticket keeping its expiry, only if its current value is `expected`,
+    /// return false if it does not exist or has been changed.
+    ///
+    /// It must be atomic (e.g. a compare-and-set script of Redis), so that concurrent updates
+    /// of the same value can not both succeed.
+    async fn update_if(&self, ticket: &str, expected: &str, value: String) -> Result<bool, Error>;
+    /// Remove and return a ticket atomically, so that a ticket can be redeemed only once.
+    async fn take(&self, ticket: &str) -> Result<Option<String>, Error>;
+}
+
+/// An in-memory [TicketStore].
+#[derive(Debug, Default)]
+pub struct MemoryTicketStore {
+    tickets: Mutex<HashMap<String, (String, SystemTime)>>,
+}
+
+impl MemoryTicketStore {
+    /// Create an empty MemoryTicketStore.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Get the number of tickets, including expired ones not evicted yet.
+    pub fn len(&self) -> usize {
+        self.tickets.lock().unwrap().len()
+    }
+    /// Check if there is no ticket.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Evict all expired tickets.
+    pub fn evict_expired(&self) {
+        let now = SystemTime::now();
+        self.tickets
+            .lock()
+            .unwrap()
+            .retain(|_, (_, expires)| *expires > now);
+    }
+}
+
+#[async_trait]
+impl TicketStore for MemoryTicketStore {
+    async fn insert(&self, ticket: &str, value: String, expires: SystemTime) -> Result<(), Error> {
+        self.evict_expired();
+        self.tickets
+            .lock()
+            .unwrap()
+            .insert(ticket.into(), (value, expires));
+        Ok(())
+    }
+
+    async fn get(&self, ticket: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .get(ticket)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(value, _)| value.clone()))
+    }
+
+    async fn update_if(&self, ticket: &str, expected: &str, value: String) -> Result<bool, Error> {
+        match self.tickets.lock().unwrap().get_mut(ticket) {
+            Some((old, expires)) if *expires > SystemTime::now() && old == expected => {
+                *old = value;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn take(&self, ticket: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .remove(ticket)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(value, _)| value))
+    }
+}
+
+/// Generate a random ticket ID of 24 hex chars.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn new_ticket() -> String {
+    HEX.to_text(&random_bytes::<12>())
+}
+
+/// Check if the ticket ID sent by a client is of the format of [new_ticket].
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn is_ticket(ticket: &str) -> bool {
+    ticket.len() == 24 && ticket.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Get the key of a ticket in the store, namespaced by the flow (e.g. `handoff`),
+/// so that a flow can only read and consume its own tickets.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn ticket_key(flow: &str, ticket: &str) -> String {
+    format!("{flow}:{ticket}")
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn store_tickets() {
+        tokio_test::block_on(async {
+            let store = MemoryTicketStore::new();
+            let ticket = new_ticket();
+            assert!(is_ticket(&ticket));
+            assert!(!is_ticket("component_verify_ticket:x"));
+            assert_eq!(ticket_key("handoff", &ticket), format!("handoff:{ticket}"));
+            let expires = SystemTime::now() + Duration::from_secs(60);
+            store.insert(&ticket, "a".into(), expires).await.unwrap();
+            assert_eq!(store.get(&ticket).await.unwrap().as_deref(), Some("a"));
+            assert!(store.update_if(&ticket, "a", "b".into()).await.unwrap());
+            assert!(!store.update_if(&ticket, "a", "c".into()).await.unwrap());
+            assert!(!store.update_if("other", "b", "c".into()).await.unwrap());
+            assert_eq!(store.take(&ticket).await.unwrap().as_deref(), Some("b"));
+            assert!(store.take(&ticket).await.unwrap().is_none());
+
+            let expired = SystemTime::now() - Duration::from_secs(1);
+            store.insert(&ticket, "a".into(), expired).await.unwrap();
+            assert!(store.get(&ticket).await.unwrap().is_none());
+            assert!(!store.update_if(&ticket, "a", "b".into()).await.unwrap());
+            store.evict_expired();
+            assert!(store.is_empty());
+        })
+    }
+}
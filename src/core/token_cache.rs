@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{access_token::AccessToken, login::Error};
+
+/// The cache of access_token shared by all [AccessTokenManager](crate::wx_login::AccessTokenManager)s
+/// using it, possibly across processes and hosts.
+///
+/// Besides storing tokens, the cache provides a lock so that only one manager refreshes
+/// the token of an app at a time, which is required since fetching a new token from
+/// `cgi-bin/token` invalidates the previous one. The protocol of a manager is:
+///
+/// 1. [get](Self::get) the token, use it if it is not about to expire;
+/// 2. otherwise [try_lock](Self::try_lock) the app, and if acquired, fetch a new token,
+///    [put](Self::put) it and [unlock](Self::unlock) the app;
+/// 3. if not acquired, wait and go back to step 1.
+///
+/// A lock must expire after its ttl, so that a crashed holder can not block others forever.
+/// For a Redis-backed implementation, `try_lock` maps to `SET <lock-key> <holder> NX PX <ttl>`,
+/// and `unlock`/`remove` map to compare-and-delete scripts.
+///
+/// The cache also keeps the component_verify_ticket of third-party platforms, which is pushed
+/// to any replica and used by the one refreshing the component_access_token.
+/// `put_ticket` maps to `SET <ticket-key> <ticket> PXAT <expires_at>`.
+#[async_trait]
+pub trait AccessTokenCache: Send + Sync + Debug {
+    /// Get the cached token of the app.
+    async fn get(&self, appid: &str) -> Result<Option<AccessToken>, Error>;
+    /// Save the token of the app.
+    async fn put(&self, appid: &str, token: &AccessToken) -> Result<(), Error>;
+    /// Remove the cached token of the app if it is still the given one.
+    async fn remove(&self, appid: &str, token: &str) -> Result<(), Error>;
+    /// Try to acquire the refresh lock of the app for `ttl`, return whether it is acquired.
+    async fn try_lock(&self, appid: &str, holder: &str, ttl: Duration) -> Result<bool, Error>;
+    /// Release the refresh lock of the app if it is held by the holder.
+    async fn unlock(&self, appid: &str, holder: &str) -> Result<(), Error>;
+    /// Get the latest component_verify_ticket of the component, `Ok(None)` if there is none
+    /// or it is expired.
+    async fn get_ticket(&self, component_appid: &str) -> Result<Option<String>, Error>;
+    /// Save the latest component_verify_ticket of the component until it expires.
+    async fn put_ticket(
+        &self,
+        component_appid: &str,
+        ticket: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), Error>;
+}
+
+/// An in-process [AccessTokenCache], which can be shared by managers of the same process.
+#[derive(Debug, Default)]
+pub struct MemoryTokenCache {
+    tokens: Mutex<HashMap<String, AccessToken>>,
+    locks: Mutex<HashMap<String, (String, Instant)>>,
+    tickets: Mutex<HashMap<String, (String, SystemTime)>>,
+}
+
+impl MemoryTokenCache {
+    /// Create an empty MemoryTokenCache.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl AccessTokenCache for MemoryTokenCache {
+    async fn get(&self, appid: &str) -> Result<Option<AccessToken>, Error> {
+        Ok(self.tokens.lock().unwrap().get(appid).cloned())
+    }
+
+    async fn put(&self, appid: &str, token: &AccessToken) -> Result<(), Error> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(appid.into(), token.clone());
+        Ok(())
+    }
+
+    async fn remove(&self, appid: &str, token: &str) -> Result<(), Error> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.get(appid).is_some_and(|t| t.token == token) {
+            tokens.remove(appid);
+        }
+        Ok(())
+    }
+
+    async fn try_lock(&self, appid: &str, holder: &str, ttl: Duration) -> Result<bool, Error> {
+        let mut locks = self.locks.lock().unwrap();
+        let now = Instant::now();
+        match locks.get(appid) {
+            Some((lock_holder, expire)) if lock_holder != holder && *expire > now => Ok(false),
+            _ => {
+                locks.insert(appid.into(), (holder.into(), now + ttl));
+                Ok(true)
+            }
+        }
+    }
+
+    async fn unlock(&self, appid: &str, holder: &str) -> Result<(), Error> {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.get(appid).is_some_and(|(h, _)| h == holder) {
+            locks.remove(appid);
+        }
+        Ok(())
+    }
+
+    async fn get_ticket(&self, component_appid: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .get(component_appid)
+            .filter(|(_, expires_at)| *expires_at > SystemTime::now())
+            .map(|(ticket, _)| ticket.clone()))
+    }
+
+    async fn put_ticket(
+        &self,
+        component_appid: &str,
+        ticket: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), Error> {
+        self.tickets
+            .lock()
+            .unwrap()
+            .insert(component_appid.into(), (ticket.into(), expires_at));
+        Ok(())
+    }
+}
+
+/// A file-backed [AccessTokenCache], which can be shared by processes on the same host.
+///
+/// The token of an app is saved in `<dir>/<appid>.token`, the component_verify_ticket of a component
+/// in `<dir>/<component_appid>.ticket`, and the lock of an app in `<dir>/<appid>.lock`,
+/// which is only read and written under an exclusive `flock` of `<dir>/<appid>.guard`.
+/// Hosts mounting the same directory may share it only if the file system supports `flock`
+/// across them, otherwise use a cache with a real distributed lock, e.g. Redis.
+#[derive(Debug)]
+pub struct FileTokenCache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileToken {
+    token: String,
+    expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileTicket {
+    ticket: String,
+    expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileLock {
+    holder: String,
+    expires_at_ms: u128,
+}
+
+impl FileTokenCache {
+    /// Create a FileTokenCache in the directory, which is created if not exists.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("create token cache dir {} fail: {e}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, appid: &str, ext: &str) -> Result<PathBuf, Error> {
+        let is_safe = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                && !s.contains("..")
+        };
+        if !is_safe(appid) || !is_safe(ext) || appid.contains('.') {
+            return Err(format!("bad appid or holder for file token cache: {appid}.{ext}").into());
+        }
+        Ok(self.dir.join(format!("{appid}.{ext}")))
+    }
+
+    async fn read_json<T: for<'de> Deserialize<'de>>(path: &PathBuf) -> Result<Option<T>, Error> {
+        match tokio::fs::read(path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content).ok()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("read {} fail: {e}", path.display()).into()),
+        }
+    }
+
+    /// Write the file by renaming a temporary file, so that readers never see a partial one.
+    async fn write_json(
+        &self,
+        appid: &str,
+        ext: &str,
+        value: &impl Serialize,
+    ) -> Result<(), Error> {
+        let path = self.path(appid, ext)?;
+        let tmp_path = self.path(appid, &format!("{ext}.tmp"))?;
+        let content = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+        tokio::fs::write(&tmp_path, content)
+            .await
+            .map_err(|e| format!("write {} fail: {e}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| format!("rename to {} fail: {e}", path.display()))?;
+        Ok(())
+    }
+
+    /// Run `f` on the lock file of the app while holding an exclusive `flock` of
+    /// `<appid>.guard`, so that checking and changing the lock is atomic to other processes.
+    async fn with_guard<T: Send + 'static>(
+        &self,
+        appid: &str,
+        f: impl FnOnce(&Path) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let guard_path = self.path(appid, "guard")?;
+        let path = self.path(appid, "lock")?;
+        tokio::task::spawn_blocking(move || {
+            let guard = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&guard_path)
+                .map_err(|e| format!("open {} fail: {e}", guard_path.display()))?;
+            guard
+                .lock()
+                .map_err(|e| format!("lock {} fail: {e}", guard_path.display()))?;
+            // the flock is released as the guard file is closed
+            f(&path)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    fn read_lock(path: &Path) -> Result<Option<FileLock>, Error> {
+        match std::fs::read(path) {
+            Ok(content) => Ok(serde_json::from_slice(&content).ok()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("read {} fail: {e}", path.display()).into()),
+        }
+    }
+
+    async fn remove_file(path: &PathBuf) -> Result<(), Error> {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(format!("remove {} fail: {e}", path.display()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn unix_ms(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+#[async_trait]
+impl AccessTokenCache for FileTokenCache {
+    async fn get(&self, appid: &str) -> Result<Option<AccessToken>, Error> {
+        let file_token: Option<FileToken> = Self::read_json(&self.path(appid, "token")?).await?;
+        Ok(file_token.map(|t| AccessToken {
+            token: t.token,
+            expires_at: UNIX_EPOCH + Duration::from_secs(t.expires_at),
+        }))
+    }
+
+    async fn put(&self, appid: &str, token: &AccessToken) -> Result<(), Error> {
+        let file_token = FileToken {
+            token: token.token.clone(),
+            expires_at: (unix_ms(token.expires_at) / 1000) as u64,
+        };
+        self.write_json(appid, "token", &file_token).await
+    }
+
+    async fn remove(&self, appid: &str, token: &str) -> Result<(), Error> {
+        let path = self.path(appid, "token")?;
+        let file_token: Option<FileToken> = Self::read_json(&path).await?;
+        if file_token.is_some_and(|t| t.token == token) {
+            Self::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    async fn try_lock(&self, appid: &str, holder: &str, ttl: Duration) -> Result<bool, Error> {
+        let tmp_path = self.path(appid, "lock.tmp")?;
+        let lock = FileLock {
+            holder: holder.into(),
+            expires_at_ms: unix_ms(SystemTime::now() + ttl),
+        };
+        self.with_guard(appid, move |path| {
+            let now = unix_ms(SystemTime::now());
+            match Self::read_lock(path)? {
+                Some(l) if l.holder != lock.holder && l.expires_at_ms > now => Ok(false),
+                // the lock is free, expired (or corrupted), or held by the holder itself
+                _ => {
+                    let content = serde_json::to_vec(&lock).map_err(|e| e.to_string())?;
+                    std::fs::write(&tmp_path, content)
+                        .map_err(|e| format!("write {} fail: {e}", tmp_path.display()))?;
+                    std::fs::rename(&tmp_path, path)
+                        .map_err(|e| format!("rename to {} fail: {e}", path.display()))?;
+                    Ok(true)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn unlock(&self, appid: &str, holder: &str) -> Result<(), Error> {
+        let holder = holder.to_string();
+        self.with_guard(appid, move |path| {
+            if Self::read_lock(path)?.is_some_and(|l| l.holder == holder) {
+                match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(format!("remove {} fail: {e}", path.display()).into())
+                    }
+                    _ => (),
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_ticket(&self, component_appid: &str) -> Result<Option<String>, Error> {
+        let file_ticket: Option<FileTicket> =
+            Self::read_json(&self.path(component_appid, "ticket")?).await?;
+        let now = (unix_ms(SystemTime::now()) / 1000) as u64;
+        Ok(file_ticket.filter(|t| t.expires_at > now).map(|t| t.ticket))
+    }
+
+    async fn put_ticket(
+        &self,
+        component_appid: &str,
+        ticket: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), Error> {
+        let file_ticket = FileTicket {
+            ticket: ticket.into(),
+            expires_at: (unix_ms(expires_at) / 1000) as u64,
+        };
+        self.write_json(component_appid, "ticket", &file_ticket)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn check_cache(cache: &dyn AccessTokenCache) {
+        let token = AccessToken {
+            token: "some_token".into(),
+            expires_at: UNIX_EPOCH + Duration::from_secs(2000000000),
+        };
+        assert!(cache.get("appid").await.unwrap().is_none());
+        cache.put("appid", &token).await.unwrap();
+        assert_eq!(cache.get("appid").await.unwrap(), Some(token));
+        cache.remove("appid", "other_token").await.unwrap();
+        assert!(cache.get("appid").await.unwrap().is_some());
+        cache.remove("appid", "some_token").await.unwrap();
+        assert!(cache.get("appid").await.unwrap().is_none());
+
+        let ttl = Duration::from_secs(10);
+        assert!(cache.try_lock("appid", "holder1", ttl).await.unwrap());
+        assert!(!cache.try_lock("appid", "holder2", ttl).await.unwrap());
+        cache.unlock("appid", "holder2").await.unwrap();
+        assert!(!cache.try_lock("appid", "holder2", ttl).await.unwrap());
+        cache.unlock("appid", "holder1").await.unwrap();
+        assert!(cache.try_lock("appid", "holder2", ttl).await.unwrap());
+        cache.unlock("appid", "holder2").await.unwrap();
+
+        assert!(cache
+            .try_lock("appid", "holder1", Duration::ZERO)
+            .await
+            .unwrap());
+        assert!(cache.try_lock("appid", "holder2", ttl).await.unwrap());
+        cache.unlock("appid", "holder2").await.unwrap();
+
+        assert!(cache.get_ticket("component_appid").await.unwrap().is_none());
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        cache
+            .put_ticket("component_appid", "some_ticket", expires_at)
+            .await
+            .unwrap();
+        let ticket = cache.get_ticket("component_appid").await.unwrap();
+        assert_eq!(ticket.as_deref(), Some("some_ticket"));
+        assert!(cache.get("component_appid").await.unwrap().is_none());
+        cache
+            .put_ticket("component_appid", "old_ticket", SystemTime::now())
+            .await
+            .unwrap();
+        assert!(cache.get_ticket("component_appid").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn memory_cache() {
+        tokio_test::block_on(check_cache(&MemoryTokenCache::new()));
+    }
+
+    #[test]
+    fn file_cache() {
+        let dir = std::env::temp_dir().join(format!("wx_login_token_cache_{}", std::process::id()));
+        let cache = FileTokenCache::new(&dir).unwrap();
+        tokio_test::block_on(async {
+            check_cache(&cache).await;
+            assert!(cache.get("../appid").await.is_err());
+
+            assert!(cache
+                .try_lock("appid", "holder1", Duration::ZERO)
+                .await
+                .unwrap());
+        });
+        // holders of other processes contend for the expired lock of holder1, only one wins
+        for round in 0..10 {
+            let winners = std::thread::scope(|s| {
+                let holders = (2..5).map(|i| {
+                    let dir = &dir;
+                    s.spawn(move || {
+                        let cache = FileTokenCache::new(dir).unwrap();
+                        let holder = format!("holder{i}-{round}");
+                        let ttl = Duration::from_secs(10);
+                        tokio_test::block_on(cache.try_lock("appid", &holder, ttl)).unwrap()
+                    })
+                });
+                let holders: Vec<_> = holders.collect();
+                holders
+                    .into_iter()
+                    .map(|h| h.join().unwrap())
+                    .filter(|&won| won)
+                    .count()
+            });
+            assert_eq!(winners, 1);
+            tokio_test::block_on(async {
+                for i in 2..5 {
+                    cache
+                        .unlock("appid", &format!("holder{i}-{round}"))
+                        .await
+                        .unwrap();
+                }
+                assert!(cache
+                    .try_lock("appid", "holder1", Duration::ZERO)
+                    .await
+                    .unwrap());
+            });
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+
+use crate::core::{
+    api::get_json,
+    config::AppInfo,
+    login::{decode_session_key, WxLogin, WxLoginErr, WxLoginOk, LOGIN_ERR},
+};
+
+pub(crate) const WECOM_API_BASE_URL: &str = "https://qyapi.weixin.qq.com";
+
+/// The key of the member of the corp passed to the claims resolver as openid, not taken as an openid.
+pub(crate) fn wecom_claims_key(corpid: &str, userid: &str) -> String {
+    format!("wecom:{corpid}:{userid}")
+}
+
+impl WxLogin {
+    /// Handle login of the mini-program of WeCom, exchanging the code of `wx.qy.login`
+    /// by `cgi-bin/miniprogram/jscode2session` with the corp access_token.
+    ///
+    /// The session is of the userid (kept as openid) of the corp, and the claims are resolved
+    /// by the [wecom_claims_key] of the member.
+    pub(crate) async fn handle_wecom_login(
+        &self,
+        app_info: &AppInfo,
+        code: &str,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        let corpid = app_info
+            .corpid
+            .as_deref()
+            .ok_or_else(|| LOGIN_ERR.err(500, "wecom-corpid-missing", &app_info.appid))?;
+        let manager = self
+            .access_token_manager(&app_info.appid)
+            .await
+            .map_err(|e| LOGIN_ERR.err(500, "access-token-fail", e))?;
+        let res: proto::Code2SessionResponse = manager
+            .call(|token| async move {
+                get_json(
+                    &self.http_client(),
+                    &format!("{WECOM_API_BASE_URL}/cgi-bin/miniprogram/jscode2session"),
+                    &[
+                        ("access_token", token.as_str()),
+                        ("js_code", code),
+                        ("grant_type", "authorization_code"),
+                    ],
+                )
+                .await
+            })
+            .await
+            .map_err(|e| LOGIN_ERR.err(401, "wecom-jscode2session-fail", e))?;
+        tracing::info!(
+            corpid = res.corpid,
+            userid = res.userid,
+            "wecom jscode2session ok"
+        );
+        if res.corpid != corpid {
+            return Err(LOGIN_ERR.err(401, "wecom-corp-mismatch", &res.corpid));
+        }
+        let session_key = decode_session_key(&res.session_key)
+            .map_err(|e| LOGIN_ERR.err(500, "session-key-invalid-base64", e))?;
+        let claims = self
+            .resolve_claims(&app_info.appid, &wecom_claims_key(corpid, &res.userid))
+            .await
+            .map_err(|e| LOGIN_ERR.err(500, "resolve-claims-fail", e))?;
+        self.issue_session(app_info, res.userid, None, session_key, claims)
+            .await
+    }
+}
+
+mod proto {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    pub(crate) struct Code2SessionResponse {
+        pub(crate) corpid: String,
+        pub(crate) userid: String,
+        pub(crate) session_key: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        access_token::AccessTokenApi,
+        api::{parse_response, ApiError},
+        authz::Claims,
+        login::tests::{test_login, test_wx_login},
+    };
+
+    #[test]
+    fn wecom_session() {
+        tokio_test::block_on(async {
+            let res: proto::Code2SessionResponse = parse_response(
+                br#"{"corpid":"CORPID","userid":"USERID","session_key":"kJtdi6RF+Dv67QkbLlPGjw==","errcode":0,"errmsg":"ok"}"#,
+            )
+            .unwrap();
+            assert_eq!(res.userid, "USERID");
+            let err = parse_response::<proto::Code2SessionResponse>(
+                br#"{"errcode":40014,"errmsg":"invalid access_token"}"#,
+            )
+            .unwrap_err();
+            assert!(err.is_access_token_invalid());
+            assert!(matches!(err, ApiError::WeChat { errcode: 40014, .. }));
+
+            let app_info = AppInfo::wecom(
+                "some_appid".into(),
+                "some_corpid".into(),
+                "some_corpsecret".into(),
+            );
+            let wx_login = test_wx_login(&app_info, |b| b);
+            let manager = wx_login.access_token_manager("some_appid").await.unwrap();
+            assert_eq!(manager.api(), AccessTokenApi::WeCom);
+
+            let (_, login_info) =
+                test_login(&wx_login, &app_info, "some_userid", Claims::new()).await;
+            assert_eq!(login_info.corpid.as_deref(), Some("some_corpid"));
+            assert_eq!(login_info.userid.as_deref(), Some("some_userid"));
+            assert_eq!(login_info.openid, "some_userid");
+            assert_eq!(
+                wecom_claims_key("some_corpid", "some_userid"),
+                "wecom:some_corpid:some_userid"
+            );
+        })
+    }
+}
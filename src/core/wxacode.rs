@@ -0,0 +1,275 @@
+use serde::Serialize;
+
+use crate::core::{
+    api::{parse_response, post_bytes, ApiError, WX_API_BASE_URL},
+    login::WxLogin,
+};
+
+/// The max length of the scene of a mini-program code.
+pub const SCENE_MAX_LEN: usize = 32;
+
+/// The punctuations allowed in the scene besides digits and letters.
+const SCENE_PUNCTUATIONS: &str = "!#$&'()*+,/:;=?@-._~";
+
+/// The mini-program version opened by the code.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvVersion {
+    Release,
+    Trial,
+    Develop,
+}
+
+/// The RGB color of the lines of a mini-program code.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// A request of an unlimited mini-program code, by `wxa/getwxacodeunlimit`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WxaCode {
+    scene: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check_path: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env_version: Option<EnvVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auto_color: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_color: Option<LineColor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_hyaline: Option<bool>,
+}
+
+impl WxaCode {
+    /// Create a request of the code with the scene, which can be encoded by [encode_scene].
+    pub fn new(scene: &str) -> Self {
+        Self {
+            scene: scene.into(),
+            page: None,
+            check_path: None,
+            env_version: None,
+            width: None,
+            auto_color: None,
+            line_color: None,
+            is_hyaline: None,
+        }
+    }
+    /// Set the page opened by the code, e.g. "pages/index/index", the default is the home page.
+    pub fn with_page(mut self, page: &str) -> Self {
+        self.page = Some(page.into());
+        self
+    }
+    /// Set whether to check the page exists in the released version, the default is true.
+    pub fn with_check_path(mut self, check_path: bool) -> Self {
+        self.check_path = Some(check_path);
+        self
+    }
+    /// Set the mini-program version opened by the code, the default is release.
+    pub fn with_env_version(mut self, env_version: EnvVersion) -> Self {
+        self.env_version = Some(env_version);
+        self
+    }
+    /// Set the width of the code in px, from 280 to 1280, the default is 430.
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = Some(width);
+        self
+    }
+    /// Set whether to color the lines automatically, the default is false.
+    pub fn with_auto_color(mut self, auto_color: bool) -> Self {
+        self.auto_color = Some(auto_color);
+        self
+    }
+    /// Set the color of the lines, used if auto color is false.
+    pub fn with_line_color(mut self, r: u8, g: u8, b: u8) -> Self {
+        self.line_color = Some(LineColor { r, g, b });
+        self
+    }
+    /// Set whether the background is transparent, the default is false.
+    pub fn with_is_hyaline(mut self, is_hyaline: bool) -> Self {
+        self.is_hyaline = Some(is_hyaline);
+        self
+    }
+}
+
+/// The image of a mini-program code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WxaCodeImage {
+    /// The content type, e.g. "image/jpeg", or "image/png" for transparent background.
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl WxLogin {
+    /// Get an unlimited mini-program code of the app by `wxa/getwxacodeunlimit`.
+    pub async fn get_wxa_code_unlimited(
+        &self,
+        appid: &str,
+        code: &WxaCode,
+    ) -> Result<WxaCodeImage, ApiError> {
+        if let Err(e) = check_scene(&code.scene) {
+            return Err(ApiError::Http(e));
+        }
+        let manager = self
+            .access_token_manager(appid)
+            .await
+            .map_err(|e| ApiError::Http(e.to_string()))?;
+        manager
+            .call(|token| async move {
+                let (content_type, data) = post_bytes(
+                    &self.http_client(),
+                    &format!("{WX_API_BASE_URL}/wxa/getwxacodeunlimit"),
+                    &[("access_token", token)],
+                    code,
+                )
+                .await?;
+                parse_image_response(content_type, data)
+            })
+            .await
+    }
+}
+
+/// Parse the response of code APIs, which is JSON on failure and image on success.
+fn parse_image_response(content_type: String, data: Vec<u8>) -> Result<WxaCodeImage, ApiError> {
+    if content_type.starts_with("image/") {
+        return Ok(WxaCodeImage { content_type, data });
+    }
+    parse_response::<serde_json::Value>(&data)?;
+    Err(ApiError::Http(format!(
+        "unexpected response of content type {content_type:?}"
+    )))
+}
+
+/// Check if the scene is valid: at most 32 chars of digits, letters and `!#$&'()*+,/:;=?@-._~`.
+pub fn check_scene(scene: &str) -> Result<(), String> {
+    if scene.len() > SCENE_MAX_LEN {
+        return Err(format!(
+            "scene longer than {SCENE_MAX_LEN} chars: {}",
+            scene.len()
+        ));
+    }
+    match scene
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !SCENE_PUNCTUATIONS.contains(*c))
+    {
+        Some(c) => Err(format!("invalid char in scene: {c:?}")),
+        None => Ok(()),
+    }
+}
+
+/// Encode params into a scene like `k1=v1&k2=v2`.
+///
+/// Keys and values can not contain `&` or `=`, and the scene must be valid by [check_scene].
+pub fn encode_scene(params: &[(&str, &str)]) -> Result<String, String> {
+    if let Some((k, v)) = params
+        .iter()
+        .find(|(k, v)| k.is_empty() || k.contains(['&', '=']) || v.contains(['&', '=']))
+    {
+        return Err(format!("invalid param in scene: {k:?}={v:?}"));
+    }
+    let scene = params
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&");
+    check_scene(&scene)?;
+    Ok(scene)
+}
+
+/// Decode a scene encoded by [encode_scene] into params.
+///
+/// The scene received by the mini-program is URL-encoded, so it is percent-decoded first.
+pub fn decode_scene(scene: &str) -> Result<Vec<(String, String)>, String> {
+    let scene = percent_decode(scene)?;
+    if scene.is_empty() {
+        return Ok(vec![]);
+    }
+    scene
+        .split('&')
+        .map(|kv| match kv.split_once('=') {
+            Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+            _ => Err(format!("invalid param in scene: {kv:?}")),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| format!("bad percent-encoding in scene: {s:?}"))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_scene() {
+        let scene = encode_scene(&[("id", "123"), ("from", "share")]).unwrap();
+        assert_eq!(scene, "id=123&from=share");
+        assert_eq!(
+            decode_scene(&scene).unwrap(),
+            vec![("id".into(), "123".into()), ("from".into(), "share".into())]
+        );
+        assert_eq!(
+            decode_scene("id%3D123%26from%3Dshare").unwrap(),
+            decode_scene(&scene).unwrap()
+        );
+        assert!(decode_scene("").unwrap().is_empty());
+        assert!(decode_scene("id").is_err());
+        assert!(decode_scene("id%3").is_err());
+
+        assert!(encode_scene(&[("id", "a&b")]).is_err());
+        assert!(encode_scene(&[("id", "中文")]).is_err());
+        assert!(encode_scene(&[("id", &"1".repeat(30))]).is_err());
+        assert!(check_scene(&"a".repeat(SCENE_MAX_LEN)).is_ok());
+    }
+
+    #[test]
+    fn build_request_and_parse_image() {
+        let code = WxaCode::new("id=123")
+            .with_page("pages/index/index")
+            .with_env_version(EnvVersion::Trial)
+            .with_line_color(0, 0, 255);
+        assert_eq!(
+            serde_json::to_value(&code).unwrap(),
+            serde_json::json!({
+                "scene": "id=123",
+                "page": "pages/index/index",
+                "env_version": "trial",
+                "line_color": {"r": 0, "g": 0, "b": 255},
+            })
+        );
+
+        let image = parse_image_response("image/jpeg".into(), vec![0xff, 0xd8]).unwrap();
+        assert_eq!(image.data, vec![0xff, 0xd8]);
+        let err = parse_image_response(
+            "application/json; encoding=utf-8".into(),
+            br#"{"errcode":41030,"errmsg":"invalid page"}"#.to_vec(),
+        )
+        .unwrap_err();
+        assert_eq!(err.errcode(), Some(41030));
+        assert!(parse_image_response("text/plain".into(), b"{}".to_vec()).is_err());
+    }
+}
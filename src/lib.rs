@@ -57,6 +57,38 @@
 //! # })
 //! ```
 //! 
+//! ## Configuration
+//! 
+//! Besides environment variables, the config can be built by `wx_login::ConfigBuilder` in code,
+//! or loaded from a TOML/JSON file by `wx_login::Config::from_file("wx_login.toml")`, which validates
+//! the settings and returns a descriptive error if they are unusable:
+//! 
+//! ```toml
+//! login_path = "/login"
+//! sig_valid_secs = 600
+//! 
+//! [[apps]]
+//! appid = "<your_app_id>"
+//! # or use `secret_file = "<path>"` / `secret_env = "<env_var_name>"`
+//! secret = "<your_app_secret>"
+//! ```
+//! 
+//! To change the config without restarting, create the middleware with a `wx_login::ConfigHandle`
+//! (e.g. `WxLoginLayer::with_config_handle(ConfigHandle::from_file("wx_login.toml")?)`), and reload it
+//! by `handle.reload()`, `handle.reload_on_sighup()` or `handle.watch_file(path, interval)`.
+//! Subsequent requests use the new config and the changed settings are logged,
+//! with secrets of apps, push endpoints and components reported only as changed keys per app.
+//! 
+//! ## WeChat Server APIs
+//! 
+//! Most WeChat server APIs require an access_token, `WxLogin::access_token_manager(appid)` returns a shared
+//! `wx_login::AccessTokenManager` of the app, which caches the token, refreshes it ahead of expiry,
+//! deduplicates concurrent refreshes, and retries once with a new token on errcode 40001/42001 by `manager.call(...)`.
+//! 
+//! To share one token among replicas, set a shared `wx_login::AccessTokenCache` by `ConfigBuilder::with_access_token_cache`,
+//! e.g. `wx_login::FileTokenCache` for processes on one host, or a Redis-backed implementation of the trait,
+//! whose refresh lock makes sure only one replica refreshes the token at a time.
+//! 
 //! ## Protocol
 //! 
 //! ### Login
@@ -110,6 +142,10 @@
 //! curl --header "WX-LOGIN-STOKEN=<stoken>" --header "WX-LOGIN-SIG=<sig>" --url "https://<host>/someapi" 
 //! ```
 //! 
+//! By default the stoken is self-contained (the WeChat session_key is encrypted inside it).
+//! Setting a `wx_login::SessionStore` (e.g. `wx_login::MemorySessionStore`) with `ConfigBuilder::with_session_store`
+//! switches to server-side sessions, where the stoken is an opaque session ID and sessions can be evicted or revoked on the server.
+//! 
 //! If the api server requires authentication (usually by using WxLoginInfo extractor) and the authentication failed,
 //! the error response (StatusCode 401|500) will be returned:
 //! 
@@ -122,6 +158,256 @@
 //! }
 //! ```
 //! 
+//! ### Authorization
+//! 
+//! Roles and permissions of a user can be resolved on login by setting a `wx_login::ClaimsResolver`
+//! with `ConfigBuilder::with_claims_resolver`, they are sealed into the session token as claims.
+//! Handlers can then require them by extractors `wx_login::RequireRole<R>` / `wx_login::RequirePermission<P>`,
+//! or routes can be guarded by `wx_login::axum::require_permission("...")` (with `route_layer`)
+//! and `wx_login::actix_web::require_permission("...")` (with `wrap`).
+//! If the requirement is not satisfied, the error response (StatusCode 403) with code
+//! *auth-role-denied* or *auth-permission-denied* will be returned.
+//! 
+//! ### Phone Number
+//! 
+//! Setting `ConfigBuilder::with_phone_number_path("/phone-number")` enables an endpoint exchanging the `code`
+//! from the mini-program `getPhoneNumber` button (by GET query or POST JSON) for the phone number of the logged-in user.
+//! It requires authentication as other apis, calls `wxa/business/getuserphonenumber`, checks the watermark appid
+//! against the session's appid, and returns `wx_login::PhoneInfo` as JSON:
+//! 
+//! ```json
+//! {
+//!   "phoneNumber": "+86 13800138000",
+//!   "purePhoneNumber": "13800138000",
+//!   "countryCode": "86",
+//!   "watermark": {"appid": "<appid>", "timestamp": 1637744274}
+//! }
+//! ```
+//! 
+//! Handlers can also call `WxLogin::get_phone_number(&login_info, code)` directly.
+//! 
+//! ### Open Data
+//! 
+//! Encrypted open data from the mini-program (e.g. `wx.getUserInfo`, `wx.getWeRunData`) can be decrypted into typed values
+//! `wx_login::UserInfo`, `wx_login::PhoneInfo`, `wx_login::ShareInfo` or `wx_login::WeRunData` by
+//! `wx_login::decrypt_open_data(encrypted_data, iv, &login_info.secret.session_key, &login_info.appid, max_age)`,
+//! which rejects data encrypted for another app, earlier than `max_age` or later than now (beyond 60 seconds of clock skew),
+//! and reports bad padding, UTF-8 or JSON
+//! as `wx_login::OpenDataError`.
+//! 
+//! In handlers the extractor `wx_login::WxOpenData<T>` does it for the logged-in user: it reads `encryptedData`, `iv`
+//! and optionally `rawData`/`signature` (verified if present) from the JSON body, decrypts the data with the session's
+//! session_key, and checks the watermark with max age set by `ConfigBuilder::with_open_data_max_age_secs` (default 600 seconds).
+//! 
+//! ### Session Key
+//! 
+//! The session_key of a session becomes invalid once the client calls `wx.login` again.
+//! Setting `ConfigBuilder::with_check_session_path("/check-session")` enables an endpoint returning `{"valid": <bool>}`
+//! for the session_key of the logged-in user (by `wxa/checksession`), so the client can login again before decrypting fails.
+//! Handlers can also call `WxLogin::check_session_key(appid, openid, &session_key)`, or
+//! `WxLogin::reset_session_key(...)` (by `wxa/resetusersessionkey`) to rotate it.
+//! 
+//! ### Message Push
+//! 
+//! Messages pushed by WeChat server (subscribe message results, media check results, authorization revocations etc.)
+//! can be received by adding `wx_login::PushEndpoint::new("/wx-push", appid, token)` with `ConfigBuilder::with_push_endpoint`,
+//! which should be the same as the message push settings (JSON format) of the app.
+//! The endpoint answers the GET verification with `echostr`, verifies the signature of every push, decrypts messages in safe mode
+//! if `PushEndpoint::with_encoding_aes_key` is set, and dispatches `wx_login::PushMessage` with typed `wx_login::PushEvent`
+//! to handlers (implementing `wx_login::PushHandler`) added by `PushEndpoint::with_handler`.
+//! Pushes with a timestamp more than 300 seconds away from now are rejected as replays.
+//! If any handler fails, the error response (StatusCode 500) is returned so that WeChat retries the push later.
+//! 
+//! On `user_authorization_revoke` and `user_authorization_cancellation` events, all sessions of the user are revoked
+//! by `WxLogin::revoke_user_sessions` before handlers are called: sessions kept by the session store are removed, and
+//! if a `wx_login::RevocationStore` (e.g. `wx_login::MemoryRevocationStore`) is set by `ConfigBuilder::with_revocation_store`,
+//! all sessions (including self-contained stokens) created before the revocation, or in the same second, fail authentication.
+//! `MemoryRevocationStore` keeps revocations for 7 days by default (`with_max_ttl`), which should cover the lifetime of sessions.
+//! Revocation fails if there is no revocation store and the session store can not remove sessions by user.
+//! An audit event is logged with tracing target `wx_login::audit`.
+//! 
+//! ### Subscribe Message
+//! 
+//! Subscribe messages can be sent to the logged-in user by `WxLogin::subscribe_message_client(&login_info)`,
+//! which supplies the appid and openid of the user. The `WxLogin` is available in request extensions
+//! (`Extension<WxLogin>` with Axum, `web::ReqData<WxLogin>` with Actix-Web).
+//! Build a `wx_login::SubscribeMessage` of a template with `with_data("thing1", "...")`, `with_page`,
+//! `with_miniprogram_state` and `with_lang`, and send it by `SubscribeMessageClient::send`.
+//! A `wx_login::SubscribeMessageError` is returned on failure, e.g. `UserRefused` (errcode 43101) if the user did not
+//! accept the subscription, or `BadTemplateData` (errcode 47003) if the data does not match the template.
+//! 
+//! ### Content Security Check
+//! 
+//! Text posted by the logged-in user can be checked by `wxa/msg_sec_check` (v2) with `WxLogin::check_text(&login_info, &check)`,
+//! where `wx_login::TextSecCheck::new(content, SecCheckScene::Comment)` is the text to check (optionally with title,
+//! nickname and signature). The openid of the session is used, and a `wx_login::SecCheckResult` is returned with the
+//! typed verdict (`SecCheckVerdict::Pass`, `Review` or `Risky`) and labels.
+//! 
+//! For a JSON request body implementing `wx_login::SecCheckContent` (returning the text to check), use the extractor
+//! `wx_login::WxSecChecked<T>` instead, which rejects risky content with StatusCode 400,
+//! and extracts the body and the result of the check otherwise.
+//! 
+//! ### Mini-Program Code
+//! 
+//! Unlimited mini-program codes can be generated by `WxLogin::get_wxa_code_unlimited(appid, &code)` with
+//! the access token of the app, where `wx_login::WxaCode::new(scene)` is the request (with page, env version, width,
+//! colors etc.). A `wx_login::WxaCodeImage` (content type and image bytes) is returned on success,
+//! or `wx_login::ApiError::WeChat` with the errcode returned by WeChat server on failure.
+//! 
+//! The scene is at most 32 chars, which can be encoded from params by `wx_login::encode_scene(&[("id", "123")])`
+//! into `id=123`, and decoded by `wx_login::decode_scene` (accepting the URL-encoded scene received by the mini-program).
+//! 
+//! ### Scan-to-Login for Web
+//! 
+//! Web pages can login by scanning a mini-program code with WeChat, by enabling `wx_login::ScanLogin::new("/scan-login", appid)`
+//! with `ConfigBuilder::with_scan_login`, which provides endpoints under the path:
+//! 
+//! 1. The web side requests `/scan-login/ticket` to get a `ticket` and a `secret` (only known by the web side).
+//! 2. The web side shows the image of `/scan-login/qrcode?ticket=...`, an unlimited mini-program code with the scene `t=<ticket>`
+//!    opening the page set by `ScanLogin::with_page`.
+//! 3. The mini-program decodes the ticket from the scene, and requests `/scan-login/confirm` with `{"ticket": "...", "action": "scan"}`
+//!    when scanned, and `"action": "confirm"` (or `"cancel"`) after the user confirms, with its login session.
+//! 4. The web side polls `/scan-login/poll?ticket=...&secret=...&wait=20`, which waits at most `wait` seconds for status changes,
+//!    and returns `{"status": "confirmed", "login": {"openid": "...", "stoken": "...", "skey": "..."}}` with a new session of the same user.
+//! 
+//! Tickets expire after `ScanLogin::with_ticket_ttl_secs` (300 seconds by default), and can be confirmed once by one user
+//! and polled once after confirmed, when the session of the web side is issued. Tickets are kept by an in-memory store by default,
+//! set a shared `wx_login::TicketStore` (whose `update_if` must be atomic) by `ConfigBuilder::with_ticket_store` for replicas.
+//! 
+//! ### Web-View Handoff
+//! 
+//! H5 pages opened in `<web-view>` of the mini-program can not access the stoken of the mini-program.
+//! Enable `wx_login::Handoff::new("/handoff/mint", "/handoff/exchange")` with `ConfigBuilder::with_handoff`, then:
+//! 
+//! 1. The mini-program requests `/handoff/mint` with its login session, and gets `{"ticket": "...", "expires_in": 60}`.
+//! 2. The mini-program opens the web-view at `https://<host>/handoff/exchange?ticket=...&redirect=/h5/index.html`.
+//! 3. The exchange endpoint issues a new session of the same openid and claims, sets it in the HttpOnly, Secure and
+//!    SameSite=Strict cookie `wx_login_stoken`, and redirects to `redirect` (a path of the same site),
+//!    or returns `{"openid": "..."}` if `redirect` is absent.
+//! 
+//! Tickets are single-use and expire after `Handoff::with_ticket_ttl_secs` (60 seconds by default).
+//! With handoff enabled, the middleware authenticates requests without the `WX-LOGIN-STOKEN` header by the cookie,
+//! as in the cookie session mode below.
+//! 
+//! ### Cookie Session
+//! 
+//! For browser clients (H5 pages, server-rendered admin tools), enable the cookie-based session mode by
+//! `ConfigBuilder::with_cookie_session(wx_login::CookieSession::new())`. The login response then sets the stoken in an
+//! HttpOnly cookie (`wx_login_stoken` by default, with name, domain, path, max-age, SameSite and Secure configurable
+//! by `CookieSession::with_*`), and the middleware authenticates requests without the `WX-LOGIN-STOKEN` header by the cookie.
+//! 
+//! The signature is not available in this mode, so CSRF is prevented by the double-submit token: login also sets the cookie
+//! `wx_login_csrf` readable by scripts, and requests other than GET, HEAD and OPTIONS must echo its value in the
+//! `X-CSRF-Token` header, otherwise they are not authenticated.
+//! 
+//! ### Credential Transport
+//! 
+//! Where the credentials come from can be set by `ConfigBuilder::with_credential_sources`, as a list of
+//! `wx_login::CredentialSource` tried in order:
+//! 
+//! - `CredentialSource::headers("X-STOKEN", "X-SIG")`: custom header names, the default is `WX-LOGIN-STOKEN` and `WX-LOGIN-SIG`.
+//! - `CredentialSource::Authorization`: the header `Authorization: WxLogin stoken="<stoken>", sig="<sig>"`,
+//!   for gateways only forwarding `Authorization`.
+//! - `CredentialSource::query("stoken", "sig")`: query params (URL-encoded), for clients which can not set headers,
+//!   e.g. WebSocket, EventSource and image URLs. The signature is calculated over the URI without the signature param,
+//!   e.g. `/ws?stoken=...&room=1` for `/ws?stoken=...&room=1&sig=...`.
+//! 
+//! ### WebSocket and SSE
+//! 
+//! Browsers and `wx.connectSocket` can not set headers of the handshake, so long-lived connections are
+//! authenticated once at the handshake by query credentials (see the above `CredentialSource::query`):
+//! 
+//! 1. The client builds the URI with the stoken, e.g. `/ws?room=1&stoken=<stoken>`, signs it with `skey` as usual,
+//!    and connects to `/ws?room=1&stoken=<stoken>&sig=<sig>`.
+//! 2. The middleware verifies the signature over the URI without `sig`, so the handshake must be made
+//!    within the valid period of signatures.
+//! 3. The handler extracts `wx_login::WxConnection` (the login info plus the session), which can end the connection
+//!    when the session expires (revoked or removed by the session store), by `run_until_expired` for futures
+//!    and `take_until_expired` for streams such as SSE events.
+//! 
+//! With the `axum-ws` and `actix-ws` features (on by default), the adapters have a helper of WebSocket:
+//! 
+//! - axum: extract `wx_login::axum::WxWebSocketUpgrade` instead of `WebSocketUpgrade`, and call
+//!   `ws.close_on_expiry(Duration::from_secs(60)).on_upgrade(|socket, login_info| async move { ... })`,
+//!   where `socket` is a `wx_login::axum::WxWebSocket` to `recv` and `send` messages.
+//! - actix-web: call `wx_login::actix_web::ws_upgrade(&req, body, &connection, Some(Duration::from_secs(60)))`
+//!   to get the response, `actix_ws::Session` and `actix_ws::MessageStream`.
+//! 
+//! Both close the socket with code 1008 (policy violation) and the reason `login session expired` when
+//! the session expires.
+//! 
+//! ### Official Account OAuth
+//! 
+//! H5 pages opened from official accounts can log in by the web authorization (OAuth2) of the official account,
+//! whose appid and secret are added like mini-programs, with `ConfigBuilder::with_oauth_login`:
+//! 
+//! ```text
+//! OAuthLogin::new("<official account appid>", "/wx/oauth", "https://example.com/wx/oauth/callback")
+//!     .with_scope(OAuthScope::UserInfo)
+//!     .with_user_handler(Arc::new(MyUserHandler))
+//! ```
+//! 
+//! 1. The H5 page is opened at `/wx/oauth?redirect=/h5/home`, which redirects to `connect/oauth2/authorize`
+//!    with a new single-use `state`, also kept in the cookie `wx_login_oauth_state` against CSRF.
+//! 2. WeChat redirects back to the callback URL with `code` and `state`, and the state is checked against the cookie.
+//! 3. The code is exchanged by `sns/oauth2/access_token`, and for `snsapi_userinfo` the profile is fetched by `sns/userinfo`.
+//!    Both are passed to the `OAuthUserHandler` if set.
+//! 4. The session of the openid is issued the same way as mini-program login (claims resolved by the `ClaimsResolver`),
+//!    and kept in cookies of the cookie session mode, then redirected to `redirect` if present, otherwise the JSON of
+//!    the login result (`openid`, `stoken` and `skey`) is returned.
+//! 
+//! Handlers use `WxLoginInfo` as usual, whose `appid` is the official account. Note that browsers do not send
+//! SameSite=Strict cookies on the redirect right after the callback, so the landing page should request APIs by itself,
+//! or use `SameSite::Lax`.
+//! 
+//! ### Website QR Login
+//! 
+//! Desktop websites can "Log in with WeChat" by the QR login (`connect/qrconnect`, scope `snsapi_login`) of a website app
+//! of the Open Platform, whose appid and secret are added like mini-programs:
+//! 
+//! ```text
+//! OAuthLogin::website("<website appid>", "/wx/qrlogin", "https://example.com/wx/qrlogin/callback")
+//! ```
+//! 
+//! The flow is the same as the above official account OAuth, and the profile is always fetched.
+//! The unionid is kept in sessions as `WxLoginInfo::unionid`, so that users of the website and mini-programs
+//! bound to the same Open Platform account can be linked by it. Mini-program login keeps the unionid returned by
+//! `jscode2session` as well.
+//! 
+//! ### WeCom Login
+//! 
+//! Mini-programs running in WeCom (Enterprise WeChat) are added by `AppInfo::wecom(appid, corpid, corpsecret)`,
+//! or `corpid = "<corpid>"` in the `[[apps]]` entry of the config file, where the secret is the corpsecret of the
+//! application bound to the mini-program.
+//! 
+//! The login protocol is the same, with the code of `wx.qy.login`, which is exchanged by
+//! `cgi-bin/miniprogram/jscode2session` of WeCom with the corp access_token (managed like other access_tokens).
+//! The session is of the member of the corp: `WxLoginInfo::corpid` and `WxLoginInfo::userid` are set, and `openid`
+//! is the userid as well, so handlers and extractors work as usual. The claims resolver is called with
+//! `wecom:<corpid>:<userid>` in place of the openid, so that members are not taken as users of WeChat.
+//! 
+//! ### Third-Party Platform Login
+//! 
+//! A third-party platform (component) logs in the mini-programs authorized to it without their secrets.
+//! The platform is added by `ConfigBuilder::with_component(Component::new(component_appid, component_secret))`,
+//! and its mini-programs by `AppInfo::component(appid, component_appid)`, or `component_appid = "<component_appid>"`
+//! (without secret) in the `[[apps]]` entry of the config file.
+//! 
+//! The login protocol is the same, while the code is exchanged by `sns/component/jscode2session` with the
+//! component_access_token, which is managed like other access_tokens (see `WxLogin::component_access_token_manager`).
+//! Fetching it requires the latest component_verify_ticket pushed by WeChat server every 10 minutes, which is kept
+//! by `AccessTokenCache::put_ticket` of the access_token cache (shared by replicas like the tokens):
+//! 
+//! ```text
+//! Component::new("<component_appid>", "<component_secret>")
+//!     // serve the authorization event URL, decrypting and saving the ticket
+//!     .with_ticket_endpoint("/wx/component/event", "<token>", "<EncodingAESKey>")
+//! ```
+//! 
+//! Or receive it elsewhere and save it by `WxLogin::set_component_verify_ticket`. Stokens of these apps are
+//! derived from the component secret, and the unionid is kept in sessions as usual. The authorizer_access_token
+//! of these apps is not managed, so `WxLogin::access_token_manager` returns an error for them.
+//! 
 //! ### Frontend
 //! 
 //! One can find frontend javascript sample code in repo *frontend* directory for reference.
@@ -2,16 +2,74 @@ pub mod wx_login {
     #[cfg(feature = "axum")]
     pub mod axum {
         pub use crate::axum::{
-            layer_with_env_var, WxLoginInfoRejection, WxLoginLayer, WxLoginService,
+            layer_with_env_var, require_permission, require_role, RequireLayer, RequireService,
+            WxLoginInfoRejection, WxLoginLayer, WxLoginService,
         };
+        #[cfg(feature = "axum-ws")]
+        pub use crate::axum::{WxWebSocket, WxWebSocketUpgrade};
     }
-    #[cfg(feature = "axum")]
+    #[cfg(feature = "actix-web")]
     pub mod actix_web {
+        #[cfg(feature = "actix-ws")]
+        pub use crate::actix_web::ws_upgrade;
         pub use crate::actix_web::{
-            middleware_with_env_var, WxLoginMiddleware, WxLoginMiddlewareService,
+            middleware_with_env_var, require_permission, require_role, RequireMiddleware,
+            RequireMiddlewareService, WxLoginMiddleware, WxLoginMiddlewareService,
         };
     }
+    pub use crate::core::access_token::{AccessToken, AccessTokenApi, AccessTokenManager};
+    pub use crate::core::api::{
+        ApiError, ERRCODE_ACCESS_TOKEN_EXPIRED, ERRCODE_INVALID_ACCESS_TOKEN,
+        ERRCODE_WECOM_INVALID_ACCESS_TOKEN,
+    };
+    pub use crate::core::authz::{
+        Claims, ClaimsResolver, Permission, RequirePermission, RequireRole, Requirement, Role,
+    };
+    pub use crate::core::component::Component;
     pub use crate::core::config::{AppInfo, Config, ConfigBuilder};
+    pub use crate::core::connection::WxConnection;
+    pub use crate::core::cookie::{
+        CookieSession, SameSite, CSRF_COOKIE_NAME, CSRF_HEADER_NAME, SESSION_COOKIE_NAME,
+    };
+    pub use crate::core::credential::{
+        CredentialSource, AUTHORIZATION_SCHEME, SIG_HEADER_NAME, STOKEN_HEADER_NAME,
+    };
+    pub use crate::core::handoff::{Handoff, HandoffTicket};
     pub use crate::core::login::{Error, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk};
-    pub use crate::core::security::{check_signature, decrpyt_data};
+    pub use crate::core::oauth::{
+        OAuthLogin, OAuthScope, OAuthUser, OAuthUserHandler, OAuthUserInfo, OAUTH_STATE_COOKIE_NAME,
+    };
+    pub use crate::core::open_data::{
+        decrpyt_data, decrypt_open_data, OpenData, OpenDataError, ShareInfo, StepInfo, UserInfo,
+        Watermark, WeRunData, WxOpenData,
+    };
+    pub use crate::core::phone::PhoneInfo;
+    pub use crate::core::push::{
+        MediaCheckResult, PushEndpoint, PushEvent, PushHandler, PushMessage,
+        SubscribeMsgSentResult, SubscribeMsgStatus,
+    };
+    pub use crate::core::registry::{AppRegistry, CachedAppRegistry, StaticAppRegistry};
+    pub use crate::core::reload::ConfigHandle;
+    pub use crate::core::revocation::{MemoryRevocationStore, RevocationStore, AUDIT_TARGET};
+    pub use crate::core::scan_login::{
+        ScanLogin, ScanLoginState, ScanLoginStatus, ScanLoginTicket,
+    };
+    pub use crate::core::sec_check::{
+        SecCheckContent, SecCheckDetail, SecCheckResult, SecCheckScene, SecCheckSuggest,
+        SecCheckVerdict, TextSecCheck, WxSecChecked, SEC_CHECK_LABEL_NORMAL,
+    };
+    pub use crate::core::security::check_signature;
+    pub use crate::core::session::{MemorySessionStore, Session, SessionStore};
+    pub use crate::core::session_key::SessionKeyStatus;
+    pub use crate::core::subscribe::{
+        Lang, MiniProgramState, SubscribeMessage, SubscribeMessageClient, SubscribeMessageError,
+        ERRCODE_BAD_TEMPLATE_DATA, ERRCODE_USER_REFUSED,
+    };
+    pub use crate::core::ticket::{MemoryTicketStore, TicketStore};
+    pub use crate::core::token_cache::{AccessTokenCache, FileTokenCache, MemoryTokenCache};
+    pub use crate::core::wxacode::{
+        check_scene, decode_scene, encode_scene, EnvVersion, LineColor, WxaCode, WxaCodeImage,
+        SCENE_MAX_LEN,
+    };
+    pub use async_trait::async_trait;
 }